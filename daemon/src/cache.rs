@@ -0,0 +1,998 @@
+use crate::config::CacheConfig;
+
+use log::debug;
+use rdns_proto::*;
+use std::collections::{BTreeMap, HashMap};
+use std::mem;
+use std::time::{Duration, SystemTime};
+
+/// Maximum number of CNAMEs followed for one lookup
+pub const MAX_CNAME_CHAIN: usize = 8;
+/// TTL of records that are served after they expired (RFC 8767 5)
+pub const STALE_TTL: u32 = 30;
+/// Approximate bytes of an entry without its names and records, the key
+/// is held by the map and by the recency order
+const ENTRY_OVERHEAD: usize = 2 * mem::size_of::<CacheKey>() + mem::size_of::<CacheEntry>();
+
+/// Identifies an RRset, the name is lowercase and without trailing dot
+///
+/// NXDOMAIN holds for every type of the name, so it is stored with the
+/// type ANY, which is never cached otherwise.
+#[derive(Clone, Debug, Hash, Eq, PartialEq)]
+pub struct CacheKey {
+    pub name: String,
+    pub rtype: QType,
+    pub rclass: QClass,
+}
+
+impl CacheKey {
+    pub fn new(name: &str, rtype: QType, rclass: QClass) -> Self {
+        Self {
+            name: normalize(name),
+            rtype,
+            rclass,
+        }
+    }
+
+    /// Key of the NXDOMAIN entry of the name
+    pub fn name_error(name: &str, rclass: QClass) -> Self {
+        Self::new(name, QType::ANY, rclass)
+    }
+}
+
+/// Lowercase name without trailing dot, as used in keys
+pub(crate) fn normalize(name: &str) -> String {
+    name.trim_end_matches('.').to_ascii_lowercase()
+}
+
+/// Records of one RRset that expire together
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CacheEntry {
+    /// The RRset, or the SOA record of a negative answer
+    pub records: Vec<ResourceRecord>,
+    /// NXDOMAIN or NODATA, depending on the key
+    pub negative: bool,
+    pub expires: SystemTime,
+    /// Position in the recency order, higher is more recently used
+    used: u64,
+    /// Approximate bytes of memory the entry takes
+    size: usize,
+    /// TTL the entry was cached with
+    ttl: u32,
+    /// Number of lookups that used the entry
+    hits: u64,
+    /// A refresh was requested already
+    prefetching: bool,
+}
+
+/// Answer to a question that is synthesized from the cache
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CachedAnswer {
+    pub rcode: Rcode,
+    pub answers: Vec<ResourceRecord>,
+    /// SOA record of a negative answer (RFC 2308 3)
+    pub authorities: Vec<ResourceRecord>,
+    /// At least one of the records expired
+    pub stale: bool,
+    /// One of the RRsets is popular and about to expire, the question should
+    /// be resolved again to refresh it
+    pub prefetch: bool,
+}
+
+/// Entry used by a lookup
+struct Hit {
+    records: Vec<ResourceRecord>,
+    negative: bool,
+    stale: bool,
+    prefetch: bool,
+}
+
+impl CacheEntry {
+    /// Records with the TTL that is left at `now`, `None` once expired
+    fn records_at(&self, now: SystemTime) -> Option<Vec<ResourceRecord>> {
+        let ttl = self.expires.duration_since(now).ok()?.as_secs() as u32;
+        if ttl == 0 {
+            return None;
+        }
+        Some(self.records_with_ttl(ttl))
+    }
+
+    fn records_with_ttl(&self, ttl: u32) -> Vec<ResourceRecord> {
+        self.records
+            .iter()
+            .map(|x| ResourceRecord { ttl, ..x.clone() })
+            .collect()
+    }
+}
+
+/// Counters of the cache since it was created
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct CacheStats {
+    /// Lookups answered from the cache
+    pub hits: u64,
+    pub misses: u64,
+    /// RRsets removed to make room for new ones
+    pub evictions: u64,
+    /// RRsets removed because their TTL and the stale window ran out
+    pub expirations: u64,
+    /// Lookups answered with expired records
+    pub stale_hits: u64,
+    /// Refreshes requested for popular RRsets
+    pub prefetches: u64,
+}
+
+/// RRsets of upstream answers by name, type and class
+///
+/// Once the number of RRsets or their approximate size reaches its limit,
+/// the least recently used RRsets are evicted. Expired RRsets are kept for
+/// the stale window, in which they are only used if no upstream server
+/// answers.
+pub struct Cache {
+    entries: HashMap<CacheKey, CacheEntry>,
+    /// Keys by the position of their last use, the first one is evicted first
+    recency: BTreeMap<u64, CacheKey>,
+    /// Position of the latest use
+    clock: u64,
+    bytes: usize,
+    max_entries: usize,
+    max_bytes: usize,
+    stale_window: Duration,
+    min_ttl: u32,
+    max_ttl: u32,
+    max_negative_ttl: u32,
+    /// TTLs by domain, including all subdomains
+    ttl_overrides: HashMap<String, u32>,
+    prefetch_hits: u64,
+    prefetch_percent: u32,
+    pub stats: CacheStats,
+}
+
+impl Cache {
+    pub fn new(config: &CacheConfig) -> Self {
+        Self {
+            entries: HashMap::with_capacity(config.max_entries.min(128)),
+            recency: BTreeMap::new(),
+            clock: 0,
+            bytes: 0,
+            max_entries: config.max_entries,
+            max_bytes: config.max_bytes,
+            stale_window: config.stale_window,
+            min_ttl: config.min_ttl,
+            max_ttl: config.max_ttl,
+            max_negative_ttl: config.max_negative_ttl,
+            ttl_overrides: config.ttl_overrides.clone(),
+            prefetch_hits: config.prefetch_hits,
+            prefetch_percent: config.prefetch_percent,
+            stats: CacheStats::default(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Approximate bytes of memory the cached RRsets take
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    #[cfg(test)]
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    #[cfg(test)]
+    pub fn get(&self, key: &CacheKey) -> Option<&CacheEntry> {
+        self.entries.get(key)
+    }
+
+    /// Records of the RRset at `now`, `None` if it is not cached, expired
+    /// or negative
+    ///
+    /// Unlike `lookup`, neither the statistics nor the recency change.
+    pub fn records(&self, key: &CacheKey, now: SystemTime) -> Option<Vec<ResourceRecord>> {
+        self.entries
+            .get(key)
+            .filter(|x| !x.negative)?
+            .records_at(now)
+    }
+
+    /// Answer to the question at `now`, CNAMEs are followed until the
+    /// records of the requested type or a negative answer are found
+    ///
+    /// `None` if any part of the chain is missing or expired, ANY is never
+    /// answered from the cache. Expired RRsets on the way are removed.
+    pub fn lookup(&mut self, question: &Question, now: SystemTime) -> Option<CachedAnswer> {
+        let answer = self.find(question, now, false);
+        match answer {
+            Some(_) => self.stats.hits += 1,
+            None => self.stats.misses += 1,
+        }
+        answer
+    }
+
+    /// Like `lookup`, but records that expired within the stale window are
+    /// used as well, with a TTL of `STALE_TTL` (RFC 8767 4)
+    pub fn lookup_stale(&mut self, question: &Question, now: SystemTime) -> Option<CachedAnswer> {
+        let answer = self.find(question, now, true);
+        if answer.as_ref().is_some_and(|x| x.stale) {
+            self.stats.stale_hits += 1;
+        }
+        answer
+    }
+
+    fn find(&mut self, question: &Question, now: SystemTime, stale: bool) -> Option<CachedAnswer> {
+        if question.qtype == QType::ANY {
+            return None;
+        }
+
+        let mut answer = CachedAnswer {
+            rcode: Rcode::NoError,
+            answers: Vec::new(),
+            authorities: Vec::new(),
+            stale: false,
+            prefetch: false,
+        };
+        let mut name = question.qname.clone();
+        for _ in 0..=MAX_CNAME_CHAIN {
+            let key = CacheKey::name_error(&name, question.qclass);
+            if let Some(hit) = self.touch(&key, now, stale) {
+                answer.rcode = Rcode::NameError;
+                answer.authorities = hit.records;
+                answer.stale |= hit.stale;
+                answer.prefetch |= hit.prefetch;
+                return Some(answer);
+            }
+
+            let key = CacheKey::new(&name, question.qtype, question.qclass);
+            if let Some(hit) = self.touch(&key, now, stale) {
+                match hit.negative {
+                    true => answer.authorities = hit.records,
+                    false => answer.answers.extend(hit.records),
+                }
+                answer.stale |= hit.stale;
+                answer.prefetch |= hit.prefetch;
+                return Some(answer);
+            }
+            if question.qtype == QType::CNAME {
+                return None;
+            }
+
+            let key = CacheKey::new(&name, QType::CNAME, question.qclass);
+            let hit = self.touch(&key, now, stale).filter(|x| !x.negative)?;
+            answer.stale |= hit.stale;
+            answer.prefetch |= hit.prefetch;
+            name = hit.records[0].target()?;
+            answer.answers.extend(hit.records);
+        }
+
+        debug!("CNAME chain of {} is too long", question.qname);
+        None
+    }
+
+    /// Records of the entry at `now`, the entry becomes the most recently
+    /// used one
+    ///
+    /// Expired entries are only returned if `stale` is set, they are removed
+    /// once the stale window passed. Once an entry with enough hits is in
+    /// the last percentage of its TTL, one refresh is requested for it.
+    fn touch(&mut self, key: &CacheKey, now: SystemTime, stale: bool) -> Option<Hit> {
+        let entry = self.entries.get_mut(key)?;
+        let (records, is_stale) = match entry.records_at(now) {
+            Some(x) => (x, false),
+            None if now >= entry.expires + self.stale_window => {
+                self.remove(key);
+                self.stats.expirations += 1;
+                return None;
+            }
+            None if stale => (entry.records_with_ttl(STALE_TTL), true),
+            None => return None,
+        };
+
+        self.clock += 1;
+        self.recency.remove(&entry.used);
+        self.recency.insert(self.clock, key.clone());
+        entry.used = self.clock;
+        entry.hits += 1;
+
+        let left = records.first().map_or(0, |x| x.ttl);
+        let prefetch = !is_stale
+            && !entry.prefetching
+            && entry.hits >= self.prefetch_hits
+            && u64::from(left) * 100 <= u64::from(entry.ttl) * u64::from(self.prefetch_percent);
+        if prefetch {
+            debug!("Prefetching {:?} with {}s left", key, left);
+            entry.prefetching = true;
+            self.stats.prefetches += 1;
+        }
+
+        Some(Hit {
+            records,
+            negative: entry.negative,
+            stale: is_stale,
+            prefetch,
+        })
+    }
+
+    /// Caches the records of the response that belong to its question, as
+    /// well as NXDOMAIN or NODATA of the last name of its CNAME chain
+    ///
+    /// Records of other names than the question and its CNAME targets are
+    /// ignored. Negative answers are only cached with a SOA record of a
+    /// zone the name belongs to (RFC 2308 5).
+    pub fn insert_response(&mut self, response: &DNS, now: SystemTime) {
+        let question = match response.questions.first() {
+            Some(x) => x,
+            None => return,
+        };
+
+        let mut names = vec![normalize(&question.qname)];
+        for _ in 0..MAX_CNAME_CHAIN {
+            let last = &names[names.len() - 1];
+            let target = response
+                .resource_records
+                .iter()
+                .filter(|x| x.rtype == QType::CNAME && x.rclass == question.qclass)
+                .find(|x| normalize(&x.name) == *last)
+                .and_then(|x| x.target())
+                .map(|x| normalize(&x));
+            match target {
+                Some(x) if !names.contains(&x) => names.push(x),
+                _ => break,
+            }
+        }
+
+        let records = response
+            .resource_records
+            .iter()
+            .filter(|x| x.rclass == question.qclass && names.contains(&normalize(&x.name)))
+            .cloned()
+            .collect::<Vec<ResourceRecord>>();
+        self.insert(&records, now);
+
+        let last = &names[names.len() - 1];
+        let key = match response.rcode {
+            Rcode::NameError => CacheKey::name_error(last, question.qclass),
+            Rcode::NoError if question.qtype == QType::ANY => return,
+            Rcode::NoError => {
+                let key = CacheKey::new(last, question.qtype, question.qclass);
+                let answered = records.iter().any(|x| {
+                    CacheKey::new(&x.name, x.rtype, x.rclass) == key
+                        || (x.rtype == QType::CNAME && normalize(&x.name) == key.name)
+                });
+                if answered {
+                    return;
+                }
+                key
+            }
+            _ => return,
+        };
+
+        let soa = response.authorities.iter().find(|x| {
+            let zone = normalize(&x.name);
+            x.rtype == QType::SOA
+                && x.rclass == question.qclass
+                && (zone.is_empty() || *last == zone || last.ends_with(&format!(".{}", zone)))
+        });
+        match soa.and_then(|x| x.negative_ttl().map(|ttl| (x, ttl))) {
+            Some((soa, ttl)) => {
+                let ttl = self.policy_ttl(&key.name, ttl, true);
+                self.insert_entry(key, vec![soa.clone()], true, ttl, now);
+            }
+            None => debug!("Not caching negative answer for {:?} without SOA", key),
+        }
+    }
+
+    /// Caches the records as RRsets, every RRset expires with its lowest
+    /// TTL (RFC 2181 5.2)
+    pub fn insert(&mut self, records: &[ResourceRecord], now: SystemTime) {
+        let mut rrsets: HashMap<CacheKey, Vec<ResourceRecord>> = HashMap::new();
+        for record in records {
+            let key = CacheKey::new(&record.name, record.rtype, record.rclass);
+            rrsets.entry(key).or_default().push(record.clone());
+        }
+
+        for (key, records) in rrsets {
+            let ttl = records.iter().map(|x| x.ttl).min().unwrap_or_default();
+            let ttl = self.policy_ttl(&key.name, ttl, false);
+            self.insert_entry(key, records, false, ttl, now);
+        }
+    }
+
+    /// TTL the RRset of the name is cached with
+    ///
+    /// The override of the longest matching domain wins, otherwise the TTL
+    /// is clamped to the configured range. Records with a TTL of zero are
+    /// never cached, unless an override applies.
+    fn policy_ttl(&self, name: &str, ttl: u32, negative: bool) -> u32 {
+        let mut suffix = name;
+        loop {
+            if let Some(x) = self.ttl_overrides.get(suffix) {
+                return *x;
+            }
+
+            match suffix.find('.') {
+                Some(index) => suffix = &suffix[index + 1..],
+                None => break,
+            }
+        }
+
+        if ttl == 0 {
+            return 0;
+        }
+        let ttl = ttl.max(self.min_ttl).min(self.max_ttl);
+        match negative {
+            true => ttl.min(self.max_negative_ttl),
+            false => ttl,
+        }
+    }
+
+    fn insert_entry(
+        &mut self,
+        key: CacheKey,
+        records: Vec<ResourceRecord>,
+        negative: bool,
+        ttl: u32,
+        now: SystemTime,
+    ) {
+        if ttl == 0 {
+            return;
+        }
+        let size = ENTRY_OVERHEAD
+            + 2 * key.name.len()
+            + records
+                .iter()
+                .map(|x| mem::size_of::<ResourceRecord>() + x.name.len() + x.rdata.len())
+                .sum::<usize>();
+        if size > self.max_bytes {
+            debug!("{:?} is larger than the cache, not caching it", key);
+            return;
+        }
+
+        self.remove(&key);
+        while self.entries.len() >= self.max_entries || self.bytes + size > self.max_bytes {
+            let oldest = match self.recency.values().next() {
+                Some(x) => x.clone(),
+                None => break,
+            };
+            self.remove(&oldest);
+            self.stats.evictions += 1;
+        }
+
+        self.clock += 1;
+        self.recency.insert(self.clock, key.clone());
+        self.bytes += size;
+        self.entries.insert(
+            key,
+            CacheEntry {
+                records,
+                negative,
+                expires: now + Duration::from_secs(u64::from(ttl)),
+                used: self.clock,
+                size,
+                ttl,
+                hits: 0,
+                prefetching: false,
+            },
+        );
+    }
+
+    /// Caches the RRset until `expires`, nothing is cached if it expired at
+    /// `now`, the TTL policy was applied already
+    pub fn restore(
+        &mut self,
+        key: CacheKey,
+        records: Vec<ResourceRecord>,
+        negative: bool,
+        expires: SystemTime,
+        now: SystemTime,
+    ) {
+        let ttl = match expires.duration_since(now) {
+            Ok(x) => x.as_secs().min(u64::from(u32::MAX)) as u32,
+            Err(_) => return,
+        };
+        self.insert_entry(key, records, negative, ttl, now);
+    }
+
+    /// Every RRset, the least recently used one first
+    pub fn iter(&self) -> impl Iterator<Item = (&CacheKey, &CacheEntry)> {
+        self.recency
+            .values()
+            .filter_map(move |x| self.entries.get_key_value(x))
+    }
+
+    /// Removes the RRset, returns it if it was cached
+    pub fn remove(&mut self, key: &CacheKey) -> Option<CacheEntry> {
+        let entry = self.entries.remove(key)?;
+        self.recency.remove(&entry.used);
+        self.bytes -= entry.size;
+        Some(entry)
+    }
+
+    /// Removes every RRset whose stale window passed at `now`, returns their
+    /// number
+    pub fn evict_expired(&mut self, now: SystemTime) -> usize {
+        let window = self.stale_window;
+        let expired = self
+            .entries
+            .iter()
+            .filter(|(_, x)| x.expires + window <= now)
+            .map(|(x, _)| x.clone())
+            .collect::<Vec<CacheKey>>();
+        for key in expired.iter() {
+            self.remove(key);
+        }
+        self.stats.expirations += expired.len() as u64;
+        expired.len()
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    fn cache(max_entries: usize) -> Cache {
+        Cache::new(&CacheConfig {
+            max_entries,
+            max_bytes: usize::MAX,
+            stale_window: Duration::ZERO,
+            ..CacheConfig::default()
+        })
+    }
+
+    pub fn record(name: &str, rtype: QType, ttl: u32, rdata: Vec<u8>) -> ResourceRecord {
+        ResourceRecord {
+            name: name.into(),
+            rtype,
+            rclass: QClass::IN,
+            cache_flush: false,
+            ttl,
+            rdlength: rdata.len() as u16,
+            rdata,
+        }
+    }
+
+    fn cname(name: &str, target: &str, ttl: u32) -> ResourceRecord {
+        ResourceRecord::cname(name.into(), target, ttl)
+    }
+
+    pub fn question(qname: &str, qtype: QType) -> Question {
+        Question {
+            qname: qname.into(),
+            qtype,
+            qclass: QClass::IN,
+            unicast_response: false,
+        }
+    }
+
+    #[test]
+    pub fn test_lookup() {
+        let now = SystemTime::now();
+        let mut cache = cache(16);
+        cache.insert(
+            &[
+                record("www.google.de", QType::A, 300, vec![172, 217, 168, 195]),
+                record("www.google.de", QType::A, 60, vec![172, 217, 168, 196]),
+            ],
+            now,
+        );
+
+        let records = cache
+            .lookup(&question("WWW.Google.DE.", QType::A), now)
+            .unwrap()
+            .answers;
+        assert_eq!(records.len(), 2);
+        assert!(records.iter().all(|x| x.ttl == 60));
+        assert_eq!(cache.len(), 1);
+
+        // other types or classes are no hit
+        assert!(cache
+            .lookup(&question("www.google.de", QType::AAAA), now)
+            .is_none());
+        let mut chaos = question("www.google.de", QType::A);
+        chaos.qclass = QClass::Unknown(3);
+        assert!(cache.lookup(&chaos, now).is_none());
+        assert!(cache
+            .lookup(&question("www.google.de", QType::ANY), now)
+            .is_none());
+
+        let later = now + Duration::from_secs(50);
+        let records = cache
+            .lookup(&question("www.google.de", QType::A), later)
+            .unwrap()
+            .answers;
+        assert_eq!(records[0].ttl, 10);
+
+        let later = now + Duration::from_secs(60);
+        assert!(cache
+            .lookup(&question("www.google.de", QType::A), later)
+            .is_none());
+        // the lookup already removed it
+        assert!(cache.is_empty());
+        assert_eq!(cache.evict_expired(later), 0);
+    }
+
+    #[test]
+    pub fn test_cname_chain() {
+        let now = SystemTime::now();
+        let mut cache = cache(16);
+        let answer = [
+            cname("www.example.com", "Edge.Example.NET", 300),
+            cname("edge.example.net", "a1.cdn.example", 30),
+            record("a1.cdn.example", QType::A, 20, vec![192, 0, 2, 1]),
+            // not part of the chain
+            record("bank.example", QType::A, 300, vec![192, 0, 2, 66]),
+        ];
+        let response = DNS {
+            qr: 1,
+            questions: vec![question("www.example.com", QType::A)],
+            resource_records: answer.to_vec(),
+            ..DNS::default()
+        };
+        cache.insert_response(&response, now);
+        assert_eq!(cache.len(), 3);
+
+        let records = cache
+            .lookup(&question("www.example.com", QType::A), now)
+            .unwrap()
+            .answers;
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[2].rdata, vec![192, 0, 2, 1]);
+
+        // the CNAME itself is an answer, but not for other types
+        let records = cache
+            .lookup(&question("www.example.com", QType::CNAME), now)
+            .unwrap()
+            .answers;
+        assert_eq!(records.len(), 1);
+        assert!(cache
+            .lookup(&question("www.example.com", QType::MX), now)
+            .is_none());
+
+        // the chain breaks once a part of it expires
+        let later = now + Duration::from_secs(25);
+        assert!(cache
+            .lookup(&question("www.example.com", QType::A), later)
+            .is_none());
+
+        let key = CacheKey::new("Edge.Example.Net.", QType::CNAME, QClass::IN);
+        assert!(cache.remove(&key).is_some());
+        assert!(cache.get(&key).is_none());
+        assert!(cache
+            .lookup(&question("www.example.com", QType::A), now)
+            .is_none());
+    }
+
+    #[test]
+    pub fn test_cname_loop() {
+        let now = SystemTime::now();
+        let mut cache = cache(16);
+        cache.insert(
+            &[
+                cname("a.example", "b.example", 60),
+                cname("b.example", "a.example", 60),
+            ],
+            now,
+        );
+
+        assert!(cache
+            .lookup(&question("a.example", QType::A), now)
+            .is_none());
+    }
+
+    #[test]
+    pub fn test_max_entries() {
+        let now = SystemTime::now();
+        let mut cache = cache(2);
+        for (i, name) in ["a.example", "b.example"].iter().enumerate() {
+            cache.insert(&[record(name, QType::A, 60, vec![192, 0, 2, i as u8])], now);
+        }
+        // a is used more recently than b now
+        assert!(cache
+            .lookup(&question("a.example", QType::A), now)
+            .is_some());
+        cache.insert(
+            &[record("c.example", QType::A, 60, vec![192, 0, 2, 3])],
+            now,
+        );
+        // records without TTL are not cached at all
+        cache.insert(&[record("a.example", QType::A, 0, vec![192, 0, 2, 4])], now);
+
+        assert_eq!(cache.len(), 2);
+        assert!(cache
+            .lookup(&question("b.example", QType::A), now)
+            .is_none());
+        let records = cache
+            .lookup(&question("a.example", QType::A), now)
+            .unwrap()
+            .answers;
+        assert_eq!(records[0].rdata, vec![192, 0, 2, 0]);
+        assert_eq!(
+            cache.stats,
+            CacheStats {
+                hits: 2,
+                misses: 1,
+                evictions: 1,
+                expirations: 0,
+                stale_hits: 0,
+                prefetches: 0,
+            }
+        );
+    }
+
+    #[test]
+    pub fn test_max_bytes() {
+        let now = SystemTime::now();
+        let mut cache = cache(16);
+        cache.insert(&[record("a.example", QType::TXT, 60, vec![0; 100])], now);
+        let size = cache.bytes();
+        assert!(size > 100);
+
+        // room for two RRsets of that size
+        let mut cache = Cache::new(&CacheConfig {
+            max_entries: 16,
+            max_bytes: 2 * size + 50,
+            stale_window: Duration::ZERO,
+            ..CacheConfig::default()
+        });
+        for name in ["a.example", "b.example", "c.example"].iter() {
+            cache.insert(&[record(name, QType::TXT, 60, vec![0; 100])], now);
+        }
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.bytes(), 2 * size);
+        assert_eq!(cache.stats.evictions, 1);
+
+        // replacing an RRset accounts only for the new one
+        cache.insert(&[record("c.example", QType::TXT, 60, vec![0; 10])], now);
+        assert_eq!(cache.bytes(), 2 * size - 90);
+
+        // larger than the whole cache
+        cache.insert(&[record("d.example", QType::TXT, 60, vec![0; 1000])], now);
+        assert_eq!(cache.len(), 2);
+        assert!(cache
+            .get(&CacheKey::new("d.example", QType::TXT, QClass::IN))
+            .is_none());
+
+        // expired RRsets are removed by the lookup
+        let later = now + Duration::from_secs(60);
+        assert!(cache
+            .lookup(&question("b.example", QType::TXT), later)
+            .is_none());
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.evict_expired(later), 1);
+        assert_eq!(cache.bytes(), 0);
+        assert_eq!(cache.stats.expirations, 2);
+    }
+
+    /// SOA of the zone with a TTL of 3600 and MINIMUM of 300
+    pub fn soa(zone: &str) -> ResourceRecord {
+        let mut rdata = vec![0, 0];
+        for x in [1u32, 7200, 3600, 1_209_600, 300].iter() {
+            rdata.extend_from_slice(&x.to_be_bytes());
+        }
+        record(zone, QType::SOA, 3600, rdata)
+    }
+
+    fn response(qname: &str, qtype: QType, rcode: Rcode) -> DNS {
+        DNS {
+            qr: 1,
+            rcode,
+            questions: vec![question(qname, qtype)],
+            ..DNS::default()
+        }
+    }
+
+    #[test]
+    pub fn test_negative() {
+        let now = SystemTime::now();
+        let mut cache = cache(16);
+
+        let mut nxdomain = response("nx.example.com", QType::A, Rcode::NameError);
+        nxdomain.authorities = vec![soa("example.com")];
+        cache.insert_response(&nxdomain, now);
+
+        // NXDOMAIN holds for every type of the name
+        let answer = cache
+            .lookup(&question("NX.example.com", QType::AAAA), now)
+            .unwrap();
+        assert_eq!(answer.rcode, Rcode::NameError);
+        assert!(answer.answers.is_empty());
+        assert_eq!(answer.authorities[0].rtype, QType::SOA);
+        assert_eq!(answer.authorities[0].ttl, 300);
+
+        let mut nodata = response("www.example.com", QType::AAAA, Rcode::NoError);
+        nodata.authorities = vec![soa("example.com")];
+        cache.insert_response(&nodata, now);
+
+        let answer = cache
+            .lookup(&question("www.example.com", QType::AAAA), now)
+            .unwrap();
+        assert_eq!(answer.rcode, Rcode::NoError);
+        assert!(answer.answers.is_empty());
+        assert_eq!(answer.authorities.len(), 1);
+        assert!(cache
+            .lookup(&question("www.example.com", QType::A), now)
+            .is_none());
+
+        let later = now + Duration::from_secs(300);
+        assert!(cache
+            .lookup(&question("nx.example.com", QType::A), later)
+            .is_none());
+        assert_eq!(cache.evict_expired(later), 1);
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    pub fn test_negative_after_cname() {
+        let now = SystemTime::now();
+        let mut cache = cache(16);
+
+        let mut nxdomain = response("www.example.com", QType::A, Rcode::NameError);
+        nxdomain.resource_records = vec![cname("www.example.com", "gone.example.net", 600)];
+        nxdomain.authorities = vec![soa("example.net")];
+        cache.insert_response(&nxdomain, now);
+
+        let answer = cache
+            .lookup(&question("www.example.com", QType::A), now)
+            .unwrap();
+        assert_eq!(answer.rcode, Rcode::NameError);
+        assert_eq!(answer.answers[0].rtype, QType::CNAME);
+        assert_eq!(answer.authorities[0].name, "example.net");
+        assert!(cache
+            .get(&CacheKey::name_error("www.example.com", QClass::IN))
+            .is_none());
+    }
+
+    #[test]
+    pub fn test_negative_without_soa() {
+        let now = SystemTime::now();
+        let mut cache = cache(16);
+
+        cache.insert_response(&response("a.example", QType::A, Rcode::NameError), now);
+
+        // the SOA of another zone proves nothing
+        let mut nxdomain = response("b.example", QType::A, Rcode::NameError);
+        nxdomain.authorities = vec![soa("other.example")];
+        cache.insert_response(&nxdomain, now);
+
+        let mut servfail = response("c.example", QType::A, Rcode::ServerFailure);
+        servfail.authorities = vec![soa("example")];
+        cache.insert_response(&servfail, now);
+
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    pub fn test_stale() {
+        let now = SystemTime::now();
+        let mut cache = Cache::new(&CacheConfig {
+            max_entries: 16,
+            max_bytes: usize::MAX,
+            stale_window: Duration::from_secs(3600),
+            ..CacheConfig::default()
+        });
+        cache.insert(
+            &[
+                cname("www.example.com", "edge.example.net", 600),
+                record("edge.example.net", QType::A, 60, vec![192, 0, 2, 1]),
+            ],
+            now,
+        );
+
+        let answer = cache
+            .lookup_stale(&question("www.example.com", QType::A), now)
+            .unwrap();
+        assert!(!answer.stale);
+        assert_eq!(answer.answers[1].ttl, 60);
+
+        // expired records are kept, but only used if asked for
+        let later = now + Duration::from_secs(120);
+        assert!(cache
+            .lookup(&question("www.example.com", QType::A), later)
+            .is_none());
+        assert_eq!(cache.evict_expired(later), 0);
+        let answer = cache
+            .lookup_stale(&question("www.example.com", QType::A), later)
+            .unwrap();
+        assert!(answer.stale);
+        assert_eq!(answer.answers[0].ttl, 480);
+        assert_eq!(answer.answers[1].ttl, STALE_TTL);
+        assert_eq!(cache.stats.stale_hits, 1);
+
+        // after the stale window they are gone
+        let later = now + Duration::from_secs(3660);
+        assert!(cache
+            .lookup_stale(&question("www.example.com", QType::A), later)
+            .is_none());
+        assert_eq!(cache.stats.expirations, 1);
+        assert_eq!(cache.evict_expired(now + Duration::from_secs(4200)), 1);
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    pub fn test_prefetch() {
+        let now = SystemTime::now();
+        let mut cache = cache(16);
+        let records = [
+            record("a.example", QType::A, 100, vec![192, 0, 2, 1]),
+            record("b.example", QType::A, 100, vec![192, 0, 2, 2]),
+        ];
+        cache.insert(&records, now);
+
+        let a = question("a.example", QType::A);
+        let b = question("b.example", QType::A);
+        assert!(!cache.lookup(&a, now).unwrap().prefetch);
+        assert!(!cache.lookup(&a, now).unwrap().prefetch);
+        assert!(!cache.lookup(&b, now).unwrap().prefetch);
+
+        // popular and in the last tenth of its TTL
+        let later = now + Duration::from_secs(91);
+        assert!(cache.lookup(&a, later).unwrap().prefetch);
+        assert!(!cache.lookup(&b, later).unwrap().prefetch);
+        // only one refresh is requested
+        assert!(!cache.lookup(&a, later).unwrap().prefetch);
+        assert_eq!(cache.stats.prefetches, 1);
+
+        // until the refresh replaced the entry
+        cache.insert(&records[..1], later);
+        for _ in 0..3 {
+            assert!(!cache.lookup(&a, later).unwrap().prefetch);
+        }
+        let later = later + Duration::from_secs(90);
+        assert!(cache.lookup(&a, later).unwrap().prefetch);
+    }
+
+    #[test]
+    pub fn test_ttl_policy() {
+        let now = SystemTime::now();
+        let mut ttl_overrides = HashMap::new();
+        ttl_overrides.insert("example.net".to_string(), 5);
+        ttl_overrides.insert("ads.example.net".to_string(), 0);
+        let mut cache = Cache::new(&CacheConfig {
+            min_ttl: 60,
+            max_ttl: 600,
+            max_negative_ttl: 120,
+            ttl_overrides,
+            stale_window: Duration::ZERO,
+            ..CacheConfig::default()
+        });
+        cache.insert(
+            &[
+                record("short.example.com", QType::A, 10, vec![192, 0, 2, 1]),
+                record("long.example.com", QType::A, 86400, vec![192, 0, 2, 2]),
+                record("zero.example.com", QType::A, 0, vec![192, 0, 2, 3]),
+                record("www.Example.net", QType::A, 3600, vec![192, 0, 2, 4]),
+                record("x.ads.example.net", QType::A, 3600, vec![192, 0, 2, 5]),
+            ],
+            now,
+        );
+
+        let ttl = |cache: &mut Cache, qname: &str, now| {
+            cache
+                .lookup(&question(qname, QType::A), now)
+                .map(|x| x.answers[0].ttl)
+        };
+        assert_eq!(ttl(&mut cache, "short.example.com", now), Some(60));
+        assert_eq!(ttl(&mut cache, "long.example.com", now), Some(600));
+        assert_eq!(ttl(&mut cache, "zero.example.com", now), None);
+        // the longest matching domain wins
+        assert_eq!(ttl(&mut cache, "www.example.net", now), Some(5));
+        assert_eq!(ttl(&mut cache, "x.ads.example.net", now), None);
+
+        // clients still get the remaining time
+        let later = now + Duration::from_secs(15);
+        assert_eq!(ttl(&mut cache, "short.example.com", later), Some(45));
+        assert_eq!(ttl(&mut cache, "long.example.com", later), Some(585));
+        assert_eq!(ttl(&mut cache, "www.example.net", later), None);
+
+        // the SOA minimum of 300 seconds is capped
+        let mut nxdomain = response("nx.example.com", QType::A, Rcode::NameError);
+        nxdomain.authorities = vec![soa("example.com")];
+        cache.insert_response(&nxdomain, now);
+        let answer = cache
+            .lookup(&question("nx.example.com", QType::A), now)
+            .unwrap();
+        assert_eq!(answer.rcode, Rcode::NameError);
+        assert_eq!(answer.authorities[0].ttl, 120);
+    }
+}
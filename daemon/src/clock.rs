@@ -0,0 +1,127 @@
+#[cfg(test)]
+use std::sync::atomic::{AtomicU64, Ordering};
+#[cfg(test)]
+use std::sync::Arc;
+#[cfg(test)]
+use std::time::Duration;
+use std::time::{Instant, SystemTime};
+
+/// Source of the current time, so that time dependent behavior can be
+/// tested without waiting
+pub trait Clock: Send + Sync {
+    /// Monotonic time for timeouts and deadlines
+    fn instant(&self) -> Instant;
+    /// Wall clock time for TTLs and the validity of signatures
+    fn system_time(&self) -> SystemTime;
+}
+
+/// The time of the system
+///
+/// The wall clock time is taken once and then advanced with the monotonic
+/// clock, so that it never jumps, not even when the system time is set
+/// backwards.
+pub struct RealClock {
+    start: Instant,
+    start_time: SystemTime,
+}
+
+impl RealClock {
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            start_time: SystemTime::now(),
+        }
+    }
+}
+
+impl Default for RealClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for RealClock {
+    fn instant(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn system_time(&self) -> SystemTime {
+        self.start_time + self.start.elapsed()
+    }
+}
+
+/// Clock that stands still until it is advanced, clones share their time
+#[cfg(test)]
+#[derive(Clone)]
+pub struct ManualClock {
+    start: Instant,
+    start_time: SystemTime,
+    /// Nanoseconds the clock was advanced by
+    offset: Arc<AtomicU64>,
+}
+
+#[cfg(test)]
+impl ManualClock {
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            start_time: SystemTime::now(),
+            offset: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        self.offset
+            .fetch_add(duration.as_nanos() as u64, Ordering::SeqCst);
+    }
+
+    fn offset(&self) -> Duration {
+        Duration::from_nanos(self.offset.load(Ordering::SeqCst))
+    }
+}
+
+#[cfg(test)]
+impl Clock for ManualClock {
+    fn instant(&self) -> Instant {
+        self.start + self.offset()
+    }
+
+    fn system_time(&self) -> SystemTime {
+        self.start_time + self.offset()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_manual_clock() {
+        let clock = ManualClock::new();
+        let shared = clock.clone();
+        let (instant, time) = (clock.instant(), clock.system_time());
+        assert_eq!(clock.instant(), instant);
+
+        shared.advance(Duration::from_millis(1500));
+        assert_eq!(clock.instant() - instant, Duration::from_millis(1500));
+        assert_eq!(
+            clock.system_time().duration_since(time).unwrap(),
+            Duration::from_millis(1500)
+        );
+    }
+
+    #[test]
+    pub fn test_real_clock() {
+        let clock = RealClock::new();
+        let time = clock.system_time();
+        let instant = clock.instant();
+
+        assert!(clock.system_time() >= time);
+        assert!(clock.instant() >= instant);
+        let difference = match SystemTime::now().duration_since(clock.system_time()) {
+            Ok(x) => x,
+            Err(e) => e.duration(),
+        };
+        assert!(difference < Duration::from_secs(1));
+    }
+}
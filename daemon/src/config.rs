@@ -0,0 +1,797 @@
+use crate::error::*;
+use crate::hosts::address_record;
+use crate::resolver::ROOT_SERVERS;
+use crate::upstream::Strategy;
+
+use log::Level;
+use rdns_proto::*;
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::time::Duration;
+use yaml_rust::{Yaml, YamlLoader};
+
+/// Port of upstream servers given without one
+const DNS_PORT: u16 = 53;
+
+const KEYS: [&str; 12] = [
+    "listen-address",
+    "servers",
+    "upstream",
+    "recursion",
+    "hosts",
+    "load_hosts_file",
+    "hosts_file",
+    "blocklist",
+    "cache",
+    "max-concurrent-queries",
+    "log-level",
+    "capture",
+];
+const CACHE_KEYS: [&str; 11] = [
+    "max-entries",
+    "max-bytes",
+    "min-ttl-s",
+    "max-ttl-s",
+    "max-negative-ttl-s",
+    "ttl-overrides",
+    "stale-window-s",
+    "client-timeout-ms",
+    "prefetch-hits",
+    "prefetch-percent",
+    "snapshot",
+];
+const UPSTREAM_KEYS: [&str; 3] = ["strategy", "timeout-ms", "deadline-ms"];
+const RECURSION_KEYS: [&str; 4] = ["enabled", "root-hints", "max-referrals", "max-queries"];
+
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// Addresses of the `listen-address` section the daemon answers on
+    pub listen: Vec<SocketAddr>,
+    /// Upstream servers queries are forwarded to
+    pub servers: Vec<SocketAddr>,
+    pub upstream: UpstreamConfig,
+    pub recursion: RecursionConfig,
+    /// Records of the `hosts` section by name
+    pub hosts: HashMap<String, Vec<ResourceRecord>>,
+    /// Whether the entries of `hosts_file` should be served as well
+    pub load_hosts_file: bool,
+    /// Hosts file that is watched for changes
+    pub hosts_file: String,
+    /// Names of the `blocklist` section, every subdomain is blocked as well
+    pub blocklist: HashSet<String>,
+    pub cache: CacheConfig,
+    /// Queries handled at once, further ones wait in the socket buffer
+    pub max_concurrent_queries: usize,
+    pub log_level: Level,
+    /// File all received and sent datagrams are recorded to as pcap
+    pub capture: Option<String>,
+}
+
+/// Settings of the `cache` section
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CacheConfig {
+    /// Maximum number of cached RRsets, the least recently used ones are
+    /// evicted once it is reached
+    pub max_entries: usize,
+    /// Approximate maximum of memory the cached RRsets take
+    pub max_bytes: usize,
+    /// Seconds RRsets are cached at least, unless their TTL is zero
+    pub min_ttl: u32,
+    /// Seconds RRsets are cached at most
+    pub max_ttl: u32,
+    /// Seconds negative answers are cached at most
+    pub max_negative_ttl: u32,
+    /// Seconds RRsets of the domains and their subdomains are cached,
+    /// regardless of their TTL, zero to never cache them
+    pub ttl_overrides: HashMap<String, u32>,
+    /// Time expired RRsets are kept to answer when no upstream server does,
+    /// zero to never serve stale answers
+    pub stale_window: Duration,
+    /// Time after which a stale answer is sent while upstream servers are
+    /// still queried
+    pub client_timeout: Duration,
+    /// Lookups after which an RRset is refreshed before it expires
+    pub prefetch_hits: u64,
+    /// Percentage of the TTL that is left when a popular RRset is refreshed,
+    /// zero to never refresh
+    pub prefetch_percent: u32,
+    /// File the cache is saved to periodically and on shutdown, and
+    /// restored from on startup
+    pub snapshot: Option<String>,
+}
+
+/// Settings of the `upstream` section
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct UpstreamConfig {
+    pub strategy: Strategy,
+    /// Time the first upstream server has to answer before the next one is
+    /// tried, doubled with every retry
+    pub timeout: Duration,
+    /// Time after which the requester gets SERVFAIL
+    pub deadline: Duration,
+}
+
+/// Settings of the `recursion` section
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RecursionConfig {
+    /// Whether queries are resolved starting at the root servers instead of
+    /// being forwarded to `servers`
+    pub enabled: bool,
+    /// Addresses of the root servers
+    pub root_hints: Vec<SocketAddr>,
+    /// Referrals followed for one name before it fails
+    pub max_referrals: u32,
+    /// Queries sent for one request, including the ones that resolve the
+    /// addresses of nameservers
+    pub max_queries: u32,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            listen: vec![SocketAddr::from(([127, 0, 0, 1], 1337))],
+            servers: vec![
+                SocketAddr::from(([8, 8, 8, 8], DNS_PORT)),
+                SocketAddr::from(([8, 8, 4, 4], DNS_PORT)),
+            ],
+            upstream: UpstreamConfig::default(),
+            recursion: RecursionConfig::default(),
+            hosts: HashMap::new(),
+            load_hosts_file: false,
+            hosts_file: "/etc/hosts".into(),
+            blocklist: HashSet::new(),
+            cache: CacheConfig::default(),
+            max_concurrent_queries: 256,
+            log_level: Level::Debug,
+            capture: None,
+        }
+    }
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            max_entries: 10_000,
+            max_bytes: 16 * 1024 * 1024,
+            min_ttl: 0,
+            max_ttl: 24 * 60 * 60,
+            max_negative_ttl: 60 * 60,
+            ttl_overrides: HashMap::new(),
+            stale_window: Duration::from_secs(24 * 60 * 60),
+            client_timeout: Duration::from_millis(1800),
+            prefetch_hits: 3,
+            prefetch_percent: 10,
+            snapshot: None,
+        }
+    }
+}
+
+impl Default for UpstreamConfig {
+    fn default() -> Self {
+        Self {
+            strategy: Strategy::Sequential,
+            timeout: Duration::from_millis(500),
+            deadline: Duration::from_millis(4000),
+        }
+    }
+}
+
+impl Default for RecursionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            root_hints: ROOT_SERVERS
+                .iter()
+                .map(|x| SocketAddr::new(IpAddr::V4(*x), DNS_PORT))
+                .collect(),
+            max_referrals: 16,
+            max_queries: 64,
+        }
+    }
+}
+
+impl Config {
+    pub fn load(path: &str) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| RdnsError::InvalidConfig(format!("{}: {}", path, e)))?;
+        Self::parse(&content)
+    }
+
+    pub fn parse(content: &str) -> Result<Self> {
+        let documents = YamlLoader::load_from_str(content)
+            .map_err(|e| RdnsError::InvalidConfig(e.to_string()))?;
+        let document = match documents.into_iter().next() {
+            Some(x) => x,
+            None => return Ok(Self::default()),
+        };
+        match &document {
+            Yaml::Hash(x) => check_keys("", x, &KEYS)?,
+            Yaml::Null => return Ok(Self::default()),
+            _ => {
+                return Err(RdnsError::InvalidConfig(
+                    "expected a mapping of settings".into(),
+                ))
+            }
+        }
+
+        let mut config = Self::default();
+
+        if !is_missing(&document["listen-address"]) {
+            config.listen = one_or_more(&document, "listen-address")?
+                .iter()
+                .map(|x| {
+                    x.as_str()
+                        .and_then(|x| x.parse::<SocketAddr>().ok())
+                        .ok_or_else(|| {
+                            invalid("listen-address", &format!("invalid address {:?}", x))
+                        })
+                })
+                .collect::<Result<Vec<SocketAddr>>>()?;
+        }
+
+        if !is_missing(&document["servers"]) {
+            config.servers = list(&document, "servers")?
+                .iter()
+                .map(|x| {
+                    upstream(x)
+                        .ok_or_else(|| invalid("servers", &format!("invalid address {:?}", x)))
+                })
+                .collect::<Result<Vec<SocketAddr>>>()?;
+        }
+
+        match &document["upstream"] {
+            Yaml::Hash(x) => check_keys("upstream.", x, &UPSTREAM_KEYS)?,
+            Yaml::BadValue | Yaml::Null => (),
+            _ => return Err(invalid("upstream", "expected a mapping")),
+        }
+        match &document["upstream"]["strategy"] {
+            Yaml::String(x) => {
+                config.upstream.strategy = Strategy::from_str(x).map_err(|_| {
+                    invalid(
+                        "upstream.strategy",
+                        "expected one of sequential, round-robin, fastest or race",
+                    )
+                })?
+            }
+            Yaml::BadValue | Yaml::Null => (),
+            _ => return Err(invalid("upstream.strategy", "expected a string")),
+        }
+        if let Some(x) = milliseconds(&document, "upstream", "timeout-ms")? {
+            config.upstream.timeout = x;
+        }
+        if let Some(x) = milliseconds(&document, "upstream", "deadline-ms")? {
+            config.upstream.deadline = x;
+        }
+        if config.upstream.timeout > config.upstream.deadline {
+            return Err(invalid(
+                "upstream.timeout-ms",
+                "expected at most upstream.deadline-ms",
+            ));
+        }
+
+        match &document["recursion"] {
+            Yaml::Hash(x) => check_keys("recursion.", x, &RECURSION_KEYS)?,
+            Yaml::BadValue | Yaml::Null => (),
+            _ => return Err(invalid("recursion", "expected a mapping")),
+        }
+        match &document["recursion"]["enabled"] {
+            Yaml::Boolean(x) => config.recursion.enabled = *x,
+            Yaml::BadValue | Yaml::Null => (),
+            _ => return Err(invalid("recursion.enabled", "expected true or false")),
+        }
+        match &document["recursion"]["root-hints"] {
+            Yaml::Array(x) if !x.is_empty() => {
+                config.recursion.root_hints = x
+                    .iter()
+                    .map(|x| {
+                        upstream(x).ok_or_else(|| {
+                            invalid("recursion.root-hints", &format!("invalid address {:?}", x))
+                        })
+                    })
+                    .collect::<Result<Vec<SocketAddr>>>()?;
+            }
+            Yaml::BadValue | Yaml::Null => (),
+            _ => {
+                return Err(invalid(
+                    "recursion.root-hints",
+                    "expected a non empty list of addresses",
+                ))
+            }
+        }
+        match &document["recursion"]["max-referrals"] {
+            Yaml::Integer(x) if (1..=i64::from(u32::MAX)).contains(x) => {
+                config.recursion.max_referrals = *x as u32
+            }
+            Yaml::BadValue | Yaml::Null => (),
+            _ => {
+                return Err(invalid(
+                    "recursion.max-referrals",
+                    "expected a positive number",
+                ))
+            }
+        }
+        match &document["recursion"]["max-queries"] {
+            Yaml::Integer(x) if (1..=i64::from(u32::MAX)).contains(x) => {
+                config.recursion.max_queries = *x as u32
+            }
+            Yaml::BadValue | Yaml::Null => (),
+            _ => {
+                return Err(invalid(
+                    "recursion.max-queries",
+                    "expected a positive number",
+                ))
+            }
+        }
+
+        for entry in list(&document, "hosts")? {
+            let entry = entry
+                .as_hash()
+                .ok_or_else(|| invalid("hosts", "expected `<address>: <name>`"))?;
+
+            for (address, names) in entry {
+                let address = address
+                    .as_str()
+                    .and_then(|x| x.parse::<IpAddr>().ok())
+                    .ok_or_else(|| invalid("hosts", &format!("invalid address {:?}", address)))?;
+
+                let names = match names {
+                    Yaml::Array(x) => x.iter().collect::<Vec<&Yaml>>(),
+                    x => vec![x],
+                };
+                for name in names {
+                    let name = normalize("hosts", name)?;
+                    config
+                        .hosts
+                        .entry(name.clone())
+                        .or_insert_with(Vec::new)
+                        .push(address_record(name, address));
+                }
+            }
+        }
+
+        config.load_hosts_file = match &document["load_hosts_file"] {
+            Yaml::Boolean(x) => *x,
+            Yaml::BadValue | Yaml::Null => false,
+            _ => return Err(invalid("load_hosts_file", "expected true or false")),
+        };
+        match &document["hosts_file"] {
+            Yaml::String(x) => config.hosts_file = x.clone(),
+            Yaml::BadValue | Yaml::Null => (),
+            _ => return Err(invalid("hosts_file", "expected a path")),
+        }
+
+        for name in list(&document, "blocklist")? {
+            config.blocklist.insert(normalize("blocklist", name)?);
+        }
+
+        match &document["cache"] {
+            Yaml::Hash(x) => check_keys("cache.", x, &CACHE_KEYS)?,
+            Yaml::BadValue | Yaml::Null => (),
+            _ => return Err(invalid("cache", "expected a mapping")),
+        }
+        match &document["cache"]["max-entries"] {
+            Yaml::Integer(x) if *x > 0 => config.cache.max_entries = *x as usize,
+            Yaml::BadValue | Yaml::Null => (),
+            _ => return Err(invalid("cache.max-entries", "expected a positive number")),
+        }
+        match &document["cache"]["max-bytes"] {
+            Yaml::Integer(x) if *x > 0 => config.cache.max_bytes = *x as usize,
+            Yaml::BadValue | Yaml::Null => (),
+            _ => return Err(invalid("cache.max-bytes", "expected a positive number")),
+        }
+        if let Some(x) = seconds(&document, "cache", "min-ttl-s")? {
+            config.cache.min_ttl = x;
+        }
+        if let Some(x) = seconds(&document, "cache", "max-ttl-s")? {
+            config.cache.max_ttl = x;
+        }
+        if let Some(x) = seconds(&document, "cache", "max-negative-ttl-s")? {
+            config.cache.max_negative_ttl = x;
+        }
+        if config.cache.min_ttl > config.cache.max_ttl {
+            return Err(invalid(
+                "cache.min-ttl-s",
+                "expected at most cache.max-ttl-s",
+            ));
+        }
+        match &document["cache"]["ttl-overrides"] {
+            Yaml::Hash(x) => {
+                for (name, ttl) in x {
+                    let ttl = match ttl {
+                        Yaml::Integer(x) if (0..=i64::from(u32::MAX)).contains(x) => *x as u32,
+                        _ => {
+                            return Err(invalid(
+                                "cache.ttl-overrides",
+                                &format!("invalid number of seconds {:?}", ttl),
+                            ))
+                        }
+                    };
+                    let name = normalize("cache.ttl-overrides", name)?;
+                    config.cache.ttl_overrides.insert(name, ttl);
+                }
+            }
+            Yaml::BadValue | Yaml::Null => (),
+            _ => {
+                return Err(invalid(
+                    "cache.ttl-overrides",
+                    "expected `<name>: <seconds>`",
+                ))
+            }
+        }
+        match &document["cache"]["stale-window-s"] {
+            Yaml::Integer(x) if *x >= 0 => {
+                config.cache.stale_window = Duration::from_secs(*x as u64)
+            }
+            Yaml::BadValue | Yaml::Null => (),
+            _ => {
+                return Err(invalid(
+                    "cache.stale-window-s",
+                    "expected a number of seconds",
+                ))
+            }
+        }
+        if let Some(x) = milliseconds(&document, "cache", "client-timeout-ms")? {
+            config.cache.client_timeout = x;
+        }
+        match &document["cache"]["prefetch-hits"] {
+            Yaml::Integer(x) if *x > 0 => config.cache.prefetch_hits = *x as u64,
+            Yaml::BadValue | Yaml::Null => (),
+            _ => return Err(invalid("cache.prefetch-hits", "expected a positive number")),
+        }
+        match &document["cache"]["prefetch-percent"] {
+            Yaml::Integer(x) if (0..=100).contains(x) => config.cache.prefetch_percent = *x as u32,
+            Yaml::BadValue | Yaml::Null => (),
+            _ => {
+                return Err(invalid(
+                    "cache.prefetch-percent",
+                    "expected a number from 0 to 100",
+                ))
+            }
+        }
+        match &document["cache"]["snapshot"] {
+            Yaml::String(x) => config.cache.snapshot = Some(x.clone()),
+            Yaml::BadValue | Yaml::Null => (),
+            _ => return Err(invalid("cache.snapshot", "expected a path")),
+        }
+
+        match &document["max-concurrent-queries"] {
+            Yaml::Integer(x) if *x > 0 => config.max_concurrent_queries = *x as usize,
+            Yaml::BadValue | Yaml::Null => (),
+            _ => {
+                return Err(invalid(
+                    "max-concurrent-queries",
+                    "expected a positive number",
+                ))
+            }
+        }
+
+        config.log_level = match &document["log-level"] {
+            Yaml::String(x) => Level::from_str(x).map_err(|_| {
+                invalid(
+                    "log-level",
+                    "expected one of error, warn, info, debug or trace",
+                )
+            })?,
+            Yaml::BadValue | Yaml::Null => config.log_level,
+            _ => return Err(invalid("log-level", "expected a string")),
+        };
+
+        config.capture = match &document["capture"] {
+            Yaml::String(x) => Some(x.clone()),
+            Yaml::BadValue | Yaml::Null => None,
+            _ => return Err(invalid("capture", "expected a path")),
+        };
+
+        Ok(config)
+    }
+}
+
+/// Converts the name to its lowercase ASCII form without trailing dot
+fn normalize(key: &str, name: &Yaml) -> Result<String> {
+    let name = name
+        .as_str()
+        .ok_or_else(|| invalid(key, &format!("invalid name {:?}", name)))?;
+
+    domain_to_ascii(name)
+        .map(|x| x.trim_end_matches('.').to_string())
+        .map_err(|_| invalid(key, &format!("invalid name {:?}", name)))
+}
+
+/// Address with an optional port, 53 if there is none
+fn upstream(address: &Yaml) -> Option<SocketAddr> {
+    let address = address.as_str()?;
+    address
+        .parse::<SocketAddr>()
+        .or_else(|_| {
+            address
+                .parse::<IpAddr>()
+                .map(|x| SocketAddr::new(x, DNS_PORT))
+        })
+        .ok()
+}
+
+/// Number of seconds that fits a TTL, `None` if the key is missing
+fn seconds(document: &Yaml, section: &str, key: &str) -> Result<Option<u32>> {
+    match &document[section][key] {
+        Yaml::Integer(x) if (0..=i64::from(u32::MAX)).contains(x) => Ok(Some(*x as u32)),
+        Yaml::BadValue | Yaml::Null => Ok(None),
+        _ => Err(invalid(
+            &format!("{}.{}", section, key),
+            "expected a number of seconds",
+        )),
+    }
+}
+
+/// Positive number of milliseconds, `None` if the key is missing
+fn milliseconds(document: &Yaml, section: &str, key: &str) -> Result<Option<Duration>> {
+    match &document[section][key] {
+        Yaml::Integer(x) if *x > 0 => Ok(Some(Duration::from_millis(*x as u64))),
+        Yaml::BadValue | Yaml::Null => Ok(None),
+        _ => Err(invalid(
+            &format!("{}.{}", section, key),
+            "expected a positive number of milliseconds",
+        )),
+    }
+}
+
+fn list<'a>(document: &'a Yaml, key: &str) -> Result<&'a [Yaml]> {
+    match &document[key] {
+        Yaml::Array(x) => Ok(x),
+        Yaml::BadValue | Yaml::Null => Ok(&[]),
+        _ => Err(invalid(key, "expected a list")),
+    }
+}
+
+/// Single value or a non empty list of values
+fn one_or_more<'a>(document: &'a Yaml, key: &str) -> Result<Vec<&'a Yaml>> {
+    match &document[key] {
+        Yaml::Array(x) if x.is_empty() => Err(invalid(key, "expected at least one value")),
+        Yaml::Array(x) => Ok(x.iter().collect()),
+        x => Ok(vec![x]),
+    }
+}
+
+fn is_missing(value: &Yaml) -> bool {
+    matches!(value, Yaml::BadValue | Yaml::Null)
+}
+
+/// Rejects keys that are not known, most likely they contain a typo
+fn check_keys(prefix: &str, hash: &yaml_rust::yaml::Hash, keys: &[&str]) -> Result<()> {
+    for key in hash.keys() {
+        match key.as_str() {
+            Some(x) if keys.contains(&x) => (),
+            Some(x) => return Err(invalid(&format!("{}{}", prefix, x), "unknown key")),
+            None => return Err(invalid(&format!("{}{:?}", prefix, key), "unknown key")),
+        }
+    }
+    Ok(())
+}
+
+fn invalid(key: &str, message: &str) -> RdnsError {
+    RdnsError::InvalidConfig(format!("{}: {}", key, message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_parse_sample() {
+        let config = Config::parse(include_str!("../config.sample.yml")).unwrap();
+
+        assert_eq!(config.hosts["dev.local"][0].rdata, vec![127, 0, 0, 1]);
+        assert_eq!(config.hosts["local"][0].rtype, QType::A);
+        assert!(!config.blocklist.is_empty());
+        assert_eq!(config.capture, None);
+        assert_eq!(config.listen, vec!["0.0.0.0:1337".parse().unwrap()]);
+        assert_eq!(
+            config.servers,
+            vec!["8.8.8.8:53".parse().unwrap(), "8.8.4.4:53".parse().unwrap()]
+        );
+        assert!(config.load_hosts_file);
+        assert_eq!(config.hosts_file, "/etc/hosts");
+        assert_eq!(config.log_level, Level::Info);
+        assert_eq!(config.cache, CacheConfig::default());
+        assert_eq!(config.max_concurrent_queries, 256);
+        assert_eq!(config.upstream, UpstreamConfig::default());
+        assert_eq!(config.recursion, RecursionConfig::default());
+    }
+
+    #[test]
+    pub fn test_parse_addresses() {
+        let config = Config::parse(
+            "
+listen-address:
+  - 127.0.0.1:53
+  - '[::1]:53'
+servers:
+  - 9.9.9.9
+  - 192.168.1.1:5353
+  - 2620:fe::fe
+  - '[2620:fe::9]:5353'
+upstream:
+  strategy: fastest
+  timeout-ms: 250
+  deadline-ms: 3000
+recursion:
+  enabled: true
+  root-hints:
+    - 198.41.0.4
+    - '[2001:503:ba3e::2:30]:53'
+  max-referrals: 8
+  max-queries: 32
+cache:
+  max-entries: 42
+  max-bytes: 65536
+  min-ttl-s: 30
+  max-ttl-s: 3600
+  max-negative-ttl-s: 300
+  ttl-overrides:
+    Example.com.: 60
+    ads.example.net: 0
+  stale-window-s: 0
+  client-timeout-ms: 1000
+  prefetch-hits: 10
+  prefetch-percent: 20
+  snapshot: /var/cache/rdns.snapshot
+max-concurrent-queries: 8
+log-level: WARN
+",
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.listen,
+            vec!["127.0.0.1:53".parse().unwrap(), "[::1]:53".parse().unwrap()]
+        );
+        assert_eq!(
+            config.servers,
+            vec![
+                "9.9.9.9:53".parse().unwrap(),
+                "192.168.1.1:5353".parse().unwrap(),
+                "[2620:fe::fe]:53".parse().unwrap(),
+                "[2620:fe::9]:5353".parse().unwrap(),
+            ]
+        );
+        assert_eq!(config.upstream.strategy, Strategy::Fastest);
+        assert_eq!(config.upstream.timeout, Duration::from_millis(250));
+        assert_eq!(config.upstream.deadline, Duration::from_millis(3000));
+        assert!(config.recursion.enabled);
+        assert_eq!(
+            config.recursion.root_hints,
+            vec![
+                "198.41.0.4:53".parse().unwrap(),
+                "[2001:503:ba3e::2:30]:53".parse().unwrap(),
+            ]
+        );
+        assert_eq!(config.recursion.max_referrals, 8);
+        assert_eq!(config.recursion.max_queries, 32);
+        assert_eq!(config.cache.max_entries, 42);
+        assert_eq!(config.cache.max_bytes, 65536);
+        assert_eq!(config.cache.min_ttl, 30);
+        assert_eq!(config.cache.max_ttl, 3600);
+        assert_eq!(config.cache.max_negative_ttl, 300);
+        assert_eq!(config.cache.ttl_overrides.len(), 2);
+        assert_eq!(config.cache.ttl_overrides["example.com"], 60);
+        assert_eq!(config.cache.ttl_overrides["ads.example.net"], 0);
+        assert_eq!(config.cache.stale_window, Duration::ZERO);
+        assert_eq!(config.cache.client_timeout, Duration::from_millis(1000));
+        assert_eq!(config.cache.prefetch_hits, 10);
+        assert_eq!(config.cache.prefetch_percent, 20);
+        assert_eq!(
+            config.cache.snapshot,
+            Some("/var/cache/rdns.snapshot".into())
+        );
+        assert_eq!(config.max_concurrent_queries, 8);
+        assert_eq!(config.log_level, Level::Warn);
+
+        // everything that is not given keeps its default
+        let config = Config::parse("capture: rdns.pcap\n").unwrap();
+        assert_eq!(config.listen, Config::default().listen);
+        assert_eq!(config.servers, Config::default().servers);
+        assert_eq!(config.log_level, Level::Debug);
+    }
+
+    #[test]
+    pub fn test_parse_unicode_names() {
+        let config = Config::parse(
+            "
+hosts:
+  - 127.0.0.1: Bücher.Local.
+  - '::1':
+    - bücher.local
+    - faß.local
+blocklist:
+  - Werbung.Bücher.de
+capture: /tmp/rdns.pcap
+",
+        )
+        .unwrap();
+
+        let records = &config.hosts["xn--bcher-kva.local"];
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].rtype, QType::A);
+        assert_eq!(records[1].rtype, QType::AAAA);
+        assert_eq!(records[1].name, "xn--bcher-kva.local");
+        assert!(config.hosts.contains_key("xn--fa-hia.local"));
+        assert!(config.blocklist.contains("werbung.xn--bcher-kva.de"));
+        assert_eq!(config.capture, Some("/tmp/rdns.pcap".into()));
+    }
+
+    #[test]
+    pub fn test_parse_invalid() {
+        match Config::parse("hosts:\n  - 127.0.0.1: xn--a.local\n") {
+            Err(RdnsError::InvalidConfig(x)) => assert!(x.starts_with("hosts:")),
+            x => panic!("Unexpected result {:?}", x),
+        }
+
+        match Config::parse("hosts:\n  - 127.0.0.1: empty..label.local\n") {
+            Err(RdnsError::InvalidConfig(x)) => assert!(x.starts_with("hosts:")),
+            x => panic!("Unexpected result {:?}", x),
+        }
+
+        match Config::parse("hosts:\n  - localhost: local\n") {
+            Err(RdnsError::InvalidConfig(x)) => assert!(x.starts_with("hosts:")),
+            x => panic!("Unexpected result {:?}", x),
+        }
+
+        match Config::parse("capture:\n  - rdns.pcap\n") {
+            Err(RdnsError::InvalidConfig(x)) => assert!(x.starts_with("capture:")),
+            x => panic!("Unexpected result {:?}", x),
+        }
+
+        match Config::parse("blocklist: example.com\n") {
+            Err(RdnsError::InvalidConfig(x)) => assert!(x.starts_with("blocklist:")),
+            x => panic!("Unexpected result {:?}", x),
+        }
+
+        let invalid = [
+            ("listen-address: 127.0.0.1\n", "listen-address:"),
+            ("listen-address: []\n", "listen-address:"),
+            ("servers:\n  - dns.google\n", "servers:"),
+            ("cache:\n  max-entries: 0\n", "cache.max-entries:"),
+            ("cache:\n  max-bytes: 1MB\n", "cache.max-bytes:"),
+            ("cache:\n  stale-window-s: -1\n", "cache.stale-window-s:"),
+            ("cache:\n  max-ttl-s: -1\n", "cache.max-ttl-s:"),
+            ("cache:\n  min-ttl-s: 90000\n", "cache.min-ttl-s:"),
+            (
+                "cache:\n  ttl-overrides:\n    - example.com\n",
+                "cache.ttl-overrides:",
+            ),
+            (
+                "cache:\n  ttl-overrides:\n    example.com: 1m\n",
+                "cache.ttl-overrides:",
+            ),
+            (
+                "cache:\n  prefetch-percent: 101\n",
+                "cache.prefetch-percent:",
+            ),
+            ("cache:\n  max-size: 1\n", "cache.max-size:"),
+            ("upstream: 500\n", "upstream:"),
+            ("upstream:\n  strategy: random\n", "upstream.strategy:"),
+            ("upstream:\n  timeout-ms: -1\n", "upstream.timeout-ms:"),
+            ("upstream:\n  deadline-ms: 100\n", "upstream.timeout-ms:"),
+            ("upstream:\n  retries: 3\n", "upstream.retries:"),
+            ("recursion: true\n", "recursion:"),
+            ("recursion:\n  enabled: 1\n", "recursion.enabled:"),
+            ("recursion:\n  root-hints: []\n", "recursion.root-hints:"),
+            (
+                "recursion:\n  root-hints:\n    - a.root-servers.net\n",
+                "recursion.root-hints:",
+            ),
+            ("recursion:\n  max-queries: 0\n", "recursion.max-queries:"),
+            ("log-level: verbose\n", "log-level:"),
+            ("max-concurrent-queries: 0\n", "max-concurrent-queries:"),
+            ("load_hosts_file: yes please\n", "load_hosts_file:"),
+            ("hosts_file: 42\n", "hosts_file:"),
+            ("listen_address: 127.0.0.1:53\n", "listen_address:"),
+        ];
+        for (content, key) in invalid.iter() {
+            match Config::parse(content) {
+                Err(RdnsError::InvalidConfig(x)) => assert!(x.starts_with(key), "{}", x),
+                x => panic!("Unexpected result {:?}", x),
+            }
+        }
+    }
+}
@@ -1,3 +1,4 @@
+use rdns_proto::Rcode;
 use std::error::Error;
 use std::fmt;
 
@@ -6,14 +7,41 @@ pub(crate) type Result<T> = std::result::Result<T, RdnsError>;
 #[derive(Debug)]
 pub enum RdnsError {
     IoError(std::io::Error),
-    Todo,
+    /// The configuration file is invalid, contains the offending key
+    InvalidConfig(String),
+    /// The command line arguments are invalid
+    InvalidArgument(String),
+    /// The cache snapshot can not be restored
+    InvalidSnapshot(String),
+    /// The query is malformed
+    FormatError(String),
+    /// The query uses a feature the daemon does not support
+    NotImplemented(String),
+}
+
+impl RdnsError {
+    /// Rcode of the answer to a query that failed with this error
+    pub fn rcode(&self) -> Rcode {
+        match self {
+            RdnsError::FormatError(_) => Rcode::FormatError,
+            RdnsError::NotImplemented(_) => Rcode::NotImplemented,
+            _ => Rcode::ServerFailure,
+        }
+    }
 }
 
 impl Error for RdnsError {}
 
 impl fmt::Display for RdnsError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{:?}", self)
+        match self {
+            RdnsError::IoError(x) => write!(f, "{}", x),
+            RdnsError::InvalidConfig(x) => write!(f, "Invalid config, {}", x),
+            RdnsError::InvalidArgument(x) => write!(f, "Invalid argument, {}", x),
+            RdnsError::InvalidSnapshot(x) => write!(f, "Invalid snapshot, {}", x),
+            RdnsError::FormatError(x) => write!(f, "Malformed query, {}", x),
+            RdnsError::NotImplemented(x) => write!(f, "Not implemented, {}", x),
+        }
     }
 }
 
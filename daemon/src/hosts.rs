@@ -0,0 +1,178 @@
+use crate::error::*;
+
+use log::debug;
+use rdns_proto::*;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+/// TTL of the records created from the `hosts` section and the hosts file
+pub const HOSTS_TTL: u32 = 3600;
+
+/// Modification time and length, a change of either triggers a reload
+type Stamp = (SystemTime, u64);
+
+/// Hosts file of the system that is reloaded when it changes
+pub struct HostsFile {
+    path: PathBuf,
+    /// `None` until the first load, `Some(None)` while the file is missing
+    loaded: Option<Option<Stamp>>,
+}
+
+impl HostsFile {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Self {
+            path: path.into(),
+            loaded: None,
+        }
+    }
+
+    /// Records of the file if it changed since the last call, a missing
+    /// file has no records
+    pub fn reload(&mut self) -> Result<Option<HashMap<String, Vec<ResourceRecord>>>> {
+        let stamp = std::fs::metadata(&self.path)
+            .and_then(|x| Ok((x.modified()?, x.len())))
+            .ok();
+        if self.loaded == Some(stamp) {
+            return Ok(None);
+        }
+
+        let records = match stamp {
+            Some(_) => parse(&std::fs::read_to_string(&self.path)?),
+            None => HashMap::new(),
+        };
+        self.loaded = Some(stamp);
+        Ok(Some(records))
+    }
+}
+
+/// Parses the hosts file format, lines that can not be parsed are skipped
+pub fn parse(content: &str) -> HashMap<String, Vec<ResourceRecord>> {
+    let mut hosts: HashMap<String, Vec<ResourceRecord>> = HashMap::new();
+
+    for line in content.lines() {
+        let line = line.split('#').next().unwrap_or_default();
+        let mut fields = line.split_whitespace();
+        let address = match fields.next() {
+            Some(x) => x,
+            None => continue,
+        };
+
+        // link local addresses with a zone can not be served
+        let address = match address.parse::<IpAddr>() {
+            Ok(x) => x,
+            Err(_) => {
+                debug!("Skipping hosts entry {:?}", line);
+                continue;
+            }
+        };
+
+        for name in fields {
+            let name = match domain_to_ascii(name) {
+                Ok(x) => x.trim_end_matches('.').to_string(),
+                Err(_) => {
+                    debug!("Skipping invalid hosts name {:?}", name);
+                    continue;
+                }
+            };
+
+            let record = address_record(name.clone(), address);
+            let records = hosts.entry(name).or_default();
+            if !records.contains(&record) {
+                records.push(record);
+            }
+        }
+    }
+
+    hosts
+}
+
+pub fn address_record(name: String, address: IpAddr) -> ResourceRecord {
+    let (rtype, rdata) = match address {
+        IpAddr::V4(x) => (QType::A, x.octets().to_vec()),
+        IpAddr::V6(x) => (QType::AAAA, x.octets().to_vec()),
+    };
+
+    ResourceRecord {
+        name,
+        rtype,
+        rclass: QClass::IN,
+        cache_flush: false,
+        ttl: HOSTS_TTL,
+        rdlength: rdata.len() as u16,
+        rdata,
+    }
+}
+
+/// Address of an A or AAAA record
+pub fn record_address(record: &ResourceRecord) -> Option<IpAddr> {
+    match (record.rtype, record.rdata.len()) {
+        (QType::A, 4) => {
+            let mut octets = [0u8; 4];
+            octets.copy_from_slice(&record.rdata);
+            Some(IpAddr::from(octets))
+        }
+        (QType::AAAA, 16) => {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(&record.rdata);
+            Some(IpAddr::from(octets))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_parse() {
+        let hosts = parse(
+            "
+# The following lines are desirable for IPv4 capable hosts
+127.0.0.1       localhost localhost.localdomain
+127.0.1.1\tDevBox.Local devbox # comment
+::1             localhost ip6-localhost
+fe80::1%lo0     localhost
+not-an-address  invalid.local
+192.168.0.11    empty..label.local
+192.168.0.10    bücher.local
+192.168.0.10    bücher.local
+",
+        );
+
+        assert_eq!(hosts["localhost"].len(), 2);
+        assert_eq!(hosts["localhost"][0].rdata, vec![127, 0, 0, 1]);
+        assert_eq!(hosts["localhost"][1].rtype, QType::AAAA);
+        assert_eq!(hosts["devbox.local"][0].rdata, vec![127, 0, 1, 1]);
+        assert!(hosts.contains_key("devbox"));
+        assert!(hosts.contains_key("ip6-localhost"));
+        assert!(!hosts.contains_key("invalid.local"));
+        assert!(!hosts.contains_key("empty..label.local"));
+        assert!(!hosts.contains_key("comment"));
+        assert_eq!(hosts["xn--bcher-kva.local"].len(), 1);
+    }
+
+    #[test]
+    pub fn test_reload() {
+        let path = std::env::temp_dir().join(format!("rdns-hosts-{}", std::process::id()));
+        let mut hosts_file = HostsFile::new(&path);
+
+        // missing files are empty, but only reported once
+        assert!(hosts_file.reload().unwrap().unwrap().is_empty());
+        assert!(hosts_file.reload().unwrap().is_none());
+
+        std::fs::write(&path, "10.0.0.1 printer.local\n").unwrap();
+        let hosts = hosts_file.reload().unwrap().unwrap();
+        assert_eq!(hosts["printer.local"][0].rdata, vec![10, 0, 0, 1]);
+        assert!(hosts_file.reload().unwrap().is_none());
+
+        std::fs::write(&path, "10.0.0.1 printer.local\n10.0.0.2 nas.local\n").unwrap();
+        let hosts = hosts_file.reload().unwrap().unwrap();
+        assert_eq!(hosts.len(), 2);
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(hosts_file.reload().unwrap().unwrap().is_empty());
+    }
+}
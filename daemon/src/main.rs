@@ -1,33 +1,514 @@
+mod cache;
+mod clock;
+mod config;
 mod error;
+mod hosts;
+mod resolver;
 mod server;
+mod snapshot;
+mod upstream;
 
-use crate::server::ServerHandler;
+use crate::clock::{Clock, RealClock};
+use crate::config::Config;
+use crate::error::RdnsError;
+use crate::hosts::HostsFile;
+use crate::server::{Outgoing, RequestKey, ServerHandler, Via, HEADER_LENGTH};
 
+use async_std::channel::{self, Receiver, Sender};
+use async_std::future;
+use async_std::io;
 use async_std::net::UdpSocket;
-use rdns_proto::DNS;
+use async_std::sync::{Arc, Mutex};
+use async_std::task;
+use log::{debug, error, info, warn};
+use rdns_proto::{CapturedMessage, PcapWriter, Transport, DNS, MAX_UDP_SIZE};
 use std::collections::HashMap;
+use std::fs::File;
+use std::future::Future;
+use std::io::BufWriter;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::pin::Pin;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+type Capture = Option<PcapWriter<BufWriter<File>>>;
+
+/// Interval the hosts file is checked for changes
+const HOSTS_FILE_INTERVAL: Duration = Duration::from_secs(5);
+/// Interval expired records are removed from the cache
+const CACHE_SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+/// Interval the cache snapshot is written
+const SNAPSHOT_INTERVAL: Duration = Duration::from_secs(300);
+/// Interval the buffered capture is written to the file
+const CAPTURE_FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+/// Upstream answers may be larger than 512 bytes if EDNS is used
+const UPSTREAM_BUFFER_SIZE: usize = 4096;
+
+const USAGE: &str = "usage: rdns_daemon [--config <path>]";
+
+/// State shared by all tasks
+struct Daemon {
+    config: Config,
+    clock: Arc<dyn Clock>,
+    handler: Mutex<ServerHandler>,
+    sockets: Vec<UdpSocket>,
+    capture: Mutex<Capture>,
+    /// Held while the snapshot is saved, as all saves share the temporary
+    /// file
+    saving: Mutex<()>,
+    /// Tasks of queries waiting for their answer, woken when it is sent
+    waiting: Mutex<HashMap<RequestKey, Arc<Sender<()>>>>,
+    permits: Permits,
+    /// Wakes the task that handles retries and deadlines
+    wake: (Sender<()>, Receiver<()>),
+}
+
+/// Limits the number of queries handled at once, the listening sockets
+/// are not read while all permits are taken
+struct Permits(Sender<()>, Receiver<()>);
+
+impl Permits {
+    fn new(count: usize) -> Self {
+        let (sender, receiver) = channel::bounded(count);
+        Self(sender, receiver)
+    }
+
+    async fn acquire(&self) {
+        let _ = self.0.send(()).await;
+    }
+
+    async fn release(&self) {
+        let _ = self.1.recv().await;
+    }
+}
 
 #[async_std::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    loggify::Loggify::init_with_level(log::Level::Debug).unwrap();
+    let config = match config_path(std::env::args().skip(1))? {
+        Some(path) => Config::load(&path)?,
+        None => Config::default(),
+    };
+    loggify::Loggify::init_with_level(config.log_level)?;
+
+    let capture = match &config.capture {
+        Some(path) => Some(
+            File::create(path)
+                .map_err(|e| e.into())
+                .and_then(|x| PcapWriter::new(BufWriter::new(x)))
+                .map_err(|e| RdnsError::InvalidConfig(format!("capture: {}", e)))?,
+        ),
+        None => None,
+    };
+    let clock: Arc<dyn Clock> = Arc::new(RealClock::new());
+    let mut server_handler = ServerHandler::with_clock(&config, clock.clone());
+    if let Some(path) = &config.cache.snapshot {
+        match snapshot::load(&mut server_handler.cache, path, clock.system_time()) {
+            Ok(x) => info!("Restored {} RRsets from {}", x, path),
+            Err(e) => warn!("Restoring the cache from {} failed: {}", path, e),
+        }
+    }
+
+    if config.recursion.enabled {
+        info!(
+            "Resolving recursively from {} root servers",
+            config.recursion.root_hints.len()
+        );
+    }
+
+    let mut sockets = Vec::with_capacity(config.listen.len());
+    for addr in config.listen.iter() {
+        let socket = UdpSocket::bind(addr)
+            .await
+            .map_err(|e| RdnsError::InvalidConfig(format!("listen-address: {}: {}", addr, e)))?;
+        info!("Listening on {}", addr);
+        sockets.push(socket);
+    }
+
+    let mut hosts_file = None;
+    if config.load_hosts_file {
+        let mut file = HostsFile::new(&config.hosts_file);
+        match file.reload() {
+            Ok(Some(x)) => server_handler.load_hosts_file(x),
+            Ok(None) => (),
+            Err(e) => warn!("Loading {} failed: {}", config.hosts_file, e),
+        }
+        hosts_file = Some(file);
+    }
+
+    let daemon = Arc::new(Daemon {
+        permits: Permits::new(config.max_concurrent_queries),
+        config,
+        clock,
+        handler: Mutex::new(server_handler),
+        sockets,
+        capture: Mutex::new(capture),
+        saving: Mutex::new(()),
+        waiting: Mutex::new(HashMap::new()),
+        wake: channel::bounded(1),
+    });
+
+    if let Some(x) = hosts_file {
+        task::spawn(watch_hosts_file(daemon.clone(), x));
+    }
+    task::spawn(sweep_cache(daemon.clone()));
+    if let Some(path) = daemon.config.cache.snapshot.clone() {
+        task::spawn(save_snapshots(daemon.clone(), path));
+    }
+    if daemon.config.capture.is_some() {
+        task::spawn(flush_captures(daemon.clone()));
+    }
+    for index in 0..daemon.sockets.len() {
+        task::spawn(receive(daemon.clone(), index));
+    }
+    task::spawn(retry(daemon.clone()));
+
+    let (shutdown, stopped) = channel::bounded(1);
+    ctrlc::set_handler(move || {
+        let _ = shutdown.try_send(());
+    })?;
+    let _ = stopped.recv().await;
+
+    info!("Shutting down");
+    if let Some(path) = &daemon.config.cache.snapshot {
+        save_snapshot(&daemon, path).await;
+    }
+    flush_capture(&daemon.capture).await;
+    Ok(())
+}
+
+/// Sends retries and SERVFAIL after deadlines of pending requests
+async fn retry(daemon: Arc<Daemon>) {
+    loop {
+        let next_timeout = daemon.handler.lock().await.next_timeout();
+        match next_timeout {
+            Some(x) => {
+                let timeout = x.saturating_duration_since(daemon.clock.instant());
+                let _ = future::timeout(timeout, daemon.wake.1.recv()).await;
+            }
+            None => {
+                let _ = daemon.wake.1.recv().await;
+            }
+        }
+        flush(&daemon).await;
+    }
+}
+
+/// Handles every datagram of the listening socket at the index in its own
+/// task
+async fn receive(daemon: Arc<Daemon>, index: usize) {
+    // queries up to the advertised payload size are accepted
+    let mut buf = vec![0u8; MAX_UDP_SIZE];
+    loop {
+        daemon.permits.acquire().await;
+        match daemon.sockets[index].recv_from(&mut buf).await {
+            Ok((length, addr)) => {
+                let datagram = buf[..length].to_vec();
+                let daemon = daemon.clone();
+                task::spawn(async move {
+                    handle_query(&daemon, index, addr, datagram).await;
+                    daemon.permits.release().await;
+                });
+            }
+            Err(e) => {
+                error!("Receiving failed: {}", e);
+                daemon.permits.release().await;
+            }
+        }
+    }
+}
+
+/// Forwards the query and waits until it is answered, so that the permit
+/// is held for the whole request
+async fn handle_query(daemon: &Arc<Daemon>, index: usize, addr: SocketAddr, datagram: Vec<u8>) {
+    if let Ok(local_addr) = daemon.sockets[index].local_addr() {
+        record(&daemon.capture, addr, local_addr, &datagram).await;
+    }
+    if datagram.len() < HEADER_LENGTH {
+        debug!("Dropping datagram of {} without header", addr);
+        return;
+    }
+
+    let key = (addr, u16::from_be_bytes([datagram[0], datagram[1]]));
+    let (done, answered) = channel::bounded(1);
+    let done = Arc::new(done);
+    {
+        // a retransmission is answered together with the query it repeats
+        let mut waiting = daemon.waiting.lock().await;
+        if waiting.contains_key(&key) {
+            debug!("Dropping retransmitted query of {}", addr);
+            return;
+        }
+        waiting.insert(key, done.clone());
+    }
+    daemon
+        .handler
+        .lock()
+        .await
+        .read_datagram(index, addr, datagram);
+    flush(daemon).await;
+
+    // the deadline of the request ends the wait, the timeout only guards
+    // against requests that are never answered
+    if daemon
+        .handler
+        .lock()
+        .await
+        .pending_requests
+        .contains_key(&key)
+    {
+        let timeout = daemon.config.upstream.deadline * 2;
+        let _ = future::timeout(timeout, answered.recv()).await;
+    }
+    // the query may have been answered and repeated in the meantime, the
+    // new task waits for its own answer
+    let mut waiting = daemon.waiting.lock().await;
+    if waiting.get(&key).is_some_and(|x| Arc::ptr_eq(x, &done)) {
+        waiting.remove(&key);
+    }
+}
+
+/// Sends everything that is due, failed upstream queries may produce new
+/// queries or answers, so repeat until everything is sent
+async fn flush(daemon: &Arc<Daemon>) {
+    loop {
+        let outgoing = daemon
+            .handler
+            .lock()
+            .await
+            .write(&daemon.config.servers, daemon.clock.instant());
+        let outgoing = match outgoing {
+            Ok(x) if !x.is_empty() => x,
+            Ok(_) => return,
+            Err(e) => {
+                error!("Preparing answers failed: {}", e);
+                return;
+            }
+        };
+
+        for message in outgoing {
+            if let Err(e) = send(daemon, &message).await {
+                error!("Sending to {} failed: {}", message.addr, e);
+                daemon.handler.lock().await.upstream_failed(&message, &e);
+            }
+
+            match message.via {
+                Via::Listener(_) => {
+                    if let Some(done) = daemon.waiting.lock().await.remove(&message.request) {
+                        let _ = done.try_send(());
+                    }
+                }
+                // the retry task has to know about the new timeout
+                Via::Upstream(_) => {
+                    let _ = daemon.wake.0.try_send(());
+                }
+            }
+        }
+    }
+}
 
-    let mut server_handler = ServerHandler::new(HashMap::new());
+/// Sends answers through their listening socket and queries from a new
+/// socket that waits for the answer
+async fn send(daemon: &Arc<Daemon>, message: &Outgoing) -> io::Result<()> {
+    match message.via {
+        Via::Listener(index) => {
+            let socket = &daemon.sockets[index];
+            record(
+                &daemon.capture,
+                socket.local_addr()?,
+                message.addr,
+                &message.message,
+            )
+            .await;
+            socket.send_to(&message.message, &message.addr).await?;
+        }
+        Via::Upstream(id) => {
+            let unspecified = match message.addr.ip() {
+                IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+                IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+            };
+            // the system picks a random port, connecting filters datagrams of
+            // other sources
+            let socket = UdpSocket::bind(SocketAddr::new(unspecified, 0)).await?;
+            socket.connect(message.addr).await?;
+            record(
+                &daemon.capture,
+                socket.local_addr()?,
+                message.addr,
+                &message.message,
+            )
+            .await;
+            socket.send(&message.message).await?;
+            task::spawn(receive_upstream(daemon.clone(), socket, id, message.addr));
+        }
+    }
+    Ok(())
+}
+
+/// Path given with `--config`, `None` if there is none
+fn config_path<I: Iterator<Item = String>>(mut args: I) -> Result<Option<String>, RdnsError> {
+    let mut path = None;
+    while let Some(arg) = args.next() {
+        if arg == "--config" {
+            path = Some(args.next().ok_or_else(|| {
+                RdnsError::InvalidArgument(format!("--config expects a path, {}", USAGE))
+            })?);
+        } else if let Some(x) = arg.strip_prefix("--config=") {
+            path = Some(x.to_string());
+        } else {
+            return Err(RdnsError::InvalidArgument(format!(
+                "unknown argument {}, {}",
+                arg, USAGE
+            )));
+        }
+    }
+    Ok(path)
+}
+
+/// Handles the first datagram with the ID of the query
+///
+/// Late answers are accepted until the deadline of the request, so the
+/// socket waits that long. Boxed, as the task is spawned by `flush` which it
+/// calls itself.
+fn receive_upstream(
+    daemon: Arc<Daemon>,
+    socket: UdpSocket,
+    id: u16,
+    upstream: SocketAddr,
+) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+    Box::pin(async move {
+        let mut buf = vec![0u8; UPSTREAM_BUFFER_SIZE];
+        let length = loop {
+            let timeout = daemon.config.upstream.deadline;
+            let length = match io::timeout(timeout, socket.recv(&mut buf)).await {
+                Ok(x) => x,
+                Err(_) => return,
+            };
+            if buf[..length].starts_with(&id.to_be_bytes()) {
+                break length;
+            }
+        };
+
+        if let Ok(local_addr) = socket.local_addr() {
+            record(&daemon.capture, upstream, local_addr, &buf[..length]).await;
+        }
+        match DNS::parse(buf[..length].to_vec()) {
+            Ok(dns) => {
+                daemon
+                    .handler
+                    .lock()
+                    .await
+                    .read_response(upstream, dns, daemon.clock.instant())
+            }
+            Err(e) => warn!("Invalid answer of {}: {:?}", upstream, e),
+        }
+        flush(&daemon).await;
+    })
+}
 
-    let socket = UdpSocket::bind("127.0.0.1:1337").await?;
-    let mut buf = vec![0u8; 512];
+/// Replaces the records of the hosts file whenever it changes
+async fn watch_hosts_file(daemon: Arc<Daemon>, mut hosts_file: HostsFile) {
+    loop {
+        task::sleep(HOSTS_FILE_INTERVAL).await;
+        match hosts_file.reload() {
+            Ok(Some(records)) => {
+                info!("Reloaded the hosts file");
+                daemon.handler.lock().await.load_hosts_file(records);
+            }
+            Ok(None) => (),
+            Err(e) => warn!("Reloading the hosts file failed: {}", e),
+        }
+    }
+}
 
+/// Removes expired records from the cache, lookups only remove the ones
+/// they come across
+async fn sweep_cache(daemon: Arc<Daemon>) {
     loop {
-        let (num_recv, addr) = socket.recv_from(&mut buf).await?;
-        let dns = DNS::parse(buf[..num_recv].to_vec()).map_err(|e| {
-            dbg!(e);
-            error::RdnsError::Todo
-        })?;
-        dbg!(&dns);
-        server_handler.read(addr, dns)?;
-
-        let (response, addrs) = server_handler.write(vec!["8.8.8.8".into(), "8.8.4.4".into()])?;
-        for addr in addrs {
-            socket.send_to(&response, &addr).await?;
+        task::sleep(CACHE_SWEEP_INTERVAL).await;
+        let mut handler = daemon.handler.lock().await;
+        if let Err(e) = handler.validate_ttl() {
+            warn!("Removing expired records failed: {}", e);
         }
+
+        let cache = &handler.cache;
+        debug!(
+            "Cache holds {} RRsets in about {} bytes, {} hits, {} misses, {} stale hits, {} prefetches, {} evictions, {} expirations",
+            cache.len(),
+            cache.bytes(),
+            cache.stats.hits,
+            cache.stats.misses,
+            cache.stats.stale_hits,
+            cache.stats.prefetches,
+            cache.stats.evictions,
+            cache.stats.expirations
+        );
+    }
+}
+
+/// Writes the cache snapshot in the interval
+async fn save_snapshots(daemon: Arc<Daemon>, path: String) {
+    loop {
+        task::sleep(SNAPSHOT_INTERVAL).await;
+        save_snapshot(&daemon, &path).await;
+    }
+}
+
+/// Writes the snapshot to memory, so that the file is written without
+/// holding the lock
+async fn save_snapshot(daemon: &Daemon, path: &str) {
+    let _saving = daemon.saving.lock().await;
+    let mut content = Vec::new();
+    let count = snapshot::write(&daemon.handler.lock().await.cache, &mut content);
+    let result = count
+        .map_err(|e| e.into())
+        .and_then(|x| snapshot::save(path, &content).map(|_| x));
+    match result {
+        Ok(x) => debug!("Saved {} RRsets to {}", x, path),
+        Err(e) => error!("Saving the cache to {} failed: {}", path, e),
+    }
+}
+
+/// Appends the datagram to the capture, on failure recording is stopped
+async fn record(
+    capture: &Mutex<Capture>,
+    source: SocketAddr,
+    destination: SocketAddr,
+    payload: &[u8],
+) {
+    let mut capture = capture.lock().await;
+    let writer = match capture.as_mut() {
+        Some(x) => x,
+        None => return,
+    };
+
+    let message = CapturedMessage {
+        timestamp: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default(),
+        source,
+        destination,
+        transport: Transport::Udp,
+        payload: payload.to_vec(),
+    };
+    if let Err(e) = writer.write(&message) {
+        error!("Recording the capture failed, stopping it: {}", e);
+        *capture = None;
+    }
+}
+
+/// Flushes the capture in the interval, so that it can be followed while
+/// the daemon runs
+async fn flush_captures(daemon: Arc<Daemon>) {
+    loop {
+        task::sleep(CAPTURE_FLUSH_INTERVAL).await;
+        flush_capture(&daemon.capture).await;
+    }
+}
+
+/// Writes the buffered part of the capture, on failure recording is stopped
+async fn flush_capture(capture: &Mutex<Capture>) {
+    let mut capture = capture.lock().await;
+    if let Some(Err(e)) = capture.as_mut().map(|x| x.flush()) {
+        error!("Recording the capture failed, stopping it: {}", e);
+        *capture = None;
     }
 }
@@ -0,0 +1,871 @@
+use crate::cache::{normalize, Cache, CacheKey, MAX_CNAME_CHAIN};
+use crate::config::RecursionConfig;
+use crate::hosts::record_address;
+
+use log::debug;
+use rdns_proto::*;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::time::SystemTime;
+
+/// IPv4 addresses of the root servers a to m
+pub const ROOT_SERVERS: [Ipv4Addr; 13] = [
+    Ipv4Addr::new(198, 41, 0, 4),
+    Ipv4Addr::new(170, 247, 170, 2),
+    Ipv4Addr::new(192, 33, 4, 12),
+    Ipv4Addr::new(199, 7, 91, 13),
+    Ipv4Addr::new(192, 203, 230, 10),
+    Ipv4Addr::new(192, 5, 5, 241),
+    Ipv4Addr::new(192, 112, 36, 4),
+    Ipv4Addr::new(198, 97, 190, 53),
+    Ipv4Addr::new(192, 36, 148, 17),
+    Ipv4Addr::new(192, 58, 128, 30),
+    Ipv4Addr::new(193, 0, 14, 129),
+    Ipv4Addr::new(199, 7, 83, 42),
+    Ipv4Addr::new(202, 12, 27, 33),
+];
+/// Port of nameservers learned from referrals
+const DNS_PORT: u16 = 53;
+/// Names resolved at once, the question and the nameservers without glue
+/// it depends on
+const MAX_NESTING: usize = 4;
+
+/// What the caller has to do next for a resolution
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Step {
+    /// Send the question to the nameserver, its response is passed to
+    /// `response`, if it does not answer `next` is called again
+    Query(SocketAddr, Question),
+    /// The resolution finished with the response to the question
+    Answer(DNS),
+    /// The resolution failed
+    Failure(ExtendedError),
+}
+
+/// Name that is resolved by following referrals from the closest known zone
+#[derive(Clone, Debug)]
+struct Task {
+    /// Question the task was created for
+    origin: Question,
+    /// Question after following the chain
+    question: Question,
+    /// CNAME and DNAME records that led from the origin to the question
+    chain: Vec<ResourceRecord>,
+    /// Zone whose nameservers are queried, empty for the root
+    zone: String,
+    /// Addresses of nameservers of the zone that were not tried yet
+    servers: Vec<SocketAddr>,
+    /// Nameservers of the zone whose addresses are not known yet
+    unresolved: Vec<String>,
+    /// The zone was looked up since the question changed
+    started: bool,
+    referrals: u32,
+}
+
+impl Task {
+    fn new(question: Question) -> Self {
+        Self {
+            origin: question.clone(),
+            question,
+            chain: Vec::new(),
+            zone: String::new(),
+            servers: Vec::new(),
+            unresolved: Vec::new(),
+            started: false,
+            referrals: 0,
+        }
+    }
+}
+
+/// What a response of a nameserver means for a task
+enum Outcome {
+    /// Final rcode, records and authorities of the task
+    Answer(Rcode, Vec<ResourceRecord>, Vec<ResourceRecord>),
+    /// The records alias the question to a name the server is not
+    /// authoritative for
+    Alias(Vec<ResourceRecord>, String),
+    /// The zone is delegated to the nameservers, together with their glue
+    Referral(String, Vec<ResourceRecord>, Vec<ResourceRecord>),
+    /// The server can not be used for the question, the next one is tried
+    Lame(&'static str),
+}
+
+/// Iterative resolution of one question (RFC 1034 5.3.3)
+///
+/// Resolutions do no I/O, `next` tells the caller which nameserver to query
+/// and `response` takes its answer. Delegations and the addresses of
+/// nameservers are cached, so that later resolutions start at the closest
+/// known zone instead of the root.
+pub struct Resolution {
+    /// The first task resolves the question of the requester, the following
+    /// ones the addresses of nameservers without glue
+    tasks: Vec<Task>,
+    /// Queries sent for all tasks
+    queries: u32,
+    /// Result of the first task once it finished
+    result: Option<Step>,
+}
+
+impl Resolution {
+    pub fn new(question: &Question) -> Self {
+        Self {
+            tasks: vec![Task::new(question.clone())],
+            queries: 0,
+            result: None,
+        }
+    }
+
+    /// Next step of the resolution at `now`, after a `Query` either its
+    /// response is passed to `response`, or the nameserver is skipped by
+    /// calling `next` again
+    pub fn next(&mut self, config: &RecursionConfig, cache: &Cache, now: SystemTime) -> Step {
+        loop {
+            if let Some(x) = self.result.take() {
+                return x;
+            }
+
+            let nesting = self.tasks.len();
+            let task = match self.tasks.last_mut() {
+                Some(x) => x,
+                None => return failure(ExtendedErrorCode::Other, "resolution finished already"),
+            };
+            if !task.started {
+                task.started = true;
+                closest_zone(task, config, cache, now);
+            }
+
+            if task.servers.is_empty() {
+                match task.unresolved.pop() {
+                    Some(name) if nesting < MAX_NESTING => {
+                        if self.tasks.iter().all(|x| x.question.qname != name) {
+                            debug!("Resolving the address of nameserver {}", name);
+                            self.tasks.push(Task::new(Question {
+                                qname: name,
+                                qtype: QType::A,
+                                qclass: QClass::IN,
+                                unicast_response: false,
+                            }));
+                        }
+                    }
+                    Some(_) => (),
+                    None => {
+                        let message = format!("no nameserver of {:?} answered", task.zone);
+                        self.abort(ExtendedErrorCode::NoReachableAuthority, &message);
+                    }
+                }
+                continue;
+            }
+
+            if self.queries >= config.max_queries {
+                return failure(
+                    ExtendedErrorCode::Other,
+                    &format!("more than {} queries", config.max_queries),
+                );
+            }
+            self.queries += 1;
+            let server = task.servers.remove(0);
+            debug!(
+                "Asking {} of {:?} for {:?}",
+                server, task.zone, task.question
+            );
+            return Step::Query(server, task.question.clone());
+        }
+    }
+
+    /// Handles the response of the nameserver the last query was sent to
+    pub fn response(
+        &mut self,
+        response: &DNS,
+        config: &RecursionConfig,
+        cache: &mut Cache,
+        now: SystemTime,
+    ) {
+        let task = match self.tasks.last_mut() {
+            Some(x) => x,
+            None => return,
+        };
+
+        match classify(task, response) {
+            Outcome::Answer(rcode, records, authorities) => {
+                let mut answers = task.chain.clone();
+                answers.extend(records);
+                self.finish(rcode, answers, authorities, cache, now);
+            }
+            Outcome::Alias(records, name) => {
+                task.chain.extend(records);
+                let aliases = task.chain.iter().filter(|x| x.rtype == QType::CNAME);
+                if aliases.count() > MAX_CNAME_CHAIN {
+                    let message = format!("CNAME chain of {} is too long", task.origin.qname);
+                    self.abort(ExtendedErrorCode::Other, &message);
+                    return;
+                }
+
+                debug!("Following the alias of {} to {}", task.question.qname, name);
+                task.question.qname = name;
+                task.started = false;
+                task.servers.clear();
+                task.unresolved.clear();
+                if let Some(x) = cache.lookup(&task.question, now) {
+                    let mut answers = task.chain.clone();
+                    answers.extend(x.answers);
+                    self.finish(x.rcode, answers, x.authorities, cache, now);
+                }
+            }
+            Outcome::Referral(zone, nameservers, glue) => {
+                task.referrals += 1;
+                if task.referrals > config.max_referrals {
+                    let message = format!("more than {} referrals", config.max_referrals);
+                    self.abort(ExtendedErrorCode::Other, &message);
+                    return;
+                }
+
+                let names = nameservers
+                    .iter()
+                    .filter_map(|x| x.target())
+                    .map(|x| normalize(&x))
+                    .collect::<Vec<String>>();
+                let (servers, unresolved) = addresses(&names, &zone, &glue, cache, now);
+                if servers.is_empty() && unresolved.is_empty() {
+                    debug!("Referral to {:?} without usable nameservers", zone);
+                    return;
+                }
+
+                debug!("Referred from {:?} to {:?}", task.zone, zone);
+                cache.insert(&nameservers, now);
+                cache.insert(&glue, now);
+                task.zone = zone;
+                task.servers = servers;
+                task.unresolved = unresolved;
+            }
+            Outcome::Lame(reason) => debug!("Skipping nameserver of {:?}, {}", task.zone, reason),
+        }
+    }
+
+    /// Completes the last task, the addresses a nameserver task resolved
+    /// are added to the task that needs them
+    fn finish(
+        &mut self,
+        rcode: Rcode,
+        answers: Vec<ResourceRecord>,
+        authorities: Vec<ResourceRecord>,
+        cache: &mut Cache,
+        now: SystemTime,
+    ) {
+        let task = match self.tasks.pop() {
+            Some(x) => x,
+            None => return,
+        };
+        let response = DNS {
+            qr: 1,
+            ra: 1,
+            rcode,
+            questions: vec![task.origin],
+            resource_records: answers,
+            authorities,
+            ..DNS::default()
+        };
+        cache.insert_response(&response, now);
+
+        match self.tasks.last_mut() {
+            Some(parent) => {
+                let servers = response
+                    .resource_records
+                    .iter()
+                    .filter_map(record_address)
+                    .map(|x| SocketAddr::new(x, DNS_PORT));
+                parent.servers.extend(servers);
+            }
+            None => self.result = Some(Step::Answer(response)),
+        }
+    }
+
+    /// Gives up on the last task, a nameserver task only leaves its
+    /// nameserver out
+    fn abort(&mut self, code: ExtendedErrorCode, message: &str) {
+        self.tasks.pop();
+        if self.tasks.is_empty() {
+            self.result = Some(failure(code, message));
+        }
+    }
+}
+
+fn failure(code: ExtendedErrorCode, message: &str) -> Step {
+    Step::Failure(ExtendedError::new(code, message))
+}
+
+/// Starts the task at the closest zone of its question whose nameservers
+/// are cached, the root if there is none
+fn closest_zone(task: &mut Task, config: &RecursionConfig, cache: &Cache, now: SystemTime) {
+    let mut zone = normalize(&task.question.qname);
+    loop {
+        let key = CacheKey::new(&zone, QType::NS, task.question.qclass);
+        if let Some(records) = cache.records(&key, now) {
+            let names = records
+                .iter()
+                .filter_map(|x| x.target())
+                .map(|x| normalize(&x))
+                .collect::<Vec<String>>();
+            let (servers, unresolved) = addresses(&names, &zone, &[], cache, now);
+            if !servers.is_empty() || !unresolved.is_empty() {
+                task.zone = zone;
+                task.servers = servers;
+                task.unresolved = unresolved;
+                return;
+            }
+        }
+
+        if zone.is_empty() {
+            break;
+        }
+        zone = parent(&zone).to_string();
+    }
+
+    task.zone = String::new();
+    task.servers = config.root_hints.clone();
+    task.unresolved = Vec::new();
+}
+
+/// Addresses of the nameservers of the zone from the glue and the cache,
+/// and the nameservers whose addresses have to be resolved
+///
+/// Nameservers within the zone can only be reached with glue, resolving
+/// them would ask the zone itself.
+fn addresses(
+    names: &[String],
+    zone: &str,
+    glue: &[ResourceRecord],
+    cache: &Cache,
+    now: SystemTime,
+) -> (Vec<SocketAddr>, Vec<String>) {
+    let mut servers = Vec::new();
+    let mut unresolved = Vec::new();
+    for name in names {
+        let mut records = glue
+            .iter()
+            .filter(|x| normalize(&x.name) == *name)
+            .cloned()
+            .collect::<Vec<ResourceRecord>>();
+        for rtype in [QType::A, QType::AAAA].iter() {
+            let key = CacheKey::new(name, *rtype, QClass::IN);
+            records.extend(cache.records(&key, now).unwrap_or_default());
+        }
+
+        let before = servers.len();
+        for address in records.iter().filter_map(record_address) {
+            let server = SocketAddr::new(address, DNS_PORT);
+            if !servers.contains(&server) {
+                servers.push(server);
+            }
+        }
+        if servers.len() == before && !is_subdomain(name, zone) {
+            unresolved.push(name.clone());
+        }
+    }
+    (servers, unresolved)
+}
+
+/// Meaning of the response for the task, records outside of the zone of
+/// the nameserver are ignored (RFC 2181 5.4.1)
+fn classify(task: &Task, response: &DNS) -> Outcome {
+    if response.qr == 0 || response.tc == 1 {
+        return Outcome::Lame("truncated or no response");
+    }
+    if !matches!(response.rcode, Rcode::NoError | Rcode::NameError) {
+        return Outcome::Lame("failure rcode");
+    }
+
+    let question = &task.question;
+    let zone = &task.zone;
+    let answers = response
+        .resource_records
+        .iter()
+        .filter(|x| x.rclass == question.qclass && is_subdomain(&normalize(&x.name), zone))
+        .collect::<Vec<&ResourceRecord>>();
+
+    let mut name = normalize(&question.qname);
+    let mut chain = Vec::new();
+    for _ in 0..=MAX_CNAME_CHAIN {
+        let records = answers
+            .iter()
+            .filter(|x| normalize(&x.name) == name)
+            .filter(|x| x.rtype == question.qtype || question.qtype == QType::ANY)
+            .map(|x| (*x).clone())
+            .collect::<Vec<ResourceRecord>>();
+        if !records.is_empty() {
+            chain.extend(records);
+            return Outcome::Answer(Rcode::NoError, chain, Vec::new());
+        }
+
+        // the CNAME synthesized by the server is ignored, the DNAME is
+        // applied to the name instead (RFC 6672 3.4)
+        let dname = answers.iter().find(|x| {
+            let owner = normalize(&x.name);
+            x.rtype == QType::DNAME
+                && !owner.is_empty()
+                && name != owner
+                && is_subdomain(&name, &owner)
+        });
+        if let Some(record) = dname {
+            let target = match record.target() {
+                Some(x) => normalize(&x),
+                None => break,
+            };
+            let owner = normalize(&record.name);
+            let prefix = &name[..name.len() - owner.len()];
+            let synthesized = match target.is_empty() {
+                true => prefix.trim_end_matches('.').to_string(),
+                false => format!("{}{}", prefix, target),
+            };
+            chain.push((*record).clone());
+            chain.push(ResourceRecord::cname(name, &synthesized, record.ttl));
+            name = synthesized;
+            continue;
+        }
+
+        let cname = answers
+            .iter()
+            .filter(|x| x.rtype == QType::CNAME && normalize(&x.name) == name)
+            .find_map(|x| x.target().map(|target| (*x, target)));
+        if let Some((record, target)) = cname {
+            chain.push(record.clone());
+            name = normalize(&target);
+            continue;
+        }
+        break;
+    }
+
+    let soa = response
+        .authorities
+        .iter()
+        .filter(|x| x.rtype == QType::SOA && x.rclass == question.qclass)
+        .find(|x| is_subdomain(&name, &normalize(&x.name)) && is_subdomain(&x.name, zone))
+        .cloned()
+        .into_iter()
+        .collect::<Vec<ResourceRecord>>();
+    let negative = response.rcode == Rcode::NameError || !soa.is_empty();
+
+    if !chain.is_empty() {
+        if response.aa == 1 && negative && is_subdomain(&name, zone) {
+            return Outcome::Answer(response.rcode.clone(), chain, soa);
+        }
+        return Outcome::Alias(chain, name);
+    }
+
+    let nameservers = response
+        .authorities
+        .iter()
+        .filter(|x| x.rtype == QType::NS && x.rclass == question.qclass)
+        .collect::<Vec<&ResourceRecord>>();
+    if let Some(first) = nameservers.first().filter(|_| response.aa == 0) {
+        let child = normalize(&first.name);
+        if child == *zone || !is_subdomain(&child, zone) || !is_subdomain(&name, &child) {
+            return Outcome::Lame("referral that does not lead closer");
+        }
+
+        let nameservers = nameservers
+            .iter()
+            .filter(|x| normalize(&x.name) == child)
+            .map(|x| (*x).clone())
+            .collect::<Vec<ResourceRecord>>();
+        let names = nameservers
+            .iter()
+            .filter_map(|x| x.target())
+            .map(|x| normalize(&x))
+            .collect::<Vec<String>>();
+        let glue = response
+            .additionals
+            .iter()
+            .filter(|x| matches!(x.rtype, QType::A | QType::AAAA))
+            .filter(|x| names.contains(&normalize(&x.name)) && is_subdomain(&x.name, zone))
+            .cloned()
+            .collect();
+        return Outcome::Referral(child, nameservers, glue);
+    }
+
+    if negative || response.aa == 1 {
+        return Outcome::Answer(response.rcode.clone(), Vec::new(), soa);
+    }
+    Outcome::Lame("neither answer nor referral")
+}
+
+fn parent(name: &str) -> &str {
+    match name.find('.') {
+        Some(index) => &name[index + 1..],
+        None => "",
+    }
+}
+
+/// Authoritative stand-ins for the root, TLD and leaf zones of a small
+/// network, queried in-process instead of over sockets
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::cache::tests::{question, record, soa};
+    use crate::config::CacheConfig;
+    use std::collections::HashMap;
+
+    pub const ROOT: &str = "10.0.0.1:53";
+
+    /// Authoritative server of one zone, answers like a real one would
+    pub struct Authority {
+        zone: String,
+        records: Vec<ResourceRecord>,
+    }
+
+    impl Authority {
+        pub fn new(zone: &str, records: Vec<ResourceRecord>) -> Self {
+            Self {
+                zone: zone.into(),
+                records,
+            }
+        }
+
+        pub fn answer(&self, query: &DNS) -> DNS {
+            let question = &query.questions[0];
+            let name = normalize(&question.qname);
+            let mut response = DNS {
+                id: query.id,
+                qr: 1,
+                aa: 1,
+                questions: query.questions.clone(),
+                ..DNS::default()
+            };
+
+            // the closest delegation below the zone, if there is one
+            let cut = self
+                .records
+                .iter()
+                .filter(|x| x.rtype == QType::NS && x.name != self.zone)
+                .filter(|x| is_subdomain(&name, &x.name))
+                .max_by_key(|x| x.name.len());
+            if let Some(cut) = cut {
+                response.aa = 0;
+                response.authorities = self.select(&cut.name, QType::NS);
+                for target in response.authorities.iter().filter_map(|x| x.target()) {
+                    response.additionals.extend(self.select(&target, QType::A));
+                }
+                return response;
+            }
+
+            let dname = self.records.iter().find(|x| {
+                x.rtype == QType::DNAME && name != x.name && is_subdomain(&name, &x.name)
+            });
+            if let Some(dname) = dname {
+                let prefix = &name[..name.len() - dname.name.len()];
+                let target = format!("{}{}", prefix, dname.target().unwrap());
+                response.resource_records = vec![
+                    dname.clone(),
+                    ResourceRecord::cname(name, &target, dname.ttl),
+                ];
+                return response;
+            }
+
+            response.resource_records = self.select(&name, question.qtype);
+            if response.resource_records.is_empty() {
+                response.resource_records = self.select(&name, QType::CNAME);
+            }
+            if response.resource_records.is_empty() {
+                let exists = self.records.iter().any(|x| is_subdomain(&x.name, &name));
+                if !exists {
+                    response.rcode = Rcode::NameError;
+                }
+                response.authorities = vec![soa(&self.zone)];
+            }
+            response
+        }
+
+        fn select(&self, name: &str, rtype: QType) -> Vec<ResourceRecord> {
+            self.records
+                .iter()
+                .filter(|x| x.name == name && x.rtype == rtype)
+                .cloned()
+                .collect()
+        }
+    }
+
+    pub fn a(name: &str, address: &str) -> ResourceRecord {
+        let address: Ipv4Addr = address.parse().unwrap();
+        record(name, QType::A, 3600, address.octets().to_vec())
+    }
+
+    pub fn ns(zone: &str, target: &str) -> ResourceRecord {
+        ResourceRecord::ns(zone.into(), target, 86400)
+    }
+
+    /// Root, TLD and leaf zones by the address of their server
+    ///
+    /// `glueless.com` is served by a nameserver in `example.net`, the com
+    /// servers have no glue for it. `lame.com` is delegated to a server
+    /// that never answers.
+    pub fn network() -> HashMap<SocketAddr, Authority> {
+        let mut network = HashMap::new();
+        network.insert(
+            ROOT.parse().unwrap(),
+            Authority::new(
+                "",
+                vec![
+                    ns("com", "a.gtld.com"),
+                    a("a.gtld.com", "10.0.1.1"),
+                    ns("net", "a.gtld.net"),
+                    a("a.gtld.net", "10.0.2.1"),
+                ],
+            ),
+        );
+        network.insert(
+            "10.0.1.1:53".parse().unwrap(),
+            Authority::new(
+                "com",
+                vec![
+                    ns("example.com", "ns1.example.com"),
+                    a("ns1.example.com", "10.0.3.1"),
+                    ns("glueless.com", "ns2.example.net"),
+                    ns("lame.com", "ns.lame.com"),
+                    a("ns.lame.com", "10.0.9.9"),
+                ],
+            ),
+        );
+        network.insert(
+            "10.0.2.1:53".parse().unwrap(),
+            Authority::new(
+                "net",
+                vec![
+                    ns("example.net", "ns1.example.net"),
+                    a("ns1.example.net", "10.0.4.1"),
+                ],
+            ),
+        );
+        network.insert(
+            "10.0.3.1:53".parse().unwrap(),
+            Authority::new(
+                "example.com",
+                vec![
+                    ns("example.com", "ns1.example.com"),
+                    a("ns1.example.com", "10.0.3.1"),
+                    a("www.example.com", "192.0.2.1"),
+                    ResourceRecord::cname("alias.example.com".into(), "www.example.net", 300),
+                    ResourceRecord::cname("local.example.com".into(), "www.example.com", 300),
+                    ResourceRecord::cname("loop.example.com".into(), "loop.example.com", 300),
+                    ResourceRecord::dname("legacy.example.com".into(), "example.net", 300),
+                ],
+            ),
+        );
+        network.insert(
+            "10.0.4.1:53".parse().unwrap(),
+            Authority::new(
+                "example.net",
+                vec![
+                    ns("example.net", "ns1.example.net"),
+                    a("ns1.example.net", "10.0.4.1"),
+                    a("ns2.example.net", "10.0.4.2"),
+                    a("www.example.net", "192.0.2.2"),
+                ],
+            ),
+        );
+        network.insert(
+            "10.0.4.2:53".parse().unwrap(),
+            Authority::new("glueless.com", vec![a("www.glueless.com", "192.0.2.3")]),
+        );
+        network
+    }
+
+    pub fn recursion() -> RecursionConfig {
+        RecursionConfig {
+            enabled: true,
+            root_hints: vec![ROOT.parse().unwrap()],
+            ..RecursionConfig::default()
+        }
+    }
+
+    /// Runs the resolution against the network, servers that are not part
+    /// of it never answer
+    fn resolve(
+        config: &RecursionConfig,
+        cache: &mut Cache,
+        qname: &str,
+        qtype: QType,
+    ) -> (Step, Vec<SocketAddr>) {
+        let network = network();
+        let now = SystemTime::now();
+        let mut resolution = Resolution::new(&question(qname, qtype));
+        let mut queried = Vec::new();
+        loop {
+            match resolution.next(config, cache, now) {
+                Step::Query(addr, question) => {
+                    queried.push(addr);
+                    let query = DNS {
+                        questions: vec![question],
+                        ..DNS::default()
+                    };
+                    if let Some(authority) = network.get(&addr) {
+                        resolution.response(&authority.answer(&query), config, cache, now);
+                    }
+                }
+                x => return (x, queried),
+            }
+        }
+    }
+
+    fn answer(step: Step) -> DNS {
+        match step {
+            Step::Answer(x) => x,
+            x => panic!("Unexpected step {:?}", x),
+        }
+    }
+
+    fn addresses(response: &DNS) -> Vec<String> {
+        response
+            .resource_records
+            .iter()
+            .filter_map(record_address)
+            .map(|x| x.to_string())
+            .collect()
+    }
+
+    #[test]
+    pub fn test_referrals() {
+        let config = recursion();
+        let mut cache = Cache::new(&CacheConfig::default());
+
+        let (step, queried) = resolve(&config, &mut cache, "WWW.example.com", QType::A);
+        let response = answer(step);
+        assert_eq!(response.rcode, Rcode::NoError);
+        assert_eq!(response.questions[0].qname, "WWW.example.com");
+        assert_eq!(addresses(&response), vec!["192.0.2.1"]);
+        assert_eq!(
+            queried,
+            vec![
+                ROOT.parse().unwrap(),
+                "10.0.1.1:53".parse().unwrap(),
+                "10.0.3.1:53".parse().unwrap()
+            ]
+        );
+
+        // the delegation is cached, the next name starts at the leaf zone
+        let (step, queried) = resolve(&config, &mut cache, "nx.example.com", QType::A);
+        let response = answer(step);
+        assert_eq!(response.rcode, Rcode::NameError);
+        assert_eq!(response.authorities[0].rtype, QType::SOA);
+        assert_eq!(queried, vec!["10.0.3.1:53".parse().unwrap()]);
+
+        let (step, _) = resolve(&config, &mut cache, "www.example.com", QType::AAAA);
+        let response = answer(step);
+        assert_eq!(response.rcode, Rcode::NoError);
+        assert!(response.resource_records.is_empty());
+        assert_eq!(response.authorities[0].rtype, QType::SOA);
+
+        // the answers are cached as well
+        let cached = cache.lookup(&question("www.example.com", QType::A), SystemTime::now());
+        assert_eq!(cached.unwrap().answers.len(), 1);
+        assert!(cache
+            .lookup(&question("nx.example.com", QType::MX), SystemTime::now())
+            .is_some());
+    }
+
+    #[test]
+    pub fn test_aliases() {
+        let config = recursion();
+        let mut cache = Cache::new(&CacheConfig::default());
+
+        // the CNAME leads to another zone, which is resolved from the root
+        let (step, queried) = resolve(&config, &mut cache, "alias.example.com", QType::A);
+        let response = answer(step);
+        let types = response.resource_records.iter().map(|x| x.rtype);
+        assert_eq!(types.collect::<Vec<QType>>(), vec![QType::CNAME, QType::A]);
+        assert_eq!(addresses(&response), vec!["192.0.2.2"]);
+        assert_eq!(queried.len(), 6);
+
+        // within the zone, the server follows the CNAME itself
+        let (step, queried) = resolve(&config, &mut cache, "local.example.com", QType::A);
+        assert_eq!(addresses(&answer(step)), vec!["192.0.2.1"]);
+        assert_eq!(queried.len(), 2);
+
+        // the DNAME is applied to the name, the target is cached already
+        let (step, queried) = resolve(&config, &mut cache, "www.legacy.example.com", QType::A);
+        let response = answer(step);
+        let types = response.resource_records.iter().map(|x| x.rtype);
+        assert_eq!(
+            types.collect::<Vec<QType>>(),
+            vec![QType::DNAME, QType::CNAME, QType::A]
+        );
+        assert_eq!(
+            response.resource_records[1].target(),
+            Some("www.example.net".into())
+        );
+        assert_eq!(queried.len(), 1);
+
+        let (step, _) = resolve(&config, &mut cache, "loop.example.com", QType::A);
+        match step {
+            Step::Failure(x) => assert!(x.extra_text.contains("too long")),
+            x => panic!("Unexpected step {:?}", x),
+        }
+    }
+
+    #[test]
+    pub fn test_glueless() {
+        let config = recursion();
+        let mut cache = Cache::new(&CacheConfig::default());
+
+        let (step, queried) = resolve(&config, &mut cache, "www.glueless.com", QType::A);
+        assert_eq!(addresses(&answer(step)), vec!["192.0.2.3"]);
+        // root, com, then root, net and example.net for the nameserver
+        assert_eq!(queried.len(), 6);
+        assert_eq!(queried[5], "10.0.4.2:53".parse().unwrap());
+
+        let key = CacheKey::new("ns2.example.net", QType::A, QClass::IN);
+        assert!(cache.records(&key, SystemTime::now()).is_some());
+    }
+
+    #[test]
+    pub fn test_limits() {
+        let mut cache = Cache::new(&CacheConfig::default());
+
+        let config = RecursionConfig {
+            max_referrals: 1,
+            ..recursion()
+        };
+        let (step, _) = resolve(&config, &mut cache, "www.example.com", QType::A);
+        match step {
+            Step::Failure(x) => assert!(x.extra_text.contains("referrals")),
+            x => panic!("Unexpected step {:?}", x),
+        }
+
+        let config = RecursionConfig {
+            max_queries: 4,
+            ..recursion()
+        };
+        let (step, queried) = resolve(&config, &mut cache, "www.glueless.com", QType::A);
+        match step {
+            Step::Failure(x) => assert!(x.extra_text.contains("queries")),
+            x => panic!("Unexpected step {:?}", x),
+        }
+        assert_eq!(queried.len(), 4);
+    }
+
+    #[test]
+    pub fn test_unreachable() {
+        let mut cache = Cache::new(&CacheConfig::default());
+
+        // the first root server never answers
+        let config = RecursionConfig {
+            root_hints: vec!["10.0.0.99:53".parse().unwrap(), ROOT.parse().unwrap()],
+            ..recursion()
+        };
+        let (step, queried) = resolve(&config, &mut cache, "www.example.com", QType::A);
+        assert_eq!(addresses(&answer(step)), vec!["192.0.2.1"]);
+        assert_eq!(queried.len(), 4);
+
+        let (step, _) = resolve(&config, &mut cache, "www.lame.com", QType::A);
+        match step {
+            Step::Failure(x) => {
+                assert_eq!(x.info_code, ExtendedErrorCode::NoReachableAuthority);
+                assert!(x.extra_text.contains("lame.com"));
+            }
+            x => panic!("Unexpected step {:?}", x),
+        }
+    }
+
+    #[test]
+    pub fn test_parent() {
+        assert_eq!(parent("www.example.com"), "example.com");
+        assert_eq!(parent("com"), "");
+    }
+}
@@ -1,10 +1,20 @@
+use crate::cache::{Cache, CachedAnswer};
+use crate::clock::Clock;
+use crate::config::{Config, RecursionConfig, UpstreamConfig};
 use crate::error::*;
+use crate::hosts::record_address;
+use crate::resolver::{Resolution, Step};
+use crate::upstream::{Strategy, Upstreams};
 
 use log::debug;
 use rdns_proto::*;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::net::SocketAddr;
-use std::time::SystemTime;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Length of the DNS header, shorter datagrams are dropped
+pub const HEADER_LENGTH: usize = 12;
 
 #[derive(Clone, Debug, Hash, Eq, PartialEq)]
 pub enum RequestState {
@@ -13,158 +23,818 @@ pub enum RequestState {
     WaitingForExternalServer,
 }
 
+/// Address and query ID of the requester, identifies a request
+pub type RequestKey = (SocketAddr, u16);
+
 #[derive(Clone, Debug, Hash, Eq, PartialEq)]
 pub struct Request {
     pub requester: SocketAddr,
+    /// Index of the listening socket the query arrived at
+    pub listener: usize,
     pub state: RequestState,
+    /// The query of the requester, replaced by the response once it is ready
     pub dns: DNS,
+    /// Largest response the requester accepts, larger ones are truncated
+    pub max_size: usize,
+    /// Upstream servers in the order they are tried
+    pub order: Vec<SocketAddr>,
+    /// Number of upstream servers that did not fail yet
+    pub upstreams: usize,
+    /// Number of times the query was sent, a race sends it to every
+    /// upstream server at once
+    pub attempts: u32,
+    /// The query is sent to the next upstream server after this point,
+    /// `None` to send it immediately
+    pub retry_at: Option<Instant>,
+    /// Point after which the requester gets SERVFAIL
+    pub deadline: Option<Instant>,
+    /// Point after which a stale answer is sent, if the cache has one
+    pub stale_at: Option<Instant>,
+    /// The requester got a stale or prefetched answer already, the request
+    /// only refreshes the cache
+    pub answered: bool,
+}
+
+impl Request {
+    fn new(listener: usize, requester: SocketAddr, state: RequestState, dns: DNS) -> Self {
+        Self {
+            requester,
+            listener,
+            state,
+            max_size: dns.max_udp_size(),
+            dns,
+            order: Vec::new(),
+            upstreams: 0,
+            attempts: 0,
+            retry_at: None,
+            deadline: None,
+            stale_at: None,
+            answered: false,
+        }
+    }
+}
+
+/// Query sent to an upstream server, stored by its own ID
+#[derive(Clone, Debug, Hash, Eq, PartialEq)]
+pub struct UpstreamQuery {
+    pub request: RequestKey,
+    pub upstream: SocketAddr,
+    /// The answer has to repeat the question
+    pub question: Question,
+    /// Attempt of the request the query belongs to
+    pub attempt: u32,
+    pub sent: Instant,
+}
+
+/// How a datagram has to be sent
+#[derive(Copy, Clone, Debug, Hash, Eq, PartialEq)]
+pub enum Via {
+    /// Answer through the listening socket at the index
+    Listener(usize),
+    /// Query with the ID from a new socket, so that the source port is random
+    /// as well (RFC 5452 9.2)
+    Upstream(u16),
+}
+
+/// Datagram that has to be sent by the caller
+#[derive(Clone, Debug, Hash, Eq, PartialEq)]
+pub struct Outgoing {
+    /// Request the datagram belongs to
+    pub request: RequestKey,
+    pub via: Via,
+    pub addr: SocketAddr,
+    pub message: Vec<u8>,
 }
 
 pub struct ServerHandler {
-    pub pending_requests: HashMap<u16, Request>,
-    pub known_addresses: HashMap<String, Vec<ResourceRecord>>,
-    pub last_checked: SystemTime,
+    pub pending_requests: HashMap<RequestKey, Request>,
+    pub upstream_queries: HashMap<u16, UpstreamQuery>,
+    pub cache: Cache,
+    /// Records of the `hosts` section and the hosts file together with
+    /// their PTR records, answered authoritatively and never expired
+    pub static_addresses: HashMap<String, Vec<ResourceRecord>>,
+    /// Records of the `hosts` section
+    pub hosts: HashMap<String, Vec<ResourceRecord>>,
+    /// Records of the hosts file
+    pub hosts_file: HashMap<String, Vec<ResourceRecord>>,
+    /// Names that are answered with NXDOMAIN, including all their subdomains
+    pub blocked: HashSet<String>,
+    pub upstream: UpstreamConfig,
+    /// Selection of the upstream servers together with their statistics,
+    /// which include the nameservers of recursive resolutions
+    pub upstream_stats: Upstreams,
+    /// Queries are resolved starting at the root servers instead of being
+    /// forwarded, `None` if recursion is disabled
+    pub recursion: Option<RecursionConfig>,
+    /// State of the recursive resolutions by their request
+    pub resolutions: HashMap<RequestKey, Resolution>,
+    /// Time after which a stale answer is sent, `None` if stale answers are
+    /// disabled
+    pub client_timeout: Option<Duration>,
+    /// Time of TTLs, the time of timeouts is given by the caller
+    pub clock: Arc<dyn Clock>,
 }
 
 impl ServerHandler {
-    pub fn new(hosts: HashMap<String, Vec<ResourceRecord>>) -> Self {
+    #[cfg(test)]
+    pub fn new(config: &Config) -> Self {
+        Self::with_clock(config, Arc::new(crate::clock::RealClock::new()))
+    }
+
+    pub fn with_clock(config: &Config, clock: Arc<dyn Clock>) -> Self {
         let mut instance = Self {
             pending_requests: HashMap::with_capacity(16),
-            known_addresses: HashMap::with_capacity(128),
-            last_checked: SystemTime::now(),
+            upstream_queries: HashMap::with_capacity(16),
+            cache: Cache::new(&config.cache),
+            static_addresses: HashMap::new(),
+            hosts: config.hosts.clone(),
+            hosts_file: HashMap::new(),
+            blocked: config.blocklist.iter().map(|x| x.to_lowercase()).collect(),
+            upstream: config.upstream.clone(),
+            upstream_stats: Upstreams::new(config.upstream.strategy),
+            recursion: Some(config.recursion.clone()).filter(|x| x.enabled),
+            resolutions: HashMap::new(),
+            client_timeout: Some(config.cache.client_timeout)
+                .filter(|_| config.cache.stale_window > Duration::ZERO),
+            clock,
         };
 
-        for (key, value) in hosts {
-            instance.known_addresses.insert(key, value);
-        }
-
+        instance.update_static_addresses();
         instance
     }
 
-    pub fn validate_ttl(&mut self) -> Result<()> {
-        for (key, value) in self.known_addresses.clone() {
-            let mut updated_resources = Vec::new();
-            for resource in value {
-                let ttl = resource
-                    .ttl
-                    .checked_sub(
-                        self.last_checked
-                            .elapsed()
-                            .map_err(|e| {
-                                dbg!(e);
-                                RdnsError::Todo
-                            })?
-                            .as_secs() as u32,
-                    )
-                    .unwrap_or(0);
-
-                if ttl > 0 {
-                    updated_resources.push(ResourceRecord {
-                        ttl: resource.ttl
-                            - self
-                                .last_checked
-                                .elapsed()
-                                .map_err(|e| {
-                                    dbg!(e);
-                                    RdnsError::Todo
-                                })?
-                                .as_secs() as u32,
-                        ..resource.clone()
-                    });
+    /// Replaces the records of the hosts file
+    pub fn load_hosts_file(&mut self, records: HashMap<String, Vec<ResourceRecord>>) {
+        self.hosts_file = records;
+        self.update_static_addresses();
+    }
+
+    fn update_static_addresses(&mut self) {
+        let mut addresses: HashMap<String, Vec<ResourceRecord>> = HashMap::new();
+        for (key, value) in self.hosts.iter().chain(self.hosts_file.iter()) {
+            let entry = addresses.entry(key.clone()).or_default();
+            for record in value.iter() {
+                if !entry.contains(record) {
+                    entry.push(record.clone());
                 }
             }
+        }
 
-            if updated_resources.is_empty() {
-                self.known_addresses.remove(&key);
-            } else {
-                self.known_addresses
-                    .insert(key.to_string(), updated_resources);
+        // answer reverse lookups for every static address
+        let mut pointers: HashMap<String, Vec<ResourceRecord>> = HashMap::new();
+        for (key, value) in addresses.iter() {
+            for record in value.iter() {
+                if let Some(addr) = record_address(record) {
+                    let pointer = ResourceRecord::ptr(reverse_name(addr), key, record.ttl);
+                    let entry = pointers.entry(pointer.name.clone()).or_default();
+                    if !entry.contains(&pointer) {
+                        entry.push(pointer);
+                    }
+                }
             }
         }
-        self.last_checked = SystemTime::now();
+        for (key, mut value) in pointers {
+            addresses.entry(key).or_default().append(&mut value);
+        }
+
+        self.static_addresses = addresses;
+    }
+
+    /// Removes expired records from the cache
+    pub fn validate_ttl(&mut self) -> Result<()> {
+        self.cache.evict_expired(self.clock.system_time());
         Ok(())
     }
 
-    pub fn read(&mut self, addr: SocketAddr, dns: DNS) -> Result<()> {
-        if self.known_addresses.contains_key(&dns.questions[0].qname) {
-            debug!("Cache hit");
-            let mut dns = dns.clone();
-            let address = &self.known_addresses[&dns.questions[0].qname];
-            dns.resource_records = address.to_vec();
+    /// Handles a datagram received by the listening socket
+    ///
+    /// Datagrams shorter than a header and responses are dropped, queries
+    /// that can not be handled are answered with the rcode of the error.
+    pub fn read_datagram(&mut self, listener: usize, addr: SocketAddr, datagram: Vec<u8>) {
+        if datagram.len() < HEADER_LENGTH {
+            debug!("Dropping datagram of {} without header", addr);
+            return;
         }
 
-        if dns.resource_records.is_empty() {
-            debug!("Adding new request");
-            self.pending_requests.insert(
-                dns.id,
-                Request {
-                    dns: dns.clone(),
-                    state: RequestState::Added,
-                    requester: addr,
-                },
-            );
-        } else {
-            self.known_addresses.insert(
-                dns.questions[0].qname.to_string(),
-                dns.resource_records.clone(),
+        let (query, result) = match DNS::parse(datagram.clone()) {
+            Ok(x) => (x.clone(), self.read(listener, addr, x)),
+            Err(e) => (
+                Self::header(&datagram),
+                Err(RdnsError::FormatError(e.to_string())),
+            ),
+        };
+        if let Err(e) = result {
+            if query.qr == 1 {
+                debug!("Ignoring invalid response sent by requester {}", addr);
+                return;
+            }
+
+            debug!("Answering {} with {:?}: {}", addr, e.rcode(), e);
+            let response = Self::synthesize(
+                &query,
+                e.rcode(),
+                ExtendedError::new(ExtendedErrorCode::Other, e.to_string()),
             );
+            self.ready_to_send(listener, addr, &query, response);
         }
+    }
 
-        self.pending_requests.entry(dns.id).and_modify(|e| {
-            if !dns.resource_records.is_empty() {
-                let dns = dns.clone();
-                e.state = RequestState::ReadyToSend;
-                e.dns.resource_records = dns.resource_records.to_vec();
+    /// Handles a query of a requester received by the listening socket
+    pub fn read(&mut self, listener: usize, addr: SocketAddr, dns: DNS) -> Result<()> {
+        if dns.qr == 1 {
+            debug!("Ignoring response sent by requester {}", addr);
+            return Ok(());
+        }
+        if dns.opcode != Opcode::Query {
+            return Err(RdnsError::NotImplemented(format!(
+                "opcode {:?}",
+                dns.opcode
+            )));
+        }
+        if dns.questions.len() != 1 {
+            return Err(RdnsError::FormatError(format!(
+                "expected one question, got {}",
+                dns.questions.len()
+            )));
+        }
+
+        let qname = dns.questions[0].qname.to_lowercase();
+        if self.is_blocked(&qname) {
+            debug!("Blocked query");
+            let response = Self::synthesize(
+                &dns,
+                Rcode::NameError,
+                ExtendedError::new(ExtendedErrorCode::Blocked, ""),
+            );
+            self.ready_to_send(listener, addr, &dns, response);
+        } else if let Some(records) = self
+            .static_addresses
+            .get(&qname)
+            .filter(|x| x.iter().any(|x| x.rclass == dns.questions[0].qclass))
+        {
+            debug!("Static hit");
+            let question = &dns.questions[0];
+            let response = DNS {
+                qr: 1,
+                aa: 1,
+                ra: 1,
+                resource_records: records
+                    .iter()
+                    .filter(|x| x.rtype == question.qtype || question.qtype == QType::ANY)
+                    .filter(|x| x.rclass == question.qclass)
+                    .cloned()
+                    .collect(),
+                authorities: Vec::new(),
+                additionals: Vec::new(),
+                edns: dns.edns.as_ref().map(|_| Edns::default()),
+                ..dns.clone()
+            };
+            self.ready_to_send(listener, addr, &dns, response);
+        } else if let Some(answer) = self
+            .cache
+            .lookup(&dns.questions[0], self.clock.system_time())
+        {
+            debug!("Cache hit");
+            if answer.prefetch {
+                self.prefetch(listener, &dns);
             }
-        });
+            let response = Self::cached_response(&dns, answer);
+            self.ready_to_send(listener, addr, &dns, response);
+        } else {
+            debug!("Adding new request");
+            self.pending_requests.insert(
+                (addr, dns.id),
+                Request::new(listener, addr, RequestState::Added, dns),
+            );
+        }
 
         Ok(())
     }
 
-    pub fn write(&mut self, servers: Vec<String>) -> Result<(Vec<u8>, Vec<SocketAddr>)> {
-        let mut response = Vec::new();
-        let mut response_addr: Vec<SocketAddr> = Vec::with_capacity(servers.len());
+    /// Datagrams that have to be sent at `now`: answers, queries for new
+    /// requests and retries of timed out ones
+    ///
+    /// Unless the strategy is a race, the query is sent to one upstream
+    /// server at a time, the next one is tried after the timeout, which
+    /// doubles with every attempt. After the deadline the requester gets
+    /// a stale answer or SERVFAIL and the request is removed.
+    ///
+    /// Once the client timeout passed, the requester gets a stale answer
+    /// while the upstream servers are still queried to refresh the cache
+    /// (RFC 8767 5).
+    ///
+    /// With recursion, the nameservers of the resolution take the place of
+    /// the upstream servers, one at a time.
+    pub fn write(&mut self, upstreams: &[SocketAddr], now: Instant) -> Result<Vec<Outgoing>> {
+        let mut outgoing = Vec::new();
 
-        for (key, value) in self.pending_requests.clone() {
+        for (key, mut value) in self.pending_requests.clone() {
             if value.state == RequestState::ReadyToSend {
                 debug!("Answering query");
-                response = value.dns.build();
-                response_addr.push(value.requester);
-                self.pending_requests.remove(&key);
+                outgoing.extend(self.answer(key, value));
+            } else if self.recursion.is_none() && upstreams.is_empty() {
+                value.dns = self.failure(
+                    &value.dns,
+                    ExtendedError::new(
+                        ExtendedErrorCode::NotReady,
+                        "no upstream server configured",
+                    ),
+                );
+                outgoing.extend(self.answer(key, value));
             } else if value.state == RequestState::Added {
-                debug!("Requesting from external server");
+                value.state = RequestState::WaitingForExternalServer;
+                value.deadline = Some(now + self.upstream.deadline);
+                value.stale_at = self
+                    .client_timeout
+                    .filter(|_| !value.answered)
+                    .map(|x| now + x);
+                if self.recursion.is_some() {
+                    debug!("Resolving recursively");
+                    let resolution = Resolution::new(&value.dns.questions[0]);
+                    self.resolutions.insert(key, resolution);
+                    outgoing.extend(self.resolve(key, value, now));
+                } else {
+                    debug!("Requesting from external server");
+                    value.order = self.upstream_stats.order(upstreams);
+                    value.upstreams = upstreams.len();
+                    outgoing.extend(self.query_upstream(key, &mut value, now));
+                    self.pending_requests.insert(key, value);
+                }
+            } else if value.deadline.is_some_and(|x| now >= x) {
+                debug!("No upstream server answered in time");
+                self.timed_out(key, value.attempts, now);
+                value.dns = self.failure(
+                    &value.dns,
+                    ExtendedError::new(
+                        ExtendedErrorCode::NoReachableAuthority,
+                        "no upstream server answered in time",
+                    ),
+                );
+                outgoing.extend(self.answer(key, value));
+            } else if value.stale_at.is_some_and(|x| now >= x) {
+                value.stale_at = None;
+                if let Some(response) = self.stale_answer(&value.dns) {
+                    debug!("Answering with a stale answer until an upstream server answers");
+                    value.answered = true;
+                    outgoing.push(Outgoing {
+                        request: key,
+                        via: Via::Listener(value.listener),
+                        addr: value.requester,
+                        message: response.build_truncated(value.max_size),
+                    });
+                }
+                self.pending_requests.insert(key, value);
+            } else if self.recursion.is_some() && value.retry_at.is_none_or(|x| now >= x) {
+                // late answers of the skipped nameserver are ignored
+                if value.retry_at.is_some() {
+                    debug!("Nameserver did not answer in time");
+                    self.timed_out(key, value.attempts, now);
+                    self.upstream_queries.retain(|_, x| x.request != key);
+                }
+                outgoing.extend(self.resolve(key, value, now));
+            } else if value.retry_at.is_none_or(|x| now >= x) {
+                debug!("Retrying with the next upstream server");
+                self.timed_out(key, value.attempts, now);
+                outgoing.extend(self.query_upstream(key, &mut value, now));
+                self.pending_requests.insert(key, value);
+            }
+        }
 
-                for server_addr in servers.iter() {
-                    let mut server_addr = server_addr.clone();
-                    server_addr.push_str(":53");
+        Ok(outgoing)
+    }
 
-                    response = value.dns.clone().build();
-                    response_addr.push(server_addr.parse().unwrap());
-                }
+    /// Earliest point at which `write` has to be called again for retries
+    /// and deadlines
+    pub fn next_timeout(&self) -> Option<Instant> {
+        self.pending_requests
+            .values()
+            .filter(|x| x.state == RequestState::WaitingForExternalServer)
+            .filter_map(|x| {
+                [x.retry_at, x.deadline, x.stale_at]
+                    .iter()
+                    .flatten()
+                    .min()
+                    .copied()
+            })
+            .min()
+    }
+
+    /// Sends the query with a new ID to the next upstream server, or to all
+    /// of them for a race
+    fn query_upstream(
+        &mut self,
+        key: RequestKey,
+        request: &mut Request,
+        now: Instant,
+    ) -> Vec<Outgoing> {
+        let targets = if self.upstream.strategy == Strategy::Race {
+            request.order.clone()
+        } else {
+            vec![request.order[request.attempts as usize % request.order.len()]]
+        };
+        let timeout = self
+            .upstream
+            .timeout
+            .checked_mul(2u32.saturating_pow(request.attempts))
+            .unwrap_or(self.upstream.deadline);
+        request.attempts += 1;
+        request.retry_at = Some(now + timeout);
 
-                self.pending_requests.insert(
-                    key,
-                    Request {
-                        dns: value.dns,
-                        state: RequestState::WaitingForExternalServer,
-                        requester: value.requester,
+        let mut outgoing = Vec::with_capacity(targets.len());
+        for addr in targets {
+            let id = self.upstream_id();
+            self.upstream_queries.insert(
+                id,
+                UpstreamQuery {
+                    request: key,
+                    upstream: addr,
+                    question: request.dns.questions[0].clone(),
+                    attempt: request.attempts,
+                    sent: now,
+                },
+            );
+            self.upstream_stats.sent(addr);
+
+            // options of the requester are not meant for the upstream server
+            let query = DNS {
+                id,
+                edns: request.dns.edns.as_ref().map(|_| Edns::default()),
+                ..request.dns.clone()
+            };
+            outgoing.push(Outgoing {
+                request: key,
+                via: Via::Upstream(id),
+                addr,
+                message: query.build(),
+            });
+        }
+        outgoing
+    }
+
+    /// Sends the query of the next step of the recursive resolution, or
+    /// answers the request once the resolution finished
+    ///
+    /// Nameservers get one timeout each, the deadline of the request covers
+    /// the whole resolution.
+    fn resolve(&mut self, key: RequestKey, mut request: Request, now: Instant) -> Option<Outgoing> {
+        let recursion = self.recursion.as_ref()?;
+        let resolution = self.resolutions.get_mut(&key)?;
+        match resolution.next(recursion, &self.cache, self.clock.system_time()) {
+            Step::Query(addr, question) => {
+                let id = self.upstream_id();
+                request.attempts += 1;
+                request.retry_at = Some(now + self.upstream.timeout);
+                self.upstream_queries.insert(
+                    id,
+                    UpstreamQuery {
+                        request: key,
+                        upstream: addr,
+                        question: question.clone(),
+                        attempt: request.attempts,
+                        sent: now,
                     },
                 );
+                self.upstream_stats.sent(addr);
+                self.pending_requests.insert(key, request);
+
+                let query = DNS {
+                    id,
+                    questions: vec![question],
+                    edns: Some(Edns::default()),
+                    ..DNS::default()
+                };
+                Some(Outgoing {
+                    request: key,
+                    via: Via::Upstream(id),
+                    addr,
+                    message: query.build(),
+                })
+            }
+            Step::Answer(response) => {
+                debug!("Resolved recursively");
+                request.dns = DNS {
+                    id: request.dns.id,
+                    rd: request.dns.rd,
+                    // the requester expects its own spelling of the question
+                    questions: request.dns.questions.clone(),
+                    edns: request.dns.edns.as_ref().map(|_| Edns::default()),
+                    ..response
+                };
+                self.answer(key, request)
+            }
+            Step::Failure(error) => {
+                debug!("Recursive resolution failed: {}", error.extra_text);
+                request.dns = self.failure(&request.dns, error);
+                self.answer(key, request)
+            }
+        }
+    }
+
+    /// Counts the unanswered queries of the attempt as timeouts
+    fn timed_out(&mut self, key: RequestKey, attempt: u32, now: Instant) {
+        for query in self.upstream_queries.values() {
+            if query.request == key && query.attempt == attempt {
+                self.upstream_stats
+                    .timed_out(query.upstream, now.duration_since(query.sent));
+            }
+        }
+    }
+
+    /// Removes the request together with its upstream queries, late answers
+    /// of upstream servers are ignored
+    ///
+    /// `None` if the requester already got an answer.
+    fn answer(&mut self, key: RequestKey, request: Request) -> Option<Outgoing> {
+        self.pending_requests.remove(&key);
+        self.resolutions.remove(&key);
+        self.upstream_queries.retain(|_, x| x.request != key);
+        if request.answered {
+            return None;
+        }
+
+        Some(Outgoing {
+            request: key,
+            via: Via::Listener(request.listener),
+            addr: request.requester,
+            message: request.dns.build_truncated(request.max_size),
+        })
+    }
+
+    /// Called when sending a query to an upstream server failed
+    ///
+    /// The next upstream server is tried on the next call of `write`. After
+    /// all upstream servers failed, the request is answered with SERVFAIL.
+    pub fn upstream_failed(&mut self, outgoing: &Outgoing, error: &std::io::Error) {
+        if let Via::Upstream(id) = outgoing.via {
+            self.upstream_queries.remove(&id);
+            self.upstream_stats.failed(outgoing.addr);
+        }
+
+        // a resolution continues with the next nameserver
+        if self.resolutions.contains_key(&outgoing.request) {
+            if let Some(request) = self.pending_requests.get_mut(&outgoing.request) {
+                request.retry_at = None;
+            }
+            return;
+        }
+
+        if !self.next_upstream(outgoing.request) {
+            return;
+        }
+        let query = match self.pending_requests.get(&outgoing.request) {
+            Some(x) => x.dns.clone(),
+            None => return,
+        };
+        let response = self.failure(
+            &query,
+            ExtendedError::new(ExtendedErrorCode::NetworkError, error.to_string()),
+        );
+        if let Some(request) = self.pending_requests.get_mut(&outgoing.request) {
+            request.dns = response;
+            request.state = RequestState::ReadyToSend;
+        }
+    }
+
+    /// Counts one upstream server of the request as failed, true if it was
+    /// the last one
+    fn next_upstream(&mut self, key: RequestKey) -> bool {
+        let request = match self.pending_requests.get_mut(&key) {
+            Some(x) if x.state == RequestState::WaitingForExternalServer => x,
+            _ => return false,
+        };
+
+        request.upstreams = request.upstreams.saturating_sub(1);
+        // a race already waits for all other servers
+        if self.upstream.strategy != Strategy::Race {
+            request.retry_at = None;
+        }
+        request.upstreams == 0
+    }
+
+    /// Handles a response of an upstream server received at `now`
+    ///
+    /// Responses are only accepted from the server the query was sent to,
+    /// with its ID and the same question (RFC 5452 9.1). SERVFAIL and
+    /// REFUSED are only passed on if no other upstream server is left.
+    pub fn read_response(&mut self, upstream: SocketAddr, mut dns: DNS, now: Instant) {
+        let matches = match self.upstream_queries.get(&dns.id) {
+            Some(x) => {
+                x.upstream == upstream
+                    && dns.qr == 1
+                    && dns.questions.len() == 1
+                    && Self::same_question(&dns.questions[0], &x.question)
+            }
+            None => false,
+        };
+        if !matches {
+            debug!("Ignoring unexpected response of {}", upstream);
+            return;
+        }
+        let query = match self.upstream_queries.remove(&dns.id) {
+            Some(x) => x,
+            None => return,
+        };
+
+        if let (Some(recursion), Some(resolution)) = (
+            self.recursion.as_ref(),
+            self.resolutions.get_mut(&query.request),
+        ) {
+            match dns.rcode {
+                Rcode::ServerFailure | Rcode::Refused => self.upstream_stats.failed(upstream),
+                _ => self
+                    .upstream_stats
+                    .answered(upstream, now.duration_since(query.sent)),
+            }
+            let time = self.clock.system_time();
+            resolution.response(&dns, recursion, &mut self.cache, time);
+            // the next call of `write` continues the resolution
+            if let Some(request) = self.pending_requests.get_mut(&query.request) {
+                request.retry_at = None;
+            }
+            return;
+        }
+
+        if let Rcode::ServerFailure | Rcode::Refused = dns.rcode {
+            debug!("{} answered with {:?}", upstream, dns.rcode);
+            self.upstream_stats.failed(upstream);
+            if !self.next_upstream(query.request) {
+                return;
+            }
+            // a stale answer is better than the failure
+            let stale = match self.pending_requests.get(&query.request) {
+                Some(x) => self.stale_answer(&x.dns.clone()),
+                None => None,
+            };
+            if let Some(x) = stale {
+                dns = x;
+            }
+        } else {
+            let rtt = now.duration_since(query.sent);
+            self.upstream_stats.answered(upstream, rtt);
+
+            // truncated answers may lack records of an RRset
+            if dns.tc == 0 {
+                self.cache.insert_response(&dns, self.clock.system_time());
+            }
+        }
+
+        if let Some(request) = self.pending_requests.get_mut(&query.request) {
+            if request.state == RequestState::WaitingForExternalServer {
+                if request.dns.edns.is_none() {
+                    dns.edns = None;
+                }
+
+                dns.id = request.dns.id;
+                // the requester expects its own spelling of the question
+                dns.questions = request.dns.questions.clone();
+                request.dns = dns;
+                request.state = RequestState::ReadyToSend;
             }
         }
-        Ok((response, response_addr))
+    }
+
+    /// Resolves the question of the query again to refresh the cache before
+    /// it expires, the answer is not sent to anyone
+    fn prefetch(&mut self, listener: usize, query: &DNS) {
+        let requester = SocketAddr::from(([0, 0, 0, 0], 0));
+        let key = loop {
+            let key = (requester, rand::random::<u16>());
+            if !self.pending_requests.contains_key(&key) {
+                break key;
+            }
+        };
+
+        let query = DNS {
+            id: key.1,
+            edns: query.edns.as_ref().map(|_| Edns::default()),
+            ..query.clone()
+        };
+        let mut request = Request::new(listener, requester, RequestState::Added, query);
+        request.answered = true;
+        self.pending_requests.insert(key, request);
+    }
+
+    /// Random ID that is not used by another upstream query
+    fn upstream_id(&self) -> u16 {
+        loop {
+            let id = rand::random::<u16>();
+            if !self.upstream_queries.contains_key(&id) {
+                return id;
+            }
+        }
+    }
+
+    fn same_question(a: &Question, b: &Question) -> bool {
+        a.qname.eq_ignore_ascii_case(&b.qname) && a.qtype == b.qtype && a.qclass == b.qclass
+    }
+
+    fn ready_to_send(&mut self, listener: usize, addr: SocketAddr, query: &DNS, response: DNS) {
+        let mut request = Request::new(listener, addr, RequestState::ReadyToSend, response);
+        request.max_size = query.max_udp_size();
+        self.pending_requests.insert((addr, query.id), request);
+    }
+
+    fn is_blocked(&self, qname: &str) -> bool {
+        let mut name = qname;
+        loop {
+            if self.blocked.contains(name) {
+                return true;
+            }
+
+            match name.find('.') {
+                Some(index) => name = &name[index + 1..],
+                None => return false,
+            }
+        }
+    }
+
+    /// Header of a datagram that could not be parsed as a whole
+    fn header(datagram: &[u8]) -> DNS {
+        let mut header = datagram[..HEADER_LENGTH].to_vec();
+        // without the counts, nothing but the header is parsed
+        header[4..].iter_mut().for_each(|x| *x = 0);
+        DNS::parse(header).unwrap_or_default()
+    }
+
+    /// Response to the query with an answer of the cache, stale answers are
+    /// marked with an extended error if the requester supports EDNS
+    fn cached_response(query: &DNS, answer: CachedAnswer) -> DNS {
+        let mut response = DNS {
+            qr: 1,
+            ra: 1,
+            rcode: answer.rcode,
+            resource_records: answer.answers,
+            authorities: answer.authorities,
+            edns: query.edns.as_ref().map(|_| Edns::default()),
+            ..query.clone()
+        };
+
+        if answer.stale && query.edns.is_some() {
+            let code = match response.rcode {
+                Rcode::NameError => ExtendedErrorCode::StaleNxdomainAnswer,
+                _ => ExtendedErrorCode::StaleAnswer,
+            };
+            response.add_extended_error(ExtendedError::new(code, ""));
+        }
+
+        response
+    }
+
+    /// Answer of the cache for the query, which may have expired within the
+    /// stale window
+    fn stale_answer(&mut self, query: &DNS) -> Option<DNS> {
+        let answer = self
+            .cache
+            .lookup_stale(query.questions.first()?, self.clock.system_time())?;
+        Some(Self::cached_response(query, answer))
+    }
+
+    /// Response for a query no upstream server answered, a stale answer if
+    /// the cache has one, otherwise SERVFAIL with the extended error
+    fn failure(&mut self, query: &DNS, error: ExtendedError) -> DNS {
+        match self.stale_answer(query) {
+            Some(x) => x,
+            None => Self::synthesize(query, Rcode::ServerFailure, error),
+        }
+    }
+
+    /// Creates a response without records for the given query
+    ///
+    /// The extended error is only attached if the requester supports EDNS
+    fn synthesize(query: &DNS, rcode: Rcode, error: ExtendedError) -> DNS {
+        let mut response = DNS {
+            qr: 1,
+            ra: 1,
+            rcode,
+            resource_records: Vec::new(),
+            authorities: Vec::new(),
+            additionals: Vec::new(),
+            edns: None,
+            ..query.clone()
+        };
+
+        if query.edns.is_some() {
+            response.add_extended_error(error);
+        }
+
+        response
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::cache::{CacheKey, STALE_TTL};
+    use crate::clock::ManualClock;
+    use crate::hosts::HOSTS_TTL;
+    use crate::resolver::tests::{network, recursion};
+    use std::time::Duration;
 
     use rdns_proto::{QClass, QType, Question};
 
     #[test]
     pub fn test_read_query() {
-        let mut server_handler = ServerHandler::new(HashMap::new());
+        let mut server_handler = ServerHandler::new(&Config::default());
         let dns = DNS {
             id: 13470,
             qr: 0,
@@ -174,6 +844,8 @@ mod tests {
             rd: 1,
             ra: 0,
             z: 0,
+            ad: 0,
+            cd: 0,
             rcode: Rcode::NoError,
             qdcount: 1,
             ancount: 0,
@@ -183,21 +855,82 @@ mod tests {
                 qname: String::from("www.google.de"),
                 qtype: QType::A,
                 qclass: QClass::IN,
+                unicast_response: false,
             }],
             resource_records: Vec::new(),
+            authorities: Vec::new(),
+            additionals: Vec::new(),
+            edns: None,
         };
 
-        server_handler
-            .read("0.0.0.0:1337".parse().unwrap(), dns)
-            .unwrap();
+        server_handler.read(0, client(), dns).unwrap();
 
         assert!(server_handler.pending_requests.len() == 1);
-        assert!(server_handler.known_addresses.is_empty());
+        assert!(server_handler.cache.is_empty());
+    }
+
+    #[test]
+    pub fn test_read_datagram_errors() {
+        let mut server_handler = ServerHandler::new(&Config::default());
+        let mut answer = |datagram: Vec<u8>| {
+            server_handler.read_datagram(0, client(), datagram);
+            let outgoing = server_handler.write(&upstreams(1), Instant::now()).unwrap();
+            outgoing
+                .first()
+                .map(|x| DNS::parse(x.message.clone()).unwrap())
+        };
+
+        assert_eq!(answer(vec![0x34, 0x9e, 0x01, 0x00]), None);
+
+        // one question announced, but it is cut off
+        let response = answer(hex_decode("349e01000001000000000000037777").unwrap()).unwrap();
+        assert_eq!(response.id, 0x349e);
+        assert_eq!(response.qr, 1);
+        assert_eq!(response.rcode, Rcode::FormatError);
+        assert!(response.questions.is_empty());
+
+        // responses are never answered, even if they are malformed
+        assert_eq!(
+            answer(hex_decode("349e81800001000000000000037777").unwrap()),
+            None
+        );
+
+        let mut query = query_with_edns();
+        query.questions.clear();
+        let response = answer(query.build()).unwrap();
+        assert_eq!(response.rcode, Rcode::FormatError);
+        assert_eq!(
+            response.extended_errors()[0].info_code,
+            ExtendedErrorCode::Other
+        );
+
+        let mut query = query_with_edns();
+        query.opcode = Opcode::Status;
+        let response = answer(query.build()).unwrap();
+        assert_eq!(response.rcode, Rcode::NotImplemented);
+        assert_eq!(response.opcode, Opcode::Status);
+        assert_eq!(response.questions.len(), 1);
+
+        // UPDATE is echoed, not replaced by another opcode
+        let mut query = query_with_edns();
+        query.opcode = Opcode::Unknown(5);
+        let bytes = query.build();
+        assert_eq!(bytes[2] >> 3 & 0b1111, 5);
+        let response = answer(bytes).unwrap();
+        assert_eq!(response.rcode, Rcode::NotImplemented);
+        assert_eq!(response.opcode, Opcode::Unknown(5));
+
+        // unknown types are forwarded
+        let mut query = query_with_edns();
+        query.questions[0].qtype = QType::Unknown(65);
+        server_handler.read_datagram(0, client(), query.build());
+        let outgoing = server_handler.write(&upstreams(1), Instant::now()).unwrap();
+        assert!(matches!(outgoing[0].via, Via::Upstream(_)));
     }
 
     #[test]
     pub fn test_read_response() {
-        let mut server_handler = ServerHandler::new(HashMap::new());
+        let mut server_handler = ServerHandler::new(&Config::default());
         let dns = DNS {
             id: 13470,
             qr: 1,
@@ -207,6 +940,8 @@ mod tests {
             rd: 1,
             ra: 1,
             z: 0,
+            ad: 0,
+            cd: 0,
             rcode: Rcode::NoError,
             qdcount: 1,
             ancount: 1,
@@ -216,31 +951,102 @@ mod tests {
                 qname: String::from("www.google.de"),
                 qtype: QType::A,
                 qclass: QClass::IN,
+                unicast_response: false,
             }],
             resource_records: vec![ResourceRecord {
                 name: String::from("www.google.de"),
                 rtype: QType::A,
                 rclass: QClass::IN,
+                cache_flush: false,
                 ttl: 238,
                 rdlength: 4,
                 rdata: vec![172, 217, 168, 195],
             }],
+            authorities: Vec::new(),
+            additionals: Vec::new(),
+            edns: None,
         };
 
-        server_handler
-            .read("0.0.0.0:1337".parse().unwrap(), dns)
-            .unwrap();
+        let outgoing = answer_upstream(&mut server_handler, dns);
+        assert_eq!(outgoing[0].addr, client());
+        assert_eq!(DNS::parse(outgoing[0].message.clone()).unwrap().id, 13470);
 
         assert!(server_handler.pending_requests.is_empty());
-        assert!(server_handler.known_addresses.len() == 1);
+        assert!(server_handler.upstream_queries.is_empty());
+        assert!(server_handler.cache.len() == 1);
     }
 
     #[test]
-    pub fn test_cache_invalidates() {
-        use std::thread;
-        use std::time::Duration;
+    pub fn test_cache_max_entries() {
+        let mut config = Config::default();
+        config.cache.max_entries = 1;
+        let mut server_handler = ServerHandler::new(&config);
+
+        for name in ["www.google.de", "www.google.com"].iter() {
+            let mut response = query_with_edns();
+            response.qr = 1;
+            response.questions[0].qname = name.to_string();
+            response.resource_records = vec![ResourceRecord {
+                name: name.to_string(),
+                rtype: QType::A,
+                rclass: QClass::IN,
+                cache_flush: false,
+                ttl: 238,
+                rdlength: 4,
+                rdata: vec![172, 217, 168, 195],
+            }];
+
+            answer_upstream(&mut server_handler, response);
+        }
+
+        // the older one made room for the newer one
+        assert_eq!(server_handler.cache.len(), 1);
+        assert_eq!(server_handler.cache.stats.evictions, 1);
+        let key = CacheKey::new("www.google.com", QType::A, QClass::IN);
+        assert!(server_handler.cache.get(&key).is_some());
+    }
+
+    #[test]
+    pub fn test_cache_by_type() {
+        let mut server_handler = ServerHandler::new(&Config::default());
+
+        let mut response = query_with_edns();
+        response.qr = 1;
+        response.resource_records = vec![ResourceRecord {
+            name: "ads.google.de".into(),
+            rtype: QType::A,
+            rclass: QClass::IN,
+            cache_flush: false,
+            ttl: 238,
+            rdlength: 4,
+            rdata: vec![172, 217, 168, 195],
+        }];
+        answer_upstream(&mut server_handler, response);
+
+        // a cached A record is no answer for AAAA
+        let mut query = query_with_edns();
+        query.questions[0].qtype = QType::AAAA;
+        server_handler.read(0, client(), query).unwrap();
+        let outgoing = server_handler.write(&upstreams(1), Instant::now()).unwrap();
+        assert!(matches!(outgoing[0].via, Via::Upstream(_)));
+
+        let mut query = query_with_edns();
+        query.id = 4242;
+        query.questions[0].qname = "ADS.Google.DE".into();
+        server_handler.read(0, client(), query).unwrap();
+        let outgoing = server_handler.write(&upstreams(1), Instant::now()).unwrap();
+        assert_eq!(outgoing.len(), 1);
+        let answer = DNS::parse(outgoing[0].message.clone()).unwrap();
+        assert_eq!(answer.questions[0].qname, "ADS.Google.DE");
+        assert_eq!(answer.resource_records[0].rdata, vec![172, 217, 168, 195]);
+    }
 
-        let mut server_handler = ServerHandler::new(HashMap::new());
+    #[test]
+    pub fn test_cache_invalidates() {
+        let mut config = Config::default();
+        config.cache.stale_window = Duration::ZERO;
+        let clock = ManualClock::new();
+        let mut server_handler = ServerHandler::with_clock(&config, Arc::new(clock.clone()));
         let dns = DNS {
             id: 13470,
             qr: 1,
@@ -250,6 +1056,8 @@ mod tests {
             rd: 1,
             ra: 1,
             z: 0,
+            ad: 0,
+            cd: 0,
             rcode: Rcode::NoError,
             qdcount: 1,
             ancount: 1,
@@ -259,25 +1067,737 @@ mod tests {
                 qname: String::from("www.google.de"),
                 qtype: QType::A,
                 qclass: QClass::IN,
+                unicast_response: false,
             }],
             resource_records: vec![ResourceRecord {
                 name: String::from("www.google.de"),
                 rtype: QType::A,
                 rclass: QClass::IN,
+                cache_flush: false,
                 ttl: 1,
                 rdlength: 4,
                 rdata: vec![172, 217, 168, 195],
             }],
+            authorities: Vec::new(),
+            additionals: Vec::new(),
+            edns: None,
+        };
+
+        answer_upstream(&mut server_handler, dns);
+
+        clock.advance(Duration::from_millis(999));
+        server_handler.validate_ttl().unwrap();
+        assert_eq!(server_handler.cache.len(), 1);
+
+        clock.advance(Duration::from_millis(1));
+        server_handler.validate_ttl().unwrap();
+
+        assert!(server_handler.pending_requests.is_empty());
+        assert!(server_handler.cache.is_empty());
+    }
+
+    #[test]
+    pub fn test_upstream_id_translation() {
+        let mut server_handler = ServerHandler::new(&Config::default());
+        let other: SocketAddr = "192.168.0.2:4242".parse().unwrap();
+
+        // two requesters using the same ID
+        server_handler.read(0, client(), query_with_edns()).unwrap();
+        let mut query = query_with_edns();
+        query.questions[0].qname = "WWW.Google.de".into();
+        server_handler.read(1, other, query).unwrap();
+        assert_eq!(server_handler.pending_requests.len(), 2);
+
+        let outgoing = server_handler.write(&upstreams(1), Instant::now()).unwrap();
+        assert_eq!(outgoing.len(), 2);
+        assert_eq!(server_handler.upstream_queries.len(), 2);
+        let query = outgoing.iter().find(|x| x.request.0 == other).unwrap();
+        let id = match query.via {
+            Via::Upstream(x) => x,
+            x => panic!("Unexpected destination {:?}", x),
+        };
+        assert_eq!(DNS::parse(query.message.clone()).unwrap().id, id);
+
+        let mut response = DNS::parse(query.message.clone()).unwrap();
+        response.qr = 1;
+        response.questions[0].qname = "www.google.de".into();
+
+        // another question, sender or ID is no answer
+        let mut wrong_question = response.clone();
+        wrong_question.questions[0].qtype = QType::AAAA;
+        server_handler.read_response(query.addr, wrong_question, Instant::now());
+        server_handler.read_response(upstreams(2)[1], response.clone(), Instant::now());
+        let mut wrong_id = response.clone();
+        wrong_id.id = id.wrapping_add(1);
+        server_handler.read_response(query.addr, wrong_id, Instant::now());
+        assert!(server_handler
+            .write(&upstreams(1), Instant::now())
+            .unwrap()
+            .is_empty());
+
+        // answers to a requester are not accepted as upstream answers
+        server_handler
+            .read(0, query.addr, response.clone())
+            .unwrap();
+        assert!(server_handler
+            .write(&upstreams(1), Instant::now())
+            .unwrap()
+            .is_empty());
+
+        server_handler.read_response(query.addr, response, Instant::now());
+        let outgoing = server_handler.write(&upstreams(1), Instant::now()).unwrap();
+        assert_eq!(outgoing.len(), 1);
+        assert_eq!(outgoing[0].addr, other);
+        assert_eq!(outgoing[0].via, Via::Listener(1));
+        let answer = DNS::parse(outgoing[0].message.clone()).unwrap();
+        assert_eq!(answer.id, 13470);
+        assert_eq!(answer.questions[0].qname, "WWW.Google.de");
+        assert_eq!(server_handler.pending_requests.len(), 1);
+    }
+
+    fn client() -> SocketAddr {
+        "0.0.0.0:1337".parse().unwrap()
+    }
+
+    fn upstreams(count: usize) -> Vec<SocketAddr> {
+        Config::default().servers[..count].to_vec()
+    }
+
+    /// Lets the query of the response be forwarded and answers it, returns
+    /// the answers for the requester
+    fn answer_upstream(server_handler: &mut ServerHandler, mut response: DNS) -> Vec<Outgoing> {
+        let query = DNS {
+            qr: 0,
+            resource_records: Vec::new(),
+            ..response.clone()
+        };
+        server_handler.read(0, client(), query).unwrap();
+
+        let outgoing = server_handler.write(&upstreams(1), Instant::now()).unwrap();
+        response.id = match outgoing[0].via {
+            Via::Upstream(x) => x,
+            x => panic!("Unexpected destination {:?}", x),
+        };
+        server_handler.read_response(outgoing[0].addr, response, Instant::now());
+        server_handler.write(&upstreams(1), Instant::now()).unwrap()
+    }
+
+    fn query_with_edns() -> DNS {
+        DNS {
+            id: 13470,
+            qr: 0,
+            opcode: Opcode::Query,
+            aa: 0,
+            tc: 0,
+            rd: 1,
+            ra: 0,
+            z: 0,
+            ad: 0,
+            cd: 0,
+            rcode: Rcode::NoError,
+            qdcount: 1,
+            ancount: 0,
+            nscount: 0,
+            arcount: 1,
+            questions: vec![Question {
+                qname: String::from("ads.google.de"),
+                qtype: QType::A,
+                qclass: QClass::IN,
+                unicast_response: false,
+            }],
+            resource_records: Vec::new(),
+            authorities: Vec::new(),
+            additionals: Vec::new(),
+            edns: Some(Edns::default()),
+        }
+    }
+
+    #[test]
+    pub fn test_blocked_extended_error() {
+        let mut config = Config::default();
+        config.blocklist.insert(String::from("Google.de"));
+        let mut server_handler = ServerHandler::new(&config);
+
+        server_handler.read(0, client(), query_with_edns()).unwrap();
+        let outgoing = server_handler.write(&upstreams(1), Instant::now()).unwrap();
+
+        assert!(outgoing.len() == 1);
+        let response = DNS::parse(outgoing[0].message.clone()).unwrap();
+        assert_eq!(response.rcode, Rcode::NameError);
+        assert_eq!(
+            response.extended_errors(),
+            vec![&ExtendedError::new(ExtendedErrorCode::Blocked, "")]
+        );
+    }
+
+    #[test]
+    pub fn test_not_ready_extended_error() {
+        let mut server_handler = ServerHandler::new(&Config::default());
+
+        let mut dns = query_with_edns();
+        dns.edns = None;
+        server_handler.read(0, client(), dns).unwrap();
+        let outgoing = server_handler.write(&[], Instant::now()).unwrap();
+
+        // no EDNS support by the requester, so there is no extended error
+        let response = DNS::parse(outgoing[0].message.clone()).unwrap();
+        assert_eq!(response.rcode, Rcode::ServerFailure);
+        assert!(response.edns.is_none());
+
+        server_handler.read(0, client(), query_with_edns()).unwrap();
+        let outgoing = server_handler.write(&[], Instant::now()).unwrap();
+
+        let response = DNS::parse(outgoing[0].message.clone()).unwrap();
+        assert_eq!(response.rcode, Rcode::ServerFailure);
+        assert_eq!(
+            response.extended_errors()[0].info_code,
+            ExtendedErrorCode::NotReady
+        );
+    }
+
+    #[test]
+    pub fn test_network_error_extended_error() {
+        let mut server_handler = ServerHandler::new(&Config::default());
+        let now = Instant::now();
+
+        server_handler.read(0, client(), query_with_edns()).unwrap();
+        let outgoing = server_handler.write(&upstreams(2), now).unwrap();
+        assert_eq!(outgoing.len(), 1);
+        assert_eq!(outgoing[0].addr, upstreams(2)[0]);
+
+        // the next server is tried right away
+        let error = std::io::Error::from(std::io::ErrorKind::ConnectionRefused);
+        server_handler.upstream_failed(&outgoing[0], &error);
+        let outgoing = server_handler.write(&upstreams(2), now).unwrap();
+        assert_eq!(outgoing.len(), 1);
+        assert_eq!(outgoing[0].addr, upstreams(2)[1]);
+
+        server_handler.upstream_failed(&outgoing[0], &error);
+        let outgoing = server_handler.write(&upstreams(2), now).unwrap();
+        assert_eq!(outgoing[0].addr, "0.0.0.0:1337".parse().unwrap());
+
+        let response = DNS::parse(outgoing[0].message.clone()).unwrap();
+        assert_eq!(response.rcode, Rcode::ServerFailure);
+        assert_eq!(
+            response.extended_errors()[0].info_code,
+            ExtendedErrorCode::NetworkError
+        );
+        assert!(server_handler.pending_requests.is_empty());
+        assert!(server_handler.upstream_queries.is_empty());
+    }
+
+    #[test]
+    pub fn test_upstream_timeout() {
+        let mut config = Config::default();
+        config.cache.stale_window = Duration::ZERO;
+        let mut server_handler = ServerHandler::new(&config);
+        let upstreams = upstreams(2);
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+
+        server_handler.read(0, client(), query_with_edns()).unwrap();
+        let first = server_handler.write(&upstreams, start).unwrap();
+        assert_eq!(first[0].addr, upstreams[0]);
+        assert_eq!(server_handler.next_timeout(), Some(at(500)));
+
+        assert!(server_handler
+            .write(&upstreams, at(499))
+            .unwrap()
+            .is_empty());
+        let outgoing = server_handler.write(&upstreams, at(500)).unwrap();
+        assert_eq!(outgoing[0].addr, upstreams[1]);
+        assert_eq!(server_handler.next_timeout(), Some(at(1500)));
+
+        // the timeout doubles with every retry, but ends at the deadline
+        let outgoing = server_handler.write(&upstreams, at(1500)).unwrap();
+        assert_eq!(outgoing[0].addr, upstreams[0]);
+        assert_eq!(server_handler.next_timeout(), Some(at(3500)));
+        server_handler.write(&upstreams, at(3500)).unwrap();
+        assert_eq!(server_handler.next_timeout(), Some(at(4000)));
+        assert_eq!(server_handler.upstream_queries.len(), 4);
+
+        let outgoing = server_handler.write(&upstreams, at(4000)).unwrap();
+        assert_eq!(outgoing.len(), 1);
+        assert_eq!(outgoing[0].via, Via::Listener(0));
+        let response = DNS::parse(outgoing[0].message.clone()).unwrap();
+        assert_eq!(response.rcode, Rcode::ServerFailure);
+        assert_eq!(
+            response.extended_errors()[0].info_code,
+            ExtendedErrorCode::NoReachableAuthority
+        );
+        assert!(server_handler.pending_requests.is_empty());
+        assert!(server_handler.upstream_queries.is_empty());
+        assert_eq!(server_handler.next_timeout(), None);
+
+        // a late answer to an earlier attempt is still accepted
+        server_handler.read(0, client(), query_with_edns()).unwrap();
+        let first = server_handler.write(&upstreams, start).unwrap();
+        server_handler.write(&upstreams, at(500)).unwrap();
+
+        let mut response = DNS::parse(first[0].message.clone()).unwrap();
+        response.qr = 1;
+        server_handler.read_response(first[0].addr, response, Instant::now());
+        let outgoing = server_handler.write(&upstreams, at(600)).unwrap();
+        assert_eq!(outgoing.len(), 1);
+        assert_eq!(outgoing[0].addr, client());
+        assert!(server_handler.upstream_queries.is_empty());
+    }
+
+    #[test]
+    pub fn test_serve_stale() {
+        let clock = ManualClock::new();
+        let mut server_handler =
+            ServerHandler::with_clock(&Config::default(), Arc::new(clock.clone()));
+        let upstreams = upstreams(1);
+        let start = Instant::now();
+        let record = ResourceRecord {
+            name: "ads.google.de".into(),
+            rtype: QType::A,
+            rclass: QClass::IN,
+            cache_flush: false,
+            ttl: 60,
+            rdlength: 4,
+            rdata: vec![172, 217, 168, 195],
+        };
+        server_handler
+            .cache
+            .insert(std::slice::from_ref(&record), clock.system_time());
+        clock.advance(Duration::from_secs(120));
+
+        // the upstream server does not answer within the client timeout
+        server_handler.read(0, client(), query_with_edns()).unwrap();
+        let query = server_handler.write(&upstreams, start).unwrap();
+        assert_eq!(
+            server_handler.next_timeout(),
+            Some(start + Duration::from_millis(500))
+        );
+        server_handler
+            .write(&upstreams, start + Duration::from_millis(500))
+            .unwrap();
+        let outgoing = server_handler
+            .write(&upstreams, start + Duration::from_millis(1800))
+            .unwrap();
+        assert_eq!(outgoing.len(), 1);
+        assert_eq!(outgoing[0].via, Via::Listener(0));
+        let answer = DNS::parse(outgoing[0].message.clone()).unwrap();
+        assert_eq!(answer.rcode, Rcode::NoError);
+        assert_eq!(answer.resource_records[0].ttl, STALE_TTL);
+        assert_eq!(
+            answer.extended_errors()[0].info_code,
+            ExtendedErrorCode::StaleAnswer
+        );
+
+        // its late answer refreshes the cache, but is not sent again
+        let mut response = DNS::parse(query[0].message.clone()).unwrap();
+        response.qr = 1;
+        response.resource_records = vec![record];
+        server_handler.read_response(query[0].addr, response, Instant::now());
+        assert!(server_handler
+            .write(&upstreams, start + Duration::from_millis(2000))
+            .unwrap()
+            .is_empty());
+        assert!(server_handler.pending_requests.is_empty());
+        let answer = server_handler
+            .cache
+            .lookup(&query_with_edns().questions[0], clock.system_time())
+            .unwrap();
+        assert!(!answer.stale);
+
+        // a stale answer is better than SERVFAIL
+        let key = CacheKey::new("ads.google.de", QType::A, QClass::IN);
+        server_handler.cache.remove(&key);
+        let nxdomain = DNS {
+            qr: 1,
+            rcode: Rcode::NameError,
+            questions: query_with_edns().questions,
+            authorities: vec![ResourceRecord {
+                name: "google.de".into(),
+                rtype: QType::SOA,
+                rclass: QClass::IN,
+                cache_flush: false,
+                ttl: 60,
+                rdlength: 22,
+                rdata: [
+                    0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 60,
+                ]
+                .to_vec(),
+            }],
+            ..DNS::default()
+        };
+        server_handler
+            .cache
+            .insert_response(&nxdomain, clock.system_time());
+        clock.advance(Duration::from_secs(120));
+
+        let mut response = query_with_edns();
+        response.qr = 1;
+        response.rcode = Rcode::ServerFailure;
+        let outgoing = answer_upstream(&mut server_handler, response);
+        let answer = DNS::parse(outgoing[0].message.clone()).unwrap();
+        assert_eq!(answer.rcode, Rcode::NameError);
+        assert_eq!(answer.authorities[0].ttl, STALE_TTL);
+        assert_eq!(
+            answer.extended_errors()[0].info_code,
+            ExtendedErrorCode::StaleNxdomainAnswer
+        );
+    }
+
+    #[test]
+    pub fn test_stale_not_cached() {
+        let clock = ManualClock::new();
+        let mut config = Config::default();
+        config.cache.stale_window = Duration::from_secs(300);
+        let mut server_handler = ServerHandler::with_clock(&config, Arc::new(clock.clone()));
+        let record = ResourceRecord {
+            name: "ads.google.de".into(),
+            rtype: QType::A,
+            rclass: QClass::IN,
+            cache_flush: false,
+            ttl: 60,
+            rdlength: 4,
+            rdata: vec![172, 217, 168, 195],
         };
+        server_handler.cache.insert(&[record], clock.system_time());
+        clock.advance(Duration::from_secs(120));
+
+        let mut response = query_with_edns();
+        response.qr = 1;
+        response.rcode = Rcode::ServerFailure;
+        let outgoing = answer_upstream(&mut server_handler, response.clone());
+        let answer = DNS::parse(outgoing[0].message.clone()).unwrap();
+        assert_eq!(answer.rcode, Rcode::NoError);
+        assert_eq!(answer.resource_records[0].ttl, STALE_TTL);
+
+        // the stale answer was not cached again, so it is gone with the window
+        clock.advance(Duration::from_secs(300));
+        let outgoing = answer_upstream(&mut server_handler, response);
+        let answer = DNS::parse(outgoing[0].message.clone()).unwrap();
+        assert_eq!(answer.rcode, Rcode::ServerFailure);
+        assert!(answer.resource_records.is_empty());
+    }
 
+    #[test]
+    pub fn test_truncation() {
+        let mut server_handler = ServerHandler::new(&Config::default());
+        // 40 records of 16 bytes do not fit into 512 bytes
+        let records: Vec<ResourceRecord> = (0..40)
+            .map(|x| ResourceRecord {
+                name: "ads.google.de".into(),
+                rtype: QType::A,
+                rclass: QClass::IN,
+                cache_flush: false,
+                ttl: 60,
+                rdlength: 4,
+                rdata: vec![192, 0, 2, x],
+            })
+            .collect();
+
+        let mut response = DNS {
+            qr: 1,
+            resource_records: records.clone(),
+            edns: None,
+            ..query_with_edns()
+        };
+        let outgoing = answer_upstream(&mut server_handler, response.clone());
+        let answer = DNS::parse(outgoing[0].message.clone()).unwrap();
+        assert!(outgoing[0].message.len() <= 512);
+        assert_eq!(answer.tc, 1);
+        assert!(answer.resource_records.is_empty());
+
+        // cache hits are truncated as well
+        response.qr = 0;
+        response.resource_records = Vec::new();
+        server_handler.read(0, client(), response).unwrap();
+        let outgoing = server_handler.write(&upstreams(1), Instant::now()).unwrap();
+        let answer = DNS::parse(outgoing[0].message.clone()).unwrap();
+        assert_eq!(outgoing[0].via, Via::Listener(0));
+        assert_eq!(answer.tc, 1);
+
+        // EDNS raises the limit
+        server_handler.read(0, client(), query_with_edns()).unwrap();
+        let outgoing = server_handler.write(&upstreams(1), Instant::now()).unwrap();
+        let answer = DNS::parse(outgoing[0].message.clone()).unwrap();
+        assert_eq!(answer.tc, 0);
+        assert_eq!(answer.resource_records.len(), records.len());
+    }
+
+    #[test]
+    pub fn test_prefetch() {
+        let clock = ManualClock::new();
+        let mut server_handler =
+            ServerHandler::with_clock(&Config::default(), Arc::new(clock.clone()));
+        let record = ResourceRecord {
+            name: "ads.google.de".into(),
+            rtype: QType::A,
+            rclass: QClass::IN,
+            cache_flush: false,
+            ttl: 100,
+            rdlength: 4,
+            rdata: vec![172, 217, 168, 195],
+        };
         server_handler
-            .read("0.0.0.0:1337".parse().unwrap(), dns)
+            .cache
+            .insert(std::slice::from_ref(&record), clock.system_time());
+        clock.advance(Duration::from_secs(95));
+
+        let mut outgoing = Vec::new();
+        for _ in 0..3 {
+            server_handler.read(0, client(), query_with_edns()).unwrap();
+            outgoing = server_handler.write(&upstreams(1), Instant::now()).unwrap();
+        }
+
+        // the third hit is answered and refreshed at once
+        assert_eq!(outgoing.len(), 2);
+        assert!(outgoing.iter().any(|x| x.via == Via::Listener(0)));
+        let query = outgoing
+            .iter()
+            .find(|x| matches!(x.via, Via::Upstream(_)))
             .unwrap();
 
-        thread::sleep(Duration::from_secs(1));
+        let mut response = DNS::parse(query.message.clone()).unwrap();
+        response.qr = 1;
+        response.resource_records = vec![record];
+        server_handler.read_response(query.addr, response, Instant::now());
+        assert!(server_handler
+            .write(&upstreams(1), Instant::now())
+            .unwrap()
+            .is_empty());
+        assert!(server_handler.pending_requests.is_empty());
+
+        let answer = server_handler
+            .cache
+            .lookup(&query_with_edns().questions[0], clock.system_time())
+            .unwrap();
+        assert_eq!(answer.answers[0].ttl, 100);
+    }
+
+    #[test]
+    pub fn test_race() {
+        let mut config = Config::default();
+        config.upstream.strategy = Strategy::Race;
+        let mut server_handler = ServerHandler::new(&config);
+        let start = Instant::now();
+
+        server_handler.read(0, client(), query_with_edns()).unwrap();
+        let outgoing = server_handler.write(&upstreams(2), start).unwrap();
+        assert_eq!(outgoing.len(), 2);
+        assert_ne!(outgoing[0].addr, outgoing[1].addr);
+
+        // SERVFAIL of one server is no good answer while another is left
+        let mut failure = DNS::parse(outgoing[0].message.clone()).unwrap();
+        failure.qr = 1;
+        failure.rcode = Rcode::ServerFailure;
+        server_handler.read_response(outgoing[0].addr, failure, start);
+        assert!(server_handler
+            .write(&upstreams(2), start)
+            .unwrap()
+            .is_empty());
+
+        let mut response = DNS::parse(outgoing[1].message.clone()).unwrap();
+        response.qr = 1;
+        let rtt = Duration::from_millis(30);
+        server_handler.read_response(outgoing[1].addr, response, start + rtt);
+        let answer = server_handler.write(&upstreams(2), start + rtt).unwrap();
+        assert_eq!(answer.len(), 1);
+        assert_eq!(
+            DNS::parse(answer[0].message.clone()).unwrap().rcode,
+            Rcode::NoError
+        );
+
+        let stats = &server_handler.upstream_stats.stats;
+        assert_eq!(stats[&outgoing[0].addr].failures, 1);
+        assert_eq!(stats[&outgoing[1].addr].answers, 1);
+        assert_eq!(stats[&outgoing[1].addr].srtt, Some(rtt));
+    }
+
+    #[test]
+    pub fn test_hosts_pointer() {
+        let config = Config::parse(
+            "
+hosts:
+  - 127.0.0.1: dev.local
+  - 127.0.0.1: local
+  - '::1': local
+",
+        )
+        .unwrap();
+        let mut server_handler = ServerHandler::new(&config);
+
+        let mut dns = query_with_edns();
+        dns.questions[0] = Question {
+            qname: reverse_name("127.0.0.1".parse().unwrap()),
+            qtype: QType::PTR,
+            qclass: QClass::IN,
+            unicast_response: false,
+        };
+        server_handler.read(0, client(), dns).unwrap();
+        let outgoing = server_handler.write(&upstreams(1), Instant::now()).unwrap();
+
+        assert!(outgoing.len() == 1);
+        let response = DNS::parse(outgoing[0].message.clone()).unwrap();
+        let mut targets = response
+            .resource_records
+            .iter()
+            .map(|x| x.rdata.clone())
+            .collect::<Vec<Vec<u8>>>();
+        targets.sort();
+        assert_eq!(
+            targets,
+            vec![
+                vec![3, 100, 101, 118, 5, 108, 111, 99, 97, 108, 0],
+                vec![5, 108, 111, 99, 97, 108, 0]
+            ]
+        );
+
+        // other classes and the sections of the query are not answered
+        let mut dns = query_with_edns();
+        dns.questions[0].qname = "dev.local".into();
+        dns.questions[0].qclass = QClass::Unknown(3);
+        dns.authorities = vec![ResourceRecord::cname("dev.local".into(), "local", 60)];
+        server_handler.read(0, client(), dns.clone()).unwrap();
+        assert_eq!(
+            server_handler.pending_requests[&(client(), dns.id)].state,
+            RequestState::Added
+        );
+        server_handler.pending_requests.clear();
+
+        dns.questions[0].qclass = QClass::IN;
+        server_handler.read(0, client(), dns).unwrap();
+        let outgoing = server_handler.write(&upstreams(1), Instant::now()).unwrap();
+        let response = DNS::parse(outgoing[0].message.clone()).unwrap();
+        assert_eq!(response.resource_records.len(), 1);
+        assert!(response.authorities.is_empty());
+
+        let name = "1.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.ip6.arpa";
+        assert_eq!(server_handler.static_addresses[name].len(), 1);
+        assert!(server_handler.cache.is_empty());
+    }
+
+    #[test]
+    pub fn test_hosts_file() {
+        let config = Config::parse("hosts:\n  - 127.0.0.1: dev.local\n").unwrap();
+        let clock = ManualClock::new();
+        let mut server_handler = ServerHandler::with_clock(&config, Arc::new(clock.clone()));
+        server_handler.load_hosts_file(crate::hosts::parse("10.0.0.1 NAS.local\n"));
+
+        let mut query = |qname: &str, qtype| {
+            let mut dns = query_with_edns();
+            dns.questions[0].qname = qname.into();
+            dns.questions[0].qtype = qtype;
+            server_handler.read(0, client(), dns).unwrap();
+            let outgoing = server_handler.write(&upstreams(1), Instant::now()).unwrap();
+            assert_eq!(outgoing.len(), 1);
+            DNS::parse(outgoing[0].message.clone()).unwrap()
+        };
+
+        let response = query("nas.local", QType::A);
+        assert_eq!(response.aa, 1);
+        assert_eq!(response.resource_records[0].rdata, vec![10, 0, 0, 1]);
+
+        // the name exists, but without records of the type
+        let response = query("NAS.local", QType::AAAA);
+        assert_eq!(response.aa, 1);
+        assert_eq!(response.rcode, Rcode::NoError);
+        assert!(response.resource_records.is_empty());
+
+        let response = query("1.0.0.10.in-addr.arpa", QType::PTR);
+        assert_eq!(response.resource_records.len(), 1);
+
+        // static records never expire
+        clock.advance(Duration::from_secs(u64::from(HOSTS_TTL) * 2));
         server_handler.validate_ttl().unwrap();
+        assert_eq!(server_handler.static_addresses.len(), 4);
+
+        // a reload replaces the entries of the file, but keeps the config
+        server_handler.load_hosts_file(HashMap::new());
+        assert!(!server_handler.static_addresses.contains_key("nas.local"));
+        assert!(server_handler.static_addresses.contains_key("dev.local"));
+    }
+
+    /// Sends the queries of the handler to the in-process network until the
+    /// requester is answered, servers outside of it never answer
+    fn query_network(server_handler: &mut ServerHandler, qname: &str, now: Instant) -> Option<DNS> {
+        let network = network();
+        let mut query = query_with_edns();
+        query.questions[0].qname = qname.into();
+        server_handler.read(0, client(), query).unwrap();
+
+        loop {
+            let outgoing = server_handler.write(&[], now).unwrap();
+            if outgoing.is_empty() {
+                return None;
+            }
+            for message in outgoing {
+                let query = DNS::parse(message.message).unwrap();
+                match (message.via, network.get(&message.addr)) {
+                    (Via::Listener(_), _) => return Some(query),
+                    (Via::Upstream(_), Some(authority)) => {
+                        assert_eq!(query.rd, 0);
+                        let response = authority.answer(&query);
+                        server_handler.read_response(message.addr, response, now);
+                    }
+                    (Via::Upstream(_), None) => (),
+                }
+            }
+        }
+    }
+
+    #[test]
+    pub fn test_recursion() {
+        let mut config = Config {
+            recursion: recursion(),
+            ..Config::default()
+        };
+        config.cache.stale_window = Duration::ZERO;
+        let mut server_handler = ServerHandler::new(&config);
+        let start = Instant::now();
+
+        let response = query_network(&mut server_handler, "Alias.example.com", start).unwrap();
+        assert_eq!(response.id, 13470);
+        assert_eq!(response.qr, 1);
+        assert_eq!(response.ra, 1);
+        assert_eq!(response.rcode, Rcode::NoError);
+        assert_eq!(response.questions[0].qname, "Alias.example.com");
+        assert_eq!(response.resource_records.len(), 2);
+        assert_eq!(response.resource_records[1].rdata, vec![192, 0, 2, 2]);
+        assert!(response.edns.is_some());
+        assert!(server_handler.resolutions.is_empty());
+        assert!(server_handler.upstream_queries.is_empty());
+        // the root is asked twice, once for each zone
+        assert_eq!(server_handler.upstream_stats.stats.len(), 5);
 
+        // the answer is cached
+        server_handler
+            .read(
+                0,
+                client(),
+                DNS {
+                    questions: response.questions.clone(),
+                    ..query_with_edns()
+                },
+            )
+            .unwrap();
+        let outgoing = server_handler.write(&[], start).unwrap();
+        assert_eq!(outgoing[0].via, Via::Listener(0));
+
+        // the nameserver of lame.com never answers, after its timeout no
+        // other one is left
+        assert!(query_network(&mut server_handler, "www.lame.com", start).is_none());
+        assert_eq!(
+            server_handler.next_timeout(),
+            Some(start + Duration::from_millis(500))
+        );
+        let outgoing = server_handler
+            .write(&[], start + Duration::from_millis(500))
+            .unwrap();
+        let response = DNS::parse(outgoing[0].message.clone()).unwrap();
+        assert_eq!(response.rcode, Rcode::ServerFailure);
+        assert_eq!(
+            response.extended_errors()[0].info_code,
+            ExtendedErrorCode::NoReachableAuthority
+        );
         assert!(server_handler.pending_requests.is_empty());
-        assert!(server_handler.known_addresses.is_empty());
+        assert!(server_handler.resolutions.is_empty());
+        assert!(server_handler.upstream_queries.is_empty());
     }
 }
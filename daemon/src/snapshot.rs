@@ -0,0 +1,248 @@
+use crate::cache::{Cache, CacheKey};
+use crate::error::*;
+
+use rdns_proto::*;
+use std::fs::{self, File};
+use std::io::{self, ErrorKind, Read, Write};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Start of every snapshot
+const MAGIC: &[u8; 4] = b"RDNS";
+/// Version of the format, snapshots of other versions are rejected
+pub const VERSION: u8 = 1;
+
+/// Writes the RRsets of the cache, the least recently used one first
+///
+/// After the magic and the version follows the number of RRsets and for
+/// each of them its name, type, class, whether it is negative, its expiry in
+/// seconds since the epoch and its records with name, type, class and
+/// rdata. Names and rdata are prefixed with their length, all numbers are
+/// big endian.
+pub fn write<W: Write>(cache: &Cache, writer: &mut W) -> io::Result<usize> {
+    writer.write_all(MAGIC)?;
+    writer.write_all(&[VERSION])?;
+    writer.write_all(&(cache.len() as u32).to_be_bytes())?;
+
+    for (key, entry) in cache.iter() {
+        let expires = entry
+            .expires
+            .duration_since(UNIX_EPOCH)
+            .map(|x| x.as_secs())
+            .unwrap_or_default();
+
+        write_bytes(writer, key.name.as_bytes())?;
+        writer.write_all(&u16::from(key.rtype).to_be_bytes())?;
+        writer.write_all(&u16::from(key.rclass).to_be_bytes())?;
+        writer.write_all(&[entry.negative as u8])?;
+        writer.write_all(&expires.to_be_bytes())?;
+        writer.write_all(&(entry.records.len() as u16).to_be_bytes())?;
+        for record in entry.records.iter() {
+            write_bytes(writer, record.name.as_bytes())?;
+            writer.write_all(&u16::from(record.rtype).to_be_bytes())?;
+            writer.write_all(&u16::from(record.rclass).to_be_bytes())?;
+            write_bytes(writer, &record.rdata)?;
+        }
+    }
+    Ok(cache.len())
+}
+
+/// Restores the RRsets of a snapshot written by `write`, TTLs are reduced by
+/// the time that passed until `now` and expired RRsets are skipped
+///
+/// Returns the number of restored RRsets.
+pub fn read<R: Read>(cache: &mut Cache, reader: &mut R, now: SystemTime) -> Result<usize> {
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(RdnsError::InvalidSnapshot("not a snapshot".into()));
+    }
+    let version = read_u8(reader)?;
+    if version != VERSION {
+        return Err(RdnsError::InvalidSnapshot(format!(
+            "unsupported version {}",
+            version
+        )));
+    }
+
+    let mut restored = 0;
+    for _ in 0..read_u32(reader)? {
+        let name = read_string(reader)?;
+        let rtype = QType::from(read_u16(reader)?);
+        let rclass = QClass::from(read_u16(reader)?);
+        let negative = read_u8(reader)? != 0;
+        let expires = UNIX_EPOCH + Duration::from_secs(read_u64(reader)?);
+
+        let count = read_u16(reader)?;
+        let mut records = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let name = read_string(reader)?;
+            let rtype = QType::from(read_u16(reader)?);
+            let rclass = QClass::from(read_u16(reader)?);
+            let rdata = read_bytes(reader)?;
+            records.push(ResourceRecord {
+                name,
+                rtype,
+                rclass,
+                cache_flush: false,
+                ttl: 0,
+                rdlength: rdata.len() as u16,
+                rdata,
+            });
+        }
+
+        if expires > now && !records.is_empty() {
+            let key = CacheKey::new(&name, rtype, rclass);
+            cache.restore(key, records, negative, expires, now);
+            restored += 1;
+        }
+    }
+    Ok(restored)
+}
+
+/// Replaces the file with the snapshot, which is written to a temporary file
+/// first so that a crash never leaves half a snapshot behind
+pub fn save(path: &str, snapshot: &[u8]) -> Result<()> {
+    let temporary = format!("{}.tmp", path);
+    let mut file = File::create(&temporary)?;
+    file.write_all(snapshot)?;
+    file.sync_all()?;
+    fs::rename(&temporary, path)?;
+    Ok(())
+}
+
+/// Restores the snapshot of the file, a missing file restores nothing
+pub fn load(cache: &mut Cache, path: &str, now: SystemTime) -> Result<usize> {
+    let content = match fs::read(path) {
+        Ok(x) => x,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e.into()),
+    };
+    read(cache, &mut content.as_slice(), now)
+}
+
+fn write_bytes<W: Write>(writer: &mut W, bytes: &[u8]) -> io::Result<()> {
+    writer.write_all(&(bytes.len() as u16).to_be_bytes())?;
+    writer.write_all(bytes)
+}
+
+fn read_u8<R: Read>(reader: &mut R) -> io::Result<u8> {
+    let mut buf = [0u8; 1];
+    reader.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+fn read_u16<R: Read>(reader: &mut R) -> io::Result<u16> {
+    let mut buf = [0u8; 2];
+    reader.read_exact(&mut buf)?;
+    Ok(u16::from_be_bytes(buf))
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_be_bytes(buf))
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> io::Result<u64> {
+    let mut buf = [0u8; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(u64::from_be_bytes(buf))
+}
+
+fn read_bytes<R: Read>(reader: &mut R) -> io::Result<Vec<u8>> {
+    let mut buf = vec![0u8; read_u16(reader)? as usize];
+    reader.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+fn read_string<R: Read>(reader: &mut R) -> Result<String> {
+    String::from_utf8(read_bytes(reader)?)
+        .map_err(|_| RdnsError::InvalidSnapshot("name is not UTF-8".into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::tests::{question, record};
+    use crate::config::CacheConfig;
+
+    #[test]
+    pub fn test_roundtrip() {
+        // whole seconds, as the snapshot does not keep fractions
+        let now = UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+        let mut cache = Cache::new(&CacheConfig::default());
+        cache.insert(
+            &[
+                record("www.example.com", QType::A, 60, vec![192, 0, 2, 1]),
+                record("www.example.com", QType::A, 60, vec![192, 0, 2, 2]),
+                record("short.example.com", QType::A, 10, vec![192, 0, 2, 3]),
+                record("x.example.com", QType::Unknown(65280), 600, vec![1, 2, 3]),
+            ],
+            now,
+        );
+
+        let mut snapshot = Vec::new();
+        assert_eq!(write(&cache, &mut snapshot).unwrap(), 3);
+
+        // the daemon was down for 20 seconds
+        let later = now + Duration::from_secs(20);
+        let mut restored = Cache::new(&CacheConfig::default());
+        assert_eq!(
+            read(&mut restored, &mut snapshot.as_slice(), later).unwrap(),
+            2
+        );
+        assert_eq!(restored.len(), 2);
+
+        let answer = restored
+            .lookup(&question("www.example.com", QType::A), later)
+            .unwrap();
+        assert_eq!(answer.answers.len(), 2);
+        assert!(answer.answers.iter().all(|x| x.ttl == 40));
+        let answer = restored
+            .lookup(&question("x.example.com", QType::Unknown(65280)), later)
+            .unwrap();
+        assert_eq!(answer.answers[0].rdata, vec![1, 2, 3]);
+        assert_eq!(answer.answers[0].ttl, 580);
+    }
+
+    #[test]
+    pub fn test_invalid() {
+        let mut cache = Cache::new(&CacheConfig::default());
+        let now = SystemTime::now();
+
+        match read(&mut cache, &mut &b"PCAP\x01\0\0\0\0"[..], now) {
+            Err(RdnsError::InvalidSnapshot(_)) => (),
+            x => panic!("Unexpected result {:?}", x),
+        }
+        match read(&mut cache, &mut &b"RDNS\x02\0\0\0\0"[..], now) {
+            Err(RdnsError::InvalidSnapshot(x)) => assert!(x.contains("version 2")),
+            x => panic!("Unexpected result {:?}", x),
+        }
+        // one RRset is announced, but missing
+        assert!(read(&mut cache, &mut &b"RDNS\x01\0\0\0\x01"[..], now).is_err());
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    pub fn test_save_load() {
+        let path = std::env::temp_dir().join(format!("rdns-snapshot-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        let now = SystemTime::now();
+        let mut cache = Cache::new(&CacheConfig::default());
+
+        // nothing to restore yet
+        assert_eq!(load(&mut cache, path, now).unwrap(), 0);
+
+        cache.insert(
+            &[record("www.example.com", QType::A, 60, vec![192, 0, 2, 1])],
+            now,
+        );
+        let mut snapshot = Vec::new();
+        write(&cache, &mut snapshot).unwrap();
+        save(path, &snapshot).unwrap();
+
+        let mut restored = Cache::new(&CacheConfig::default());
+        assert_eq!(load(&mut restored, path, now).unwrap(), 1);
+        std::fs::remove_file(path).unwrap();
+    }
+}
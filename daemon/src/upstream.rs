@@ -0,0 +1,241 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Every n-th request of the `fastest` strategy goes to the least used
+/// server first, so that its round trip time stays up to date
+pub const EXPLORATION_RATE: u64 = 20;
+/// Number of servers statistics are kept for, beyond it the least recently
+/// used one is dropped, as recursion queries an unbounded set of servers
+pub const MAX_SERVERS: usize = 1024;
+
+/// Order in which the upstream servers are queried
+#[derive(Copy, Clone, Debug, Hash, Eq, PartialEq)]
+pub enum Strategy {
+    /// Always in the configured order
+    Sequential,
+    /// Every request starts with the next server
+    RoundRobin,
+    /// Lowest smoothed round trip time first
+    Fastest,
+    /// All servers at once, the first good answer wins
+    Race,
+}
+
+impl FromStr for Strategy {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "sequential" => Ok(Strategy::Sequential),
+            "round-robin" => Ok(Strategy::RoundRobin),
+            "fastest" => Ok(Strategy::Fastest),
+            "race" => Ok(Strategy::Race),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct UpstreamStats {
+    pub queries: u64,
+    pub answers: u64,
+    /// Failed sends, timeouts and SERVFAIL or REFUSED answers
+    pub failures: u64,
+    /// Smoothed round trip time (RFC 6298 2), `None` until the first answer
+    pub srtt: Option<Duration>,
+    /// Value of the use counter at the last update
+    used: u64,
+}
+
+impl UpstreamStats {
+    fn add_sample(&mut self, rtt: Duration) {
+        self.srtt = Some(match self.srtt {
+            Some(x) => (x * 7 + rtt) / 8,
+            None => rtt,
+        });
+    }
+}
+
+/// Selects the upstream servers of new requests and keeps their statistics
+pub struct Upstreams {
+    pub strategy: Strategy,
+    pub stats: HashMap<SocketAddr, UpstreamStats>,
+    /// Number of requests an order was selected for
+    requests: u64,
+    /// Incremented on every update of the statistics
+    uses: u64,
+}
+
+impl Upstreams {
+    pub fn new(strategy: Strategy) -> Self {
+        Self {
+            strategy,
+            stats: HashMap::new(),
+            requests: 0,
+            uses: 0,
+        }
+    }
+
+    /// Order in which the servers are tried for a new request
+    pub fn order(&mut self, servers: &[SocketAddr]) -> Vec<SocketAddr> {
+        let mut order = servers.to_vec();
+        let requests = self.requests;
+        self.requests += 1;
+        if order.is_empty() {
+            return order;
+        }
+
+        match self.strategy {
+            Strategy::Sequential | Strategy::Race => (),
+            Strategy::RoundRobin => order.rotate_left((requests % servers.len() as u64) as usize),
+            Strategy::Fastest => {
+                // servers without an answer yet come first
+                order.sort_by_key(|x| self.stats.get(x).and_then(|x| x.srtt));
+
+                if requests % EXPLORATION_RATE == EXPLORATION_RATE - 1 {
+                    let least_used = (0..order.len())
+                        .min_by_key(|i| self.stats.get(&order[*i]).map_or(0, |x| x.queries))
+                        .unwrap_or(0);
+                    let server = order.remove(least_used);
+                    order.insert(0, server);
+                }
+            }
+        }
+        order
+    }
+
+    pub fn sent(&mut self, server: SocketAddr) {
+        self.entry(server).queries += 1;
+    }
+
+    pub fn answered(&mut self, server: SocketAddr, rtt: Duration) {
+        let stats = self.entry(server);
+        stats.answers += 1;
+        stats.add_sample(rtt);
+    }
+
+    pub fn failed(&mut self, server: SocketAddr) {
+        self.entry(server).failures += 1;
+    }
+
+    /// The server did not answer within `elapsed`, which is taken as round
+    /// trip time so that slow servers move to the back
+    pub fn timed_out(&mut self, server: SocketAddr, elapsed: Duration) {
+        let stats = self.entry(server);
+        stats.failures += 1;
+        stats.add_sample(elapsed);
+    }
+
+    /// Statistics of the server, a new server replaces the least recently
+    /// used one once `MAX_SERVERS` are known
+    fn entry(&mut self, server: SocketAddr) -> &mut UpstreamStats {
+        if !self.stats.contains_key(&server) && self.stats.len() >= MAX_SERVERS {
+            let oldest = self
+                .stats
+                .iter()
+                .min_by_key(|(_, x)| x.used)
+                .map(|(x, _)| *x);
+            if let Some(x) = oldest {
+                self.stats.remove(&x);
+            }
+        }
+
+        self.uses += 1;
+        let stats = self.stats.entry(server).or_default();
+        stats.used = self.uses;
+        stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn servers() -> Vec<SocketAddr> {
+        vec![
+            "10.0.0.1:53".parse().unwrap(),
+            "10.0.0.2:53".parse().unwrap(),
+            "10.0.0.3:53".parse().unwrap(),
+        ]
+    }
+
+    #[test]
+    pub fn test_order() {
+        let servers = servers();
+
+        let mut upstreams = Upstreams::new(Strategy::Sequential);
+        assert_eq!(upstreams.order(&servers), servers);
+        assert_eq!(upstreams.order(&servers), servers);
+
+        let mut upstreams = Upstreams::new(Strategy::RoundRobin);
+        let first = (0..4)
+            .map(|_| upstreams.order(&servers)[0])
+            .collect::<Vec<SocketAddr>>();
+        assert_eq!(first, vec![servers[0], servers[1], servers[2], servers[0]]);
+        assert!(upstreams.order(&[]).is_empty());
+
+        assert_eq!(Strategy::from_str("round-robin"), Ok(Strategy::RoundRobin));
+        assert!(Strategy::from_str("random").is_err());
+    }
+
+    #[test]
+    pub fn test_fastest() {
+        let servers = servers();
+        let mut upstreams = Upstreams::new(Strategy::Fastest);
+
+        upstreams.sent(servers[0]);
+        upstreams.answered(servers[0], Duration::from_millis(80));
+        upstreams.sent(servers[1]);
+        upstreams.answered(servers[1], Duration::from_millis(20));
+        // unknown servers are tried first
+        assert_eq!(
+            upstreams.order(&servers),
+            vec![servers[2], servers[1], servers[0]]
+        );
+
+        upstreams.sent(servers[2]);
+        upstreams.timed_out(servers[2], Duration::from_millis(500));
+        assert_eq!(
+            upstreams.order(&servers),
+            vec![servers[1], servers[0], servers[2]]
+        );
+        assert_eq!(upstreams.stats[&servers[2]].failures, 1);
+
+        // a single slow answer does not outweigh the history
+        for _ in 0..10 {
+            upstreams.sent(servers[1]);
+            upstreams.answered(servers[1], Duration::from_millis(20));
+        }
+        upstreams.answered(servers[1], Duration::from_millis(180));
+        assert_eq!(
+            upstreams.stats[&servers[1]].srtt,
+            Some(Duration::from_millis(40))
+        );
+
+        // from time to time the least used server is explored
+        let first = (2..EXPLORATION_RATE - 1)
+            .map(|_| upstreams.order(&servers)[0])
+            .collect::<Vec<SocketAddr>>();
+        assert!(first.iter().all(|x| *x == servers[1]));
+        assert_eq!(upstreams.order(&servers)[0], servers[0]);
+    }
+
+    #[test]
+    pub fn test_max_servers() {
+        let servers = servers();
+        let mut upstreams = Upstreams::new(Strategy::Fastest);
+        upstreams.sent(servers[0]);
+        for port in 0..MAX_SERVERS as u16 {
+            upstreams.sent(SocketAddr::from(([10, 0, 1, 1], port)));
+            upstreams.sent(servers[0]);
+        }
+
+        assert_eq!(upstreams.stats.len(), MAX_SERVERS);
+        assert_eq!(upstreams.stats[&servers[0]].queries, MAX_SERVERS as u64 + 1);
+        assert!(!upstreams
+            .stats
+            .contains_key(&SocketAddr::from(([10, 0, 1, 1], 0))));
+    }
+}
@@ -1,10 +1,13 @@
+mod edns;
 mod opcode;
 mod rcode;
 
+pub use self::edns::*;
 pub use self::opcode::*;
 pub use self::rcode::*;
 
 use crate::error::*;
+use crate::mdns::CLASS_FLAG;
 use crate::qclass::{as_u16 as qclass_as_u16, QClass};
 use crate::qtype::{as_u16 as qtype_as_u16, QType};
 use crate::reader::*;
@@ -17,6 +20,8 @@ pub struct Question {
     pub qname: String,
     pub qtype: QType,
     pub qclass: QClass,
+    /// mDNS only, the querier prefers a unicast response (RFC 6762 5.4)
+    pub unicast_response: bool,
 }
 
 #[derive(Clone, Debug, Hash, Eq, PartialEq)]
@@ -24,11 +29,197 @@ pub struct ResourceRecord {
     pub name: String,
     pub rtype: QType,
     pub rclass: QClass,
+    /// mDNS only, the record replaces all cached records of the same name,
+    /// type and class (RFC 6762 10.2)
+    pub cache_flush: bool,
     pub ttl: u32,
     pub rdlength: u16,
     pub rdata: Vec<u8>,
 }
 
+impl ResourceRecord {
+    /// Creates a PTR record pointing from `name` to `target`
+    pub fn ptr(name: String, target: &str, ttl: u32) -> Self {
+        Self::with_target(name, QType::PTR, target, ttl)
+    }
+
+    /// Creates a CNAME record aliasing `name` to `target`
+    pub fn cname(name: String, target: &str, ttl: u32) -> Self {
+        Self::with_target(name, QType::CNAME, target, ttl)
+    }
+
+    /// Creates an NS record delegating the zone `name` to `target`
+    pub fn ns(name: String, target: &str, ttl: u32) -> Self {
+        Self::with_target(name, QType::NS, target, ttl)
+    }
+
+    /// Creates a DNAME record redirecting the names below `name` to
+    /// `target`
+    pub fn dname(name: String, target: &str, ttl: u32) -> Self {
+        Self::with_target(name, QType::DNAME, target, ttl)
+    }
+
+    fn with_target(name: String, rtype: QType, target: &str, ttl: u32) -> Self {
+        let rdata = Writer::new().write_name_uncompressed(target).build();
+
+        Self {
+            name,
+            rtype,
+            rclass: QClass::IN,
+            cache_flush: false,
+            ttl,
+            rdlength: rdata.len() as u16,
+            rdata,
+        }
+    }
+
+    /// TTL of negative answers proved by this SOA record, the lower of its
+    /// TTL and the MINIMUM field (RFC 2308 5)
+    pub fn negative_ttl(&self) -> Option<u32> {
+        if self.rtype != QType::SOA || self.rdata.len() < 4 {
+            return None;
+        }
+
+        // MINIMUM is the last field, names are stored uncompressed
+        let minimum = &self.rdata[self.rdata.len() - 4..];
+        let minimum = u32::from_be_bytes([minimum[0], minimum[1], minimum[2], minimum[3]]);
+        Some(self.ttl.min(minimum))
+    }
+
+    /// Name the RDATA of a CNAME, DNAME, NS or PTR record points to
+    pub fn target(&self) -> Option<String> {
+        match self.rtype {
+            QType::CNAME | QType::DNAME | QType::NS | QType::PTR => {
+                read_name(&mut Cursor::new(self.rdata.as_slice())).ok()
+            }
+            _ => None,
+        }
+    }
+
+    fn parse(reader: &mut Cursor<&[u8]>, name: String, rtype: u16) -> Result<Self> {
+        let rtype = QType::from(rtype);
+        let (rclass, cache_flush) = split_class(reader.read_u16()?);
+        let ttl = reader.read_u32()?;
+        let rdlength = reader.read_u16()?;
+
+        let layout = rdata_layout(rtype);
+        let rdata = if layout.is_empty() {
+            reader.read_length(rdlength as usize)?
+        } else {
+            // names may point anywhere into the message, store them
+            // uncompressed so the RDATA stands on its own
+            let end = reader.position() + u64::from(rdlength);
+            let mut writer = Writer::new();
+            for part in layout {
+                writer = match part {
+                    RdataPart::Name => writer.write_name_uncompressed(&read_name(reader)?),
+                    RdataPart::U16 => writer.write_u16_be(reader.read_u16()?),
+                    RdataPart::U32 => writer.write_u32_be(reader.read_u32()?),
+                };
+            }
+
+            if reader.position() != end {
+                return Err(DnsParseError::InvalidRdata);
+            }
+            writer.build()
+        };
+
+        Ok(Self {
+            name,
+            rtype,
+            rclass,
+            cache_flush,
+            ttl,
+            rdlength: rdata.len() as u16,
+            rdata,
+        })
+    }
+
+    fn write(self, writer: Writer) -> Writer {
+        let writer = writer
+            .write_name(&self.name)
+            .write_u16_be(qtype_as_u16(self.rtype))
+            .write_u16_be(join_class(self.rclass, self.cache_flush))
+            .write_u32_be(self.ttl);
+
+        let length_position = writer.position();
+        let mut writer = writer.write_u16_be(0);
+
+        // RDATA that can not be read on its own, like RDATA that still
+        // contains compression pointers, is copied as it is
+        let layout = rdata_layout(self.rtype);
+        let mut rdata = Cursor::new(self.rdata.as_slice());
+        let compressible = !layout.is_empty()
+            && layout.iter().all(|x| match x {
+                RdataPart::Name => read_name(&mut rdata).is_ok(),
+                RdataPart::U16 => rdata.read_u16().is_ok(),
+                RdataPart::U32 => rdata.read_u32().is_ok(),
+            })
+            && rdata.position() as usize == self.rdata.len();
+
+        if compressible {
+            let mut rdata = Cursor::new(self.rdata.as_slice());
+            for part in layout {
+                writer = match part {
+                    RdataPart::Name => {
+                        writer.write_name(&read_name(&mut rdata).unwrap_or_default())
+                    }
+                    RdataPart::U16 => writer.write_u16_be(rdata.read_u16().unwrap_or_default()),
+                    RdataPart::U32 => writer.write_u32_be(rdata.read_u32().unwrap_or_default()),
+                };
+            }
+        } else {
+            writer = writer.write_vec(self.rdata);
+        }
+
+        let length = writer.position() - length_position - 2;
+        writer.replace_u16_be(length_position, length as u16)
+    }
+}
+
+/// Separates the class from the mDNS flag in its top bit
+pub(crate) fn split_class(value: u16) -> (QClass, bool) {
+    (QClass::from(value & !CLASS_FLAG), value & CLASS_FLAG != 0)
+}
+
+pub(crate) fn join_class(class: QClass, flag: bool) -> u16 {
+    qclass_as_u16(class) | if flag { CLASS_FLAG } else { 0 }
+}
+
+/// Field of an RDATA that contains domain names
+pub(crate) enum RdataPart {
+    Name,
+    U16,
+    U32,
+}
+
+/// Layout of the RDATA of all types that are allowed to contain compressed
+/// names (RFC 3597 4), empty for every other type
+pub(crate) fn rdata_layout(rtype: QType) -> &'static [RdataPart] {
+    match rtype {
+        QType::NS
+        | QType::MD
+        | QType::MF
+        | QType::CNAME
+        | QType::MB
+        | QType::MG
+        | QType::MR
+        | QType::PTR => &[RdataPart::Name],
+        QType::MINFO => &[RdataPart::Name, RdataPart::Name],
+        QType::MX => &[RdataPart::U16, RdataPart::Name],
+        QType::SOA => &[
+            RdataPart::Name,
+            RdataPart::Name,
+            RdataPart::U32,
+            RdataPart::U32,
+            RdataPart::U32,
+            RdataPart::U32,
+            RdataPart::U32,
+        ],
+        _ => &[],
+    }
+}
+
 #[derive(Clone, Debug, Default, Hash, Eq, PartialEq)]
 pub struct DNS {
     pub id: u16,
@@ -39,105 +230,88 @@ pub struct DNS {
     pub rd: u8,
     pub ra: u8,
     pub z: u8,
+    /// Authentic data, all records were validated with DNSSEC
+    pub ad: u8,
+    /// Checking disabled, the requester does not want DNSSEC validation
+    pub cd: u8,
     pub rcode: Rcode,
     pub qdcount: u16,
     pub ancount: u16,
-    pub nscount: u16, // currently ignored
-    pub arcount: u16, // currently ignored
+    pub nscount: u16,
+    pub arcount: u16,
     pub questions: Vec<Question>,
+    /// Records of the answer section
     pub resource_records: Vec<ResourceRecord>,
+    /// Records of the authority section
+    pub authorities: Vec<ResourceRecord>,
+    /// Records of the additional section, without the OPT record
+    pub additionals: Vec<ResourceRecord>,
+    /// Content of the OPT record, if the message contained one
+    pub edns: Option<Edns>,
 }
 
 impl DNS {
     pub fn parse(byte_arr: Vec<u8>) -> Result<Self> {
-        let mut protocol = Cursor::new(byte_arr.clone());
-
-        let mut reader = Reader::new(&byte_arr);
-        let id = protocol.read_u16()?;
+        let mut reader = Cursor::new(byte_arr.as_slice());
+        let id = reader.read_u16()?;
 
-        let flags = protocol.read_binary()?;
+        let flags = reader.read_binary()?;
         let qr = flags[0];
         let opcode = Opcode::from(&flags[1..=4]);
         let aa = flags[5];
         let tc = flags[6];
         let rd = flags[7];
 
-        let flags = protocol.read_binary()?;
+        let flags = reader.read_binary()?;
         let ra = flags[0];
+        let z = flags[1];
+        let ad = flags[2];
+        let cd = flags[3];
         let rcode = Rcode::from(&flags[4..=7]);
-        let z = 0;
 
-        let qdcount = protocol.read_u16()?;
-        let ancount = protocol.read_u16()?;
-        let nscount = protocol.read_u16()?;
-        let arcount = protocol.read_u16()?;
+        let qdcount = reader.read_u16()?;
+        let ancount = reader.read_u16()?;
+        let nscount = reader.read_u16()?;
+        let arcount = reader.read_u16()?;
 
         let mut questions = Vec::with_capacity(1);
         for _ in 0..qdcount {
-            let mut domain = Vec::with_capacity(3);
-            let mut qname_length = protocol.read_u8()?;
-
-            while qname_length != 0 {
-                let qname = protocol.read_length(qname_length as usize)?;
-                domain.push(qname);
-                qname_length = protocol.read_u8()?;
-            }
-
-            let qname = String::from_utf8(domain.join(&46)).map_err(DnsParseError::StringParseError)?;
-            let qtype = QType::from(protocol.read_u16()?);
-            let qclass = QClass::from(protocol.read_u16()?);
+            let qname = read_name(&mut reader)?;
+            let qtype = QType::from(reader.read_u16()?);
+            let (qclass, unicast_response) = split_class(reader.read_u16()?);
 
             questions.push(Question {
                 qname,
                 qtype,
                 qclass,
+                unicast_response,
             });
         }
 
-        let mut resource_records = Vec::new();
-        if qr == 1 {
-            dbg!(protocol);
-            for _ in 0..ancount {
-                let mut name = Vec::new();
-                let name_offset = reader.read_u8()?;
-                let mut currrent_position = 0;
-
-                if name_offset == 192 {
-                    let offset = reader.read_u8()?;
-                    currrent_position = reader.position();
-                    reader.set_position(u64::from(offset));
-
-                    let mut next_length = reader.read_u8()?;
-                    while next_length != 0 {
-                        let mut rname = reader.read_length(next_length as usize)?;
-                        name.append(&mut rname);
-                        next_length = reader.read_u8()?;
-
-                        if next_length != 0 {
-                            name.push(46);
-                        }
-                    }
-                }
-
-                reader.set_position(currrent_position);
+        let mut resource_records = Vec::with_capacity(ancount as usize);
+        for _ in 0..ancount {
+            let name = read_name(&mut reader)?;
+            let rtype = reader.read_u16()?;
+            resource_records.push(ResourceRecord::parse(&mut reader, name, rtype)?);
+        }
 
-                let rtype = crate::qtype::as_qtype(reader.read_u16_be()?);
-                let rclass = crate::qclass::as_qclass(reader.read_u16_be()?);
+        let mut authorities = Vec::with_capacity(nscount as usize);
+        for _ in 0..nscount {
+            let name = read_name(&mut reader)?;
+            let rtype = reader.read_u16()?;
+            authorities.push(ResourceRecord::parse(&mut reader, name, rtype)?);
+        }
 
-                let ttl = reader.read_u32_be()?;
-                let rdlength = reader.read_u16_be()?;
-                let rdata = reader.read_length(rdlength as usize)?;
+        let mut additionals = Vec::new();
+        let mut edns = None;
+        for _ in 0..arcount {
+            let name = read_name(&mut reader)?;
+            let rtype = reader.read_u16()?;
 
-                let resource_record = ResourceRecord {
-                    name: String::from_utf8(name).map_err(DnsParseError::StringParseError)?,
-                    rtype,
-                    rclass,
-                    ttl,
-                    rdlength,
-                    rdata,
-                };
-
-                resource_records.push(resource_record);
+            if rtype == OPT {
+                edns = Some(Edns::parse(&mut reader)?);
+            } else {
+                additionals.push(ResourceRecord::parse(&mut reader, name, rtype)?);
             }
         }
 
@@ -150,6 +324,8 @@ impl DNS {
             rd,
             ra,
             z,
+            ad,
+            cd,
             rcode,
             qdcount,
             ancount,
@@ -157,78 +333,108 @@ impl DNS {
             arcount,
             questions,
             resource_records,
+            authorities,
+            additionals,
+            edns,
         })
     }
 
     pub fn build(self) -> Vec<u8> {
         let opcode: &[u8] = self.opcode.into();
-        //let opcode = u8_to_four_bit(self.opcode);
         let flags = [
             self.qr, opcode[0], opcode[1], opcode[2], opcode[3], self.aa, self.tc, self.rd,
         ];
 
         let rcode: &[u8] = self.rcode.into();
-        let flags2 = [self.ra, 0, 0, 0, rcode[0], rcode[1], rcode[2], rcode[3]];
+        let flags2 = [
+            self.ra, self.z, self.ad, self.cd, rcode[0], rcode[1], rcode[2], rcode[3],
+        ];
 
-        let writer = Writer::with_capacity(128)
+        let arcount = self.additionals.len() + self.edns.iter().count();
+        let mut writer = Writer::with_capacity(512)
             .write_u16_be(self.id)
             .write_binary_as_u8(flags)
             .write_binary_as_u8(flags2)
             .write_u16_be(self.questions.len() as u16)
             .write_u16_be(self.resource_records.len() as u16)
-            .write_u16_be(0) // nscount
-            .write_u16_be(0); // arcount
+            .write_u16_be(self.authorities.len() as u16)
+            .write_u16_be(arcount as u16);
 
-        let mut questions = Vec::new();
         for question in self.questions {
-            let splitted_qname = question.qname.split('.').collect::<Vec<&str>>();
+            writer = writer
+                .write_name(&question.qname)
+                .write_u16_be(qtype_as_u16(question.qtype))
+                .write_u16_be(join_class(question.qclass, question.unicast_response));
+        }
 
-            for name in splitted_qname {
-                questions.push(name.len() as u8);
-                questions.append(&mut name.as_bytes().to_vec());
-            }
+        for resource in self
+            .resource_records
+            .into_iter()
+            .chain(self.authorities)
+            .chain(self.additionals)
+        {
+            writer = resource.write(writer);
+        }
 
-            let mut writer = Writer::new()
-                .write_u8(0) // name is done
-                .write_u16_be(qtype_as_u16(question.qtype))
-                .write_u16_be(qclass_as_u16(question.qclass))
-                .build();
-            questions.append(&mut writer);
+        if let Some(edns) = self.edns {
+            writer = edns.write(writer);
         }
 
-        let mut position_question = writer.position();
-        let writer = writer.write_vec(questions);
-
-        if !self.resource_records.is_empty() {
-            let mut responses = Vec::new();
-            for resource in self.resource_records {
-                let mut writer = Writer::new()
-                    .write_u8(192)
-                    .write_u8(position_question as u8)
-                    .write_u16_be(qtype_as_u16(resource.rtype))
-                    .write_u16_be(qclass_as_u16(resource.rclass))
-                    .write_u32_be(resource.ttl)
-                    .write_u16_be(resource.rdlength)
-                    .write_vec(resource.rdata)
-                    .build();
-                responses.append(&mut writer);
-
-                if resource.rtype == QType::CNAME {
-                    position_question += 4; // ignore www.
-                }
-            }
+        writer.build()
+    }
 
-            writer.write_vec(responses).build()
-        } else {
-            writer.build()
+    /// Largest UDP response the sender of this query accepts, at most
+    /// `MAX_UDP_SIZE`
+    pub fn max_udp_size(&self) -> usize {
+        match &self.edns {
+            Some(x) => (x.udp_payload_size as usize).clamp(MIN_UDP_SIZE, MAX_UDP_SIZE),
+            None => MIN_UDP_SIZE,
+        }
+    }
+
+    /// Builds the message, dropping records that exceed `max_size` bytes
+    ///
+    /// Additional records are dropped first. If the message still does not
+    /// fit, the answer and authority sections are dropped as well and TC is
+    /// set (RFC 2181 9).
+    pub fn build_truncated(mut self, max_size: usize) -> Vec<u8> {
+        let message = self.clone().build();
+        if message.len() <= max_size {
+            return message;
+        }
+
+        self.additionals.clear();
+        let message = self.clone().build();
+        if message.len() <= max_size {
+            return message;
         }
+
+        self.tc = 1;
+        self.resource_records.clear();
+        self.authorities.clear();
+        self.build()
+    }
+
+    /// All extended errors of the OPT record, empty if there is none
+    pub fn extended_errors(&self) -> Vec<&ExtendedError> {
+        self.edns
+            .as_ref()
+            .map(|x| x.extended_errors())
+            .unwrap_or_default()
+    }
+
+    /// Attaches an extended error, adding an OPT record if necessary
+    pub fn add_extended_error(&mut self, error: ExtendedError) {
+        self.edns
+            .get_or_insert_with(Edns::default)
+            .options
+            .push(EdnsOption::ExtendedError(error));
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use hex;
 
     #[test]
     pub fn test_parse_query_google() {
@@ -247,6 +453,8 @@ mod tests {
                 rd: 1,
                 ra: 0,
                 z: 0,
+                ad: 0,
+                cd: 0,
                 rcode: Rcode::NoError,
                 qdcount: 1,
                 ancount: 0,
@@ -255,9 +463,13 @@ mod tests {
                 questions: vec![Question {
                     qname: String::from("www.google.de"),
                     qtype: QType::A,
-                    qclass: QClass::IN
+                    qclass: QClass::IN,
+                    unicast_response: false
                 }],
-                resource_records: Vec::new()
+                resource_records: Vec::new(),
+                authorities: Vec::new(),
+                additionals: Vec::new(),
+                edns: None
             }
         );
     }
@@ -279,6 +491,8 @@ mod tests {
                 rd: 1,
                 ra: 1,
                 z: 0,
+                ad: 0,
+                cd: 0,
                 rcode: Rcode::NoError,
                 qdcount: 1,
                 ancount: 1,
@@ -287,16 +501,21 @@ mod tests {
                 questions: vec![Question {
                     qname: String::from("www.google.de"),
                     qtype: QType::A,
-                    qclass: QClass::IN
+                    qclass: QClass::IN,
+                    unicast_response: false
                 }],
                 resource_records: vec![ResourceRecord {
                     name: String::from("www.google.de"),
                     rtype: QType::A,
                     rclass: QClass::IN,
+                    cache_flush: false,
                     ttl: 238,
                     rdlength: 4,
                     rdata: vec![172, 217, 168, 195]
-                }]
+                }],
+                authorities: Vec::new(),
+                additionals: Vec::new(),
+                edns: None
             }
         );
     }
@@ -312,6 +531,8 @@ mod tests {
             rd: 1,
             ra: 0,
             z: 0,
+            ad: 0,
+            cd: 0,
             rcode: Rcode::NoError,
             qdcount: 1,
             ancount: 0,
@@ -321,8 +542,12 @@ mod tests {
                 qname: String::from("www.google.de"),
                 qtype: QType::A,
                 qclass: QClass::IN,
+                unicast_response: false,
             }],
             resource_records: Vec::new(),
+            authorities: Vec::new(),
+            additionals: Vec::new(),
+            edns: None,
         }
         .build();
 
@@ -342,6 +567,8 @@ mod tests {
             rd: 1,
             ra: 1,
             z: 0,
+            ad: 0,
+            cd: 0,
             rcode: Rcode::NoError,
             qdcount: 1,
             ancount: 1,
@@ -351,15 +578,20 @@ mod tests {
                 qname: String::from("www.google.de"),
                 qtype: QType::A,
                 qclass: QClass::IN,
+                unicast_response: false,
             }],
             resource_records: vec![ResourceRecord {
                 name: String::from("www.google.de"),
                 rtype: QType::A,
                 rclass: QClass::IN,
+                cache_flush: false,
                 ttl: 238,
                 rdlength: 4,
                 rdata: vec![172, 217, 168, 195],
             }],
+            authorities: Vec::new(),
+            additionals: Vec::new(),
+            edns: None,
         }
         .build();
 
@@ -385,6 +617,8 @@ mod tests {
                 rd: 1,
                 ra: 0,
                 z: 0,
+                ad: 0,
+                cd: 0,
                 rcode: Rcode::NoError,
                 qdcount: 1,
                 ancount: 0,
@@ -393,9 +627,13 @@ mod tests {
                 questions: vec![Question {
                     qname: String::from("www.github.com"),
                     qtype: QType::A,
-                    qclass: QClass::IN
+                    qclass: QClass::IN,
+                    unicast_response: false
                 }],
-                resource_records: Vec::new()
+                resource_records: Vec::new(),
+                authorities: Vec::new(),
+                additionals: Vec::new(),
+                edns: None
             }
         );
     }
@@ -417,6 +655,8 @@ mod tests {
                 rd: 1,
                 ra: 1,
                 z: 0,
+                ad: 0,
+                cd: 0,
                 rcode: Rcode::NoError,
                 qdcount: 1,
                 ancount: 3,
@@ -425,21 +665,25 @@ mod tests {
                 questions: vec![Question {
                     qname: String::from("www.github.com"),
                     qtype: QType::A,
-                    qclass: QClass::IN
+                    qclass: QClass::IN,
+                    unicast_response: false
                 }],
                 resource_records: vec![
                     ResourceRecord {
                         name: String::from("www.github.com"),
                         rtype: QType::CNAME,
                         rclass: QClass::IN,
+                        cache_flush: false,
                         ttl: 1171,
-                        rdlength: 2,
-                        rdata: vec![192, 16]
+                        rdlength: 12,
+                        // compressed names are stored uncompressed
+                        rdata: vec![6, 103, 105, 116, 104, 117, 98, 3, 99, 111, 109, 0]
                     },
                     ResourceRecord {
                         name: String::from("github.com"),
                         rtype: QType::A,
                         rclass: QClass::IN,
+                        cache_flush: false,
                         ttl: 59,
                         rdlength: 4,
                         rdata: vec![192, 30, 253, 113]
@@ -448,11 +692,15 @@ mod tests {
                         name: String::from("github.com"),
                         rtype: QType::A,
                         rclass: QClass::IN,
+                        cache_flush: false,
                         ttl: 59,
                         rdlength: 4,
                         rdata: vec![192, 30, 253, 112]
                     }
-                ]
+                ],
+                authorities: Vec::new(),
+                additionals: Vec::new(),
+                edns: None
             }
         );
     }
@@ -468,6 +716,8 @@ mod tests {
             rd: 1,
             ra: 0,
             z: 0,
+            ad: 0,
+            cd: 0,
             rcode: Rcode::NoError,
             qdcount: 1,
             ancount: 0,
@@ -477,8 +727,12 @@ mod tests {
                 qname: String::from("www.github.com"),
                 qtype: QType::A,
                 qclass: QClass::IN,
+                unicast_response: false,
             }],
             resource_records: Vec::new(),
+            authorities: Vec::new(),
+            additionals: Vec::new(),
+            edns: None,
         }
         .build();
 
@@ -498,6 +752,8 @@ mod tests {
             rd: 1,
             ra: 1,
             z: 0,
+            ad: 0,
+            cd: 0,
             rcode: Rcode::NoError,
             qdcount: 1,
             ancount: 3,
@@ -507,12 +763,14 @@ mod tests {
                 qname: String::from("www.github.com"),
                 qtype: QType::A,
                 qclass: QClass::IN,
+                unicast_response: false,
             }],
             resource_records: vec![
                 ResourceRecord {
                     name: String::from("www.github.com"),
                     rtype: QType::CNAME,
                     rclass: QClass::IN,
+                    cache_flush: false,
                     ttl: 1171,
                     rdlength: 2,
                     rdata: vec![192, 16],
@@ -521,6 +779,7 @@ mod tests {
                     name: String::from("github.com"),
                     rtype: QType::A,
                     rclass: QClass::IN,
+                    cache_flush: false,
                     ttl: 59,
                     rdlength: 4,
                     rdata: vec![192, 30, 253, 113],
@@ -529,11 +788,15 @@ mod tests {
                     name: String::from("github.com"),
                     rtype: QType::A,
                     rclass: QClass::IN,
+                    cache_flush: false,
                     ttl: 59,
                     rdlength: 4,
                     rdata: vec![192, 30, 253, 112],
                 },
             ],
+            authorities: Vec::new(),
+            additionals: Vec::new(),
+            edns: None,
         }
         .build();
 
@@ -542,6 +805,22 @@ mod tests {
         assert_eq!(hex_vec, hex_query);
     }
 
+    #[test]
+    pub fn test_build_response_github_compressed() {
+        let hex_response = "224c81800001000300000000037777770667697468756203636f6d0000010001c00c00050001000004930002c010c010000100010000003b0004c01efd71c010000100010000003b0004c01efd70";
+        let dns = DNS::parse(hex::decode(hex_response).unwrap()).unwrap();
+
+        // the uncompressed name in the CNAME is compressed again
+        assert_eq!(hex::encode(dns.build()), hex_response);
+    }
+
+    #[test]
+    pub fn test_parse_invalid_rdata() {
+        // CNAME with a RDLENGTH that does not match its name
+        let hex_response = "224c81800001000100000000037777770667697468756203636f6d0000010001c00c00050001000004930003c010";
+        assert!(DNS::parse(hex::decode(hex_response).unwrap()).is_err());
+    }
+
     #[test]
     pub fn test_parse_query_play_google_aaaa() {
         let hex_query = "8af00100000100000000000004706c617906676f6f676c6503636f6d00001c0001";
@@ -559,6 +838,8 @@ mod tests {
                 rd: 1,
                 ra: 0,
                 z: 0,
+                ad: 0,
+                cd: 0,
                 rcode: Rcode::NoError,
                 qdcount: 1,
                 ancount: 0,
@@ -567,9 +848,13 @@ mod tests {
                 questions: vec![Question {
                     qname: String::from("play.google.com"),
                     qtype: QType::AAAA,
-                    qclass: QClass::IN
+                    qclass: QClass::IN,
+                    unicast_response: false
                 }],
-                resource_records: Vec::new()
+                resource_records: Vec::new(),
+                authorities: Vec::new(),
+                additionals: Vec::new(),
+                edns: None
             }
         );
     }
@@ -591,6 +876,8 @@ mod tests {
                 rd: 1,
                 ra: 1,
                 z: 0,
+                ad: 0,
+                cd: 0,
                 rcode: Rcode::NoError,
                 qdcount: 1,
                 ancount: 1,
@@ -599,16 +886,21 @@ mod tests {
                 questions: vec![Question {
                     qname: String::from("play.google.com"),
                     qtype: QType::AAAA,
-                    qclass: QClass::IN
+                    qclass: QClass::IN,
+                    unicast_response: false
                 }],
                 resource_records: vec![ResourceRecord {
                     name: String::from("play.google.com"),
                     rtype: QType::AAAA,
                     rclass: QClass::IN,
+                    cache_flush: false,
                     ttl: 108,
                     rdlength: 16,
                     rdata: vec![42, 0, 20, 80, 64, 1, 8, 21, 0, 0, 0, 0, 0, 0, 32, 14]
-                }]
+                }],
+                authorities: Vec::new(),
+                additionals: Vec::new(),
+                edns: None
             }
         );
     }
@@ -624,6 +916,8 @@ mod tests {
             rd: 1,
             ra: 0,
             z: 0,
+            ad: 0,
+            cd: 0,
             rcode: Rcode::NoError,
             qdcount: 1,
             ancount: 0,
@@ -633,8 +927,12 @@ mod tests {
                 qname: String::from("play.google.com"),
                 qtype: QType::AAAA,
                 qclass: QClass::IN,
+                unicast_response: false,
             }],
             resource_records: Vec::new(),
+            authorities: Vec::new(),
+            additionals: Vec::new(),
+            edns: None,
         }
         .build();
 
@@ -654,6 +952,8 @@ mod tests {
             rd: 1,
             ra: 1,
             z: 0,
+            ad: 0,
+            cd: 0,
             rcode: Rcode::NoError,
             qdcount: 1,
             ancount: 1,
@@ -663,15 +963,20 @@ mod tests {
                 qname: String::from("play.google.com"),
                 qtype: QType::AAAA,
                 qclass: QClass::IN,
+                unicast_response: false,
             }],
             resource_records: vec![ResourceRecord {
                 name: String::from("play.google.com"),
                 rtype: QType::AAAA,
                 rclass: QClass::IN,
+                cache_flush: false,
                 ttl: 108,
                 rdlength: 16,
                 rdata: vec![42, 0, 20, 80, 64, 1, 8, 21, 0, 0, 0, 0, 0, 0, 32, 14],
             }],
+            authorities: Vec::new(),
+            additionals: Vec::new(),
+            edns: None,
         }
         .build();
 
@@ -679,4 +984,218 @@ mod tests {
         let hex_vec = hex::encode(vector);
         assert_eq!(hex_vec, hex_query);
     }
+
+    #[test]
+    pub fn test_parse_response_extended_error() {
+        let hex_response = "349e818300010000000000010377777706676f6f676c6502646500000100010000290\
+            4d000000000000d000f0009000f626c6f636b6564";
+        let hex_response = hex::decode(hex_response).unwrap();
+        let dns = DNS::parse(hex_response).unwrap();
+
+        assert_eq!(dns.rcode, Rcode::NameError);
+        assert_eq!(
+            dns.edns,
+            Some(Edns {
+                udp_payload_size: 1232,
+                extended_rcode: 0,
+                version: 0,
+                dnssec_ok: false,
+                options: vec![EdnsOption::ExtendedError(ExtendedError {
+                    info_code: ExtendedErrorCode::Blocked,
+                    extra_text: String::from("blocked")
+                })]
+            })
+        );
+        assert_eq!(
+            dns.extended_errors(),
+            vec![&ExtendedError::new(ExtendedErrorCode::Blocked, "blocked")]
+        );
+    }
+
+    #[test]
+    pub fn test_build_response_extended_error() {
+        let mut dns = DNS {
+            id: 13470,
+            qr: 1,
+            opcode: Opcode::Query,
+            aa: 0,
+            tc: 0,
+            rd: 1,
+            ra: 1,
+            z: 0,
+            ad: 0,
+            cd: 0,
+            rcode: Rcode::NameError,
+            qdcount: 1,
+            ancount: 0,
+            nscount: 0,
+            arcount: 1,
+            questions: vec![Question {
+                qname: String::from("www.google.de"),
+                qtype: QType::A,
+                qclass: QClass::IN,
+                unicast_response: false,
+            }],
+            resource_records: Vec::new(),
+            authorities: Vec::new(),
+            additionals: Vec::new(),
+            edns: None,
+        };
+        dns.add_extended_error(ExtendedError::new(ExtendedErrorCode::Blocked, "blocked"));
+
+        let hex_response = "349e818300010000000000010377777706676f6f676c6502646500000100010000290\
+            4d000000000000d000f0009000f626c6f636b6564";
+        assert_eq!(hex::encode(dns.clone().build()), hex_response);
+        assert_eq!(DNS::parse(dns.clone().build()).unwrap(), dns);
+    }
+
+    #[test]
+    pub fn test_parse_unknown_extended_error() {
+        let dns = DNS {
+            arcount: 1,
+            edns: Some(Edns {
+                dnssec_ok: true,
+                options: vec![
+                    EdnsOption::ExtendedError(ExtendedError::new(
+                        ExtendedErrorCode::Unassigned(1337),
+                        "",
+                    )),
+                    EdnsOption::Unknown {
+                        code: 10,
+                        data: vec![1, 2, 3, 4, 5, 6, 7, 8],
+                    },
+                ],
+                ..Edns::default()
+            }),
+            ..DNS::default()
+        };
+
+        assert_eq!(DNS::parse(dns.clone().build()).unwrap(), dns);
+    }
+
+    #[test]
+    pub fn test_parse_unknown_type() {
+        // HTTPS query of class CH with an answer of type 65280
+        let hex_response = "349e8180000100010000000005746f6b656e0000410003\
+            c00cff0000030000003c0003010203";
+        let dns = DNS::parse(hex::decode(hex_response).unwrap()).unwrap();
+
+        assert_eq!(dns.questions[0].qtype, QType::Unknown(65));
+        assert_eq!(dns.questions[0].qclass, QClass::Unknown(3));
+        assert_eq!(dns.resource_records[0].rtype, QType::Unknown(65280));
+        assert_eq!(dns.resource_records[0].rdata, vec![1, 2, 3]);
+        assert_eq!(hex::encode(dns.build()), hex_response);
+    }
+
+    #[test]
+    pub fn test_record_target() {
+        let record = ResourceRecord::cname("www.example.com".into(), "example.com", 60);
+        assert_eq!(record.rtype, QType::CNAME);
+        assert_eq!(record.target(), Some("example.com".into()));
+
+        let record = ResourceRecord::ns("example.com".into(), "ns1.example.net", 60);
+        assert_eq!(record.rtype, QType::NS);
+        assert_eq!(record.target(), Some("ns1.example.net".into()));
+
+        // the RDATA of a DNAME is never compressed (RFC 6672 2.5)
+        let record = ResourceRecord::dname("example.com".into(), "example.net", 60);
+        assert_eq!(record.rtype, QType::DNAME);
+        assert_eq!(record.target(), Some("example.net".into()));
+        assert_eq!(u16::from(record.rtype), 39);
+
+        let record = ResourceRecord::ptr("1.2.0.192.in-addr.arpa".into(), "host.example", 60);
+        assert_eq!(record.target(), Some("host.example".into()));
+
+        let record = ResourceRecord {
+            rtype: QType::A,
+            ..record
+        };
+        assert_eq!(record.target(), None);
+        assert_eq!(record.negative_ttl(), None);
+
+        // a.example. hostmaster.a.example. 1 7200 3600 1209600 300
+        let rdata = Writer::new()
+            .write_name_uncompressed("a.example")
+            .write_name_uncompressed("hostmaster.a.example")
+            .write_u32_be(1)
+            .write_u32_be(7200)
+            .write_u32_be(3600)
+            .write_u32_be(1_209_600)
+            .write_u32_be(300)
+            .build();
+        let soa = ResourceRecord {
+            name: "a.example".into(),
+            rtype: QType::SOA,
+            rclass: QClass::IN,
+            cache_flush: false,
+            ttl: 3600,
+            rdlength: rdata.len() as u16,
+            rdata,
+        };
+        assert_eq!(soa.negative_ttl(), Some(300));
+        assert_eq!(ResourceRecord { ttl: 60, ..soa }.negative_ttl(), Some(60));
+    }
+
+    #[test]
+    pub fn test_parse_invalid_extended_error() {
+        // EDE option with a single byte instead of the two byte info code
+        let hex_response = "349e81830000000000000001000029 04d0 00000000 0005 000f 0001 00";
+        let hex_response = hex::decode(hex_response.replace(' ', "")).unwrap();
+        assert!(DNS::parse(hex_response).is_err());
+    }
+
+    #[test]
+    pub fn test_build_truncated() {
+        let mut query = DNS {
+            questions: vec![Question {
+                qname: String::from("www.google.de"),
+                qtype: QType::A,
+                qclass: QClass::IN,
+                unicast_response: false,
+            }],
+            ..DNS::default()
+        };
+        assert_eq!(query.max_udp_size(), 512);
+        query.edns = Some(Edns {
+            udp_payload_size: 4096,
+            ..Edns::default()
+        });
+        assert_eq!(query.max_udp_size(), 1232);
+        query.edns = Some(Edns {
+            udp_payload_size: 100,
+            ..Edns::default()
+        });
+        assert_eq!(query.max_udp_size(), 512);
+
+        let record = ResourceRecord {
+            name: String::from("www.google.de"),
+            rtype: QType::A,
+            rclass: QClass::IN,
+            cache_flush: false,
+            ttl: 60,
+            rdlength: 4,
+            rdata: vec![172, 217, 168, 195],
+        };
+        let response = DNS {
+            qr: 1,
+            resource_records: vec![record.clone(); 10],
+            additionals: vec![record; 10],
+            ..query.clone()
+        };
+        let size = response.clone().build().len();
+        assert_eq!(
+            response.clone().build_truncated(size),
+            response.clone().build()
+        );
+
+        let message = DNS::parse(response.clone().build_truncated(size - 1)).unwrap();
+        assert_eq!(message.tc, 0);
+        assert_eq!(message.resource_records.len(), 10);
+        assert!(message.additionals.is_empty());
+
+        let message = DNS::parse(response.build_truncated(100)).unwrap();
+        assert_eq!(message.tc, 1);
+        assert!(message.resource_records.is_empty());
+        assert!(message.edns.is_some());
+    }
 }
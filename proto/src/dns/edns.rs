@@ -0,0 +1,291 @@
+use crate::error::*;
+use crate::reader::ByteReader;
+use crate::writer::Writer;
+
+use std::io::Cursor;
+
+/// Type of the OPT pseudo resource record
+pub(crate) const OPT: u16 = 41;
+
+/// Option code of Extended DNS Errors, RFC 8914
+const EXTENDED_ERROR: u16 = 15;
+
+/// Largest UDP message every requester accepts, RFC 1035 4.2.1
+pub const MIN_UDP_SIZE: usize = 512;
+/// Largest UDP message that is sent or advertised, avoids IP fragmentation
+pub const MAX_UDP_SIZE: usize = 1232;
+
+/// EDNS(0) information carried in the OPT pseudo record of the additional
+/// section, RFC 6891
+#[derive(Clone, Debug, Hash, Eq, PartialEq)]
+pub struct Edns {
+    /// Largest UDP payload the sender is able to reassemble
+    pub udp_payload_size: u16,
+    /// Upper eight bits of the twelve bit extended rcode
+    pub extended_rcode: u8,
+    pub version: u8,
+    /// DO bit, the sender is able to handle DNSSEC records
+    pub dnssec_ok: bool,
+    pub options: Vec<EdnsOption>,
+}
+
+impl Default for Edns {
+    fn default() -> Self {
+        Self {
+            udp_payload_size: MAX_UDP_SIZE as u16,
+            extended_rcode: 0,
+            version: 0,
+            dnssec_ok: false,
+            options: Vec::new(),
+        }
+    }
+}
+
+impl Edns {
+    /// Parses the OPT record, starting directly behind its type field
+    pub(crate) fn parse(reader: &mut Cursor<&[u8]>) -> Result<Self> {
+        let udp_payload_size = reader.read_u16()?;
+        let ttl = reader.read_u32()?;
+        let rdlength = reader.read_u16()?;
+        let rdata = reader.read_length(rdlength as usize)?;
+
+        let mut options = Vec::new();
+        let mut rdata = Cursor::new(rdata.as_slice());
+        while (rdata.position() as usize) < rdata.get_ref().len() {
+            let code = rdata.read_u16()?;
+            let length = rdata.read_u16()?;
+            let data = rdata
+                .read_length(length as usize)
+                .map_err(|_| DnsParseError::InvalidOption)?;
+            options.push(EdnsOption::parse(code, data)?);
+        }
+
+        Ok(Self {
+            udp_payload_size,
+            extended_rcode: (ttl >> 24) as u8,
+            version: (ttl >> 16) as u8,
+            dnssec_ok: ttl & 0x8000 != 0,
+            options,
+        })
+    }
+
+    /// Writes the OPT record including its empty owner name
+    pub(crate) fn write(self, writer: Writer) -> Writer {
+        let ttl = u32::from(self.extended_rcode) << 24
+            | u32::from(self.version) << 16
+            | if self.dnssec_ok { 0x8000 } else { 0 };
+
+        let mut rdata = Writer::new();
+        for option in self.options {
+            let (code, data) = option.build();
+            rdata = rdata
+                .write_u16_be(code)
+                .write_u16_be(data.len() as u16)
+                .write_vec(data);
+        }
+        let rdata = rdata.build();
+
+        writer
+            .write_u8(0)
+            .write_u16_be(OPT)
+            .write_u16_be(self.udp_payload_size)
+            .write_u32_be(ttl)
+            .write_u16_be(rdata.len() as u16)
+            .write_vec(rdata)
+    }
+
+    /// All extended errors contained in the options
+    pub fn extended_errors(&self) -> Vec<&ExtendedError> {
+        self.options
+            .iter()
+            .filter_map(|x| match x {
+                EdnsOption::ExtendedError(x) => Some(x),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+#[derive(Clone, Debug, Hash, Eq, PartialEq)]
+pub enum EdnsOption {
+    /// 15 -> Extended DNS Error, RFC 8914
+    ExtendedError(ExtendedError),
+    /// Every option that is not interpreted, kept as is
+    Unknown { code: u16, data: Vec<u8> },
+}
+
+impl EdnsOption {
+    fn parse(code: u16, data: Vec<u8>) -> Result<Self> {
+        match code {
+            EXTENDED_ERROR => {
+                if data.len() < 2 {
+                    return Err(DnsParseError::InvalidOption);
+                }
+
+                let info_code = u16::from_be_bytes([data[0], data[1]]);
+                // RFC 8914 only recommends UTF-8, so be lenient
+                let extra_text = String::from_utf8_lossy(&data[2..])
+                    .trim_end_matches('\0')
+                    .to_string();
+
+                Ok(Self::ExtendedError(ExtendedError {
+                    info_code: ExtendedErrorCode::from(info_code),
+                    extra_text,
+                }))
+            }
+            _ => Ok(Self::Unknown { code, data }),
+        }
+    }
+
+    fn build(self) -> (u16, Vec<u8>) {
+        match self {
+            Self::ExtendedError(x) => {
+                let data = Writer::new()
+                    .write_u16_be(x.info_code.into())
+                    .write_vec(x.extra_text.into_bytes())
+                    .build();
+                (EXTENDED_ERROR, data)
+            }
+            Self::Unknown { code, data } => (code, data),
+        }
+    }
+}
+
+/// Extended DNS Error option, RFC 8914
+#[derive(Clone, Debug, Hash, Eq, PartialEq)]
+pub struct ExtendedError {
+    pub info_code: ExtendedErrorCode,
+    /// Additional human readable information, may be empty
+    pub extra_text: String,
+}
+
+impl ExtendedError {
+    pub fn new<S: Into<String>>(info_code: ExtendedErrorCode, extra_text: S) -> Self {
+        Self {
+            info_code,
+            extra_text: extra_text.into(),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Hash, Eq, PartialEq)]
+pub enum ExtendedErrorCode {
+    /// 0 -> The error does not match any other code, see the extra text
+    Other,
+    /// 1 -> The zone uses a DNSKEY algorithm the resolver does not support
+    UnsupportedDnskeyAlgorithm,
+    /// 2 -> The zone uses a DS digest type the resolver does not support
+    UnsupportedDsDigestType,
+    /// 3 -> The answer was served from an expired cache entry
+    StaleAnswer,
+    /// 4 -> The answer was forged, for example by a policy
+    ForgedAnswer,
+    /// 5 -> DNSSEC validation ended in the indeterminate state
+    DnssecIndeterminate,
+    /// 6 -> DNSSEC validation ended in the bogus state
+    DnssecBogus,
+    /// 7 -> No valid signature, at least one has expired
+    SignatureExpired,
+    /// 8 -> No valid signature, at least one is not yet valid
+    SignatureNotYetValid,
+    /// 9 -> A DS record exists, but no matching DNSKEY
+    DnskeyMissing,
+    /// 10 -> No RRSIG was found for an RRset that should be signed
+    RrsigsMissing,
+    /// 11 -> No DNSKEY with the zone key bit set was found
+    NoZoneKeyBitSet,
+    /// 12 -> The denial of existence could not be proven
+    NsecMissing,
+    /// 13 -> The error itself was returned from the cache
+    CachedError,
+    /// 14 -> The server is not fully up and running yet
+    NotReady,
+    /// 15 -> The domain is on a blocklist of the operator
+    Blocked,
+    /// 16 -> The domain is blocked because of an external requirement
+    Censored,
+    /// 17 -> The domain is blocked because the requester asked for it
+    Filtered,
+    /// 18 -> The requester is not allowed to query this server
+    Prohibited,
+    /// 19 -> The NXDOMAIN answer was served from an expired cache entry
+    StaleNxdomainAnswer,
+    /// 20 -> The server is not authoritative and does not recurse
+    NotAuthoritative,
+    /// 21 -> The requested operation is not supported
+    NotSupported,
+    /// 22 -> None of the authoritative servers could be reached
+    NoReachableAuthority,
+    /// 23 -> An unrecoverable network error occurred
+    NetworkError,
+    /// 24 -> The authoritative server returned invalid data
+    InvalidData,
+    /// Every code that is not assigned by RFC 8914
+    Unassigned(u16),
+}
+
+impl From<u16> for ExtendedErrorCode {
+    fn from(x: u16) -> Self {
+        match x {
+            0 => Self::Other,
+            1 => Self::UnsupportedDnskeyAlgorithm,
+            2 => Self::UnsupportedDsDigestType,
+            3 => Self::StaleAnswer,
+            4 => Self::ForgedAnswer,
+            5 => Self::DnssecIndeterminate,
+            6 => Self::DnssecBogus,
+            7 => Self::SignatureExpired,
+            8 => Self::SignatureNotYetValid,
+            9 => Self::DnskeyMissing,
+            10 => Self::RrsigsMissing,
+            11 => Self::NoZoneKeyBitSet,
+            12 => Self::NsecMissing,
+            13 => Self::CachedError,
+            14 => Self::NotReady,
+            15 => Self::Blocked,
+            16 => Self::Censored,
+            17 => Self::Filtered,
+            18 => Self::Prohibited,
+            19 => Self::StaleNxdomainAnswer,
+            20 => Self::NotAuthoritative,
+            21 => Self::NotSupported,
+            22 => Self::NoReachableAuthority,
+            23 => Self::NetworkError,
+            24 => Self::InvalidData,
+            _ => Self::Unassigned(x),
+        }
+    }
+}
+
+impl From<ExtendedErrorCode> for u16 {
+    fn from(x: ExtendedErrorCode) -> Self {
+        match x {
+            ExtendedErrorCode::Other => 0,
+            ExtendedErrorCode::UnsupportedDnskeyAlgorithm => 1,
+            ExtendedErrorCode::UnsupportedDsDigestType => 2,
+            ExtendedErrorCode::StaleAnswer => 3,
+            ExtendedErrorCode::ForgedAnswer => 4,
+            ExtendedErrorCode::DnssecIndeterminate => 5,
+            ExtendedErrorCode::DnssecBogus => 6,
+            ExtendedErrorCode::SignatureExpired => 7,
+            ExtendedErrorCode::SignatureNotYetValid => 8,
+            ExtendedErrorCode::DnskeyMissing => 9,
+            ExtendedErrorCode::RrsigsMissing => 10,
+            ExtendedErrorCode::NoZoneKeyBitSet => 11,
+            ExtendedErrorCode::NsecMissing => 12,
+            ExtendedErrorCode::CachedError => 13,
+            ExtendedErrorCode::NotReady => 14,
+            ExtendedErrorCode::Blocked => 15,
+            ExtendedErrorCode::Censored => 16,
+            ExtendedErrorCode::Filtered => 17,
+            ExtendedErrorCode::Prohibited => 18,
+            ExtendedErrorCode::StaleNxdomainAnswer => 19,
+            ExtendedErrorCode::NotAuthoritative => 20,
+            ExtendedErrorCode::NotSupported => 21,
+            ExtendedErrorCode::NoReachableAuthority => 22,
+            ExtendedErrorCode::NetworkError => 23,
+            ExtendedErrorCode::InvalidData => 24,
+            ExtendedErrorCode::Unassigned(x) => x,
+        }
+    }
+}
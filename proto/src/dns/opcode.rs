@@ -1,36 +1,54 @@
-#[derive(Clone, Debug, Hash, Eq, PartialEq)]
+#[derive(Clone, Debug, Default, Hash, Eq, PartialEq)]
 pub enum Opcode {
+    #[default]
     Query,
     IQuery,
     Status,
-    Reserved,
+    /// Opcode without special handling, for example NOTIFY or UPDATE, the
+    /// value is kept so that it can be written back
+    Unknown(u8),
 }
 
-impl Default for Opcode {
-    fn default() -> Self {
-        Self::Query
-    }
-}
+/// Bits of every opcode, most significant first
+const BITS: [[u8; 4]; 16] = [
+    [0, 0, 0, 0],
+    [0, 0, 0, 1],
+    [0, 0, 1, 0],
+    [0, 0, 1, 1],
+    [0, 1, 0, 0],
+    [0, 1, 0, 1],
+    [0, 1, 1, 0],
+    [0, 1, 1, 1],
+    [1, 0, 0, 0],
+    [1, 0, 0, 1],
+    [1, 0, 1, 0],
+    [1, 0, 1, 1],
+    [1, 1, 0, 0],
+    [1, 1, 0, 1],
+    [1, 1, 1, 0],
+    [1, 1, 1, 1],
+];
 
 impl From<&[u8]> for Opcode {
-    /// Will silently declare everything invalid as reserved
+    /// Only the lower bit of each of the four values is used
     fn from(x: &[u8]) -> Self {
-        match x {
-            [0, 0, 0, 0] => Self::Query,
-            [0, 0, 0, 1] => Self::IQuery,
-            [0, 0, 1, 0] => Self::Status,
-            _ => Self::Reserved,
+        match x.iter().take(4).fold(0, |acc, x| acc << 1 | x & 1) {
+            0 => Self::Query,
+            1 => Self::IQuery,
+            2 => Self::Status,
+            x => Self::Unknown(x),
         }
     }
 }
 
-impl Into<&[u8]> for Opcode {
-    fn into(self) -> &'static [u8] {
-        match self {
-            Self::Query => &[0, 0, 0, 0],
-            Self::IQuery => &[0, 0, 0, 1],
-            Self::Status => &[0, 0, 1, 0],
-            _ => &[1, 1, 1, 1],
-        }
+impl From<Opcode> for &'static [u8] {
+    fn from(x: Opcode) -> Self {
+        let value = match x {
+            Opcode::Query => 0,
+            Opcode::IQuery => 1,
+            Opcode::Status => 2,
+            Opcode::Unknown(x) => x & 0b1111,
+        };
+        &BITS[value as usize]
     }
-}
\ No newline at end of file
+}
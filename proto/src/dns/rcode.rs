@@ -1,6 +1,7 @@
-#[derive(Clone, Debug, Hash, Eq, PartialEq)]
+#[derive(Clone, Debug, Default, Hash, Eq, PartialEq)]
 pub enum Rcode {
     /// 0 -> No error condition
+    #[default]
     NoError,
     /// 1 -> The name server was unable to interpret the query
     FormatError,
@@ -11,18 +12,12 @@ pub enum Rcode {
     /// 4 -> The name server does not support the requested kind of query
     NotImplemented,
     /// 5 -> The name server refuses to perform the specified operation for policy reasons.
-    /// For example, a name server may not wish to provide the information to the particular requester, or 
+    /// For example, a name server may not wish to provide the information to the particular requester, or
     /// a name server may not wish to perform a particular operation (e.g., zone transfer) for particular data
     Refused,
     Reserved,
 }
 
-impl Default for Rcode {
-    fn default() -> Self {
-        Self::NoError
-    }
-}
-
 impl From<&[u8]> for Rcode {
     /// Will silently declare everything invalid as reserved
     fn from(x: &[u8]) -> Self {
@@ -38,16 +33,16 @@ impl From<&[u8]> for Rcode {
     }
 }
 
-impl Into<&[u8]> for Rcode {
-    fn into(self) -> &'static [u8] {
-        match self {
-            Self::NoError => &[0, 0, 0, 0],
-            Self::FormatError => &[0, 0, 0, 1],
-            Self::ServerFailure => &[0, 0, 1, 0],
-            Self::NameError => &[0, 0, 1, 1],
-            Self::NotImplemented => &[0, 1, 0, 0],
-            Self::Refused => &[0, 1, 0, 1],
+impl From<Rcode> for &'static [u8] {
+    fn from(x: Rcode) -> Self {
+        match x {
+            Rcode::NoError => &[0, 0, 0, 0],
+            Rcode::FormatError => &[0, 0, 0, 1],
+            Rcode::ServerFailure => &[0, 0, 1, 0],
+            Rcode::NameError => &[0, 0, 1, 1],
+            Rcode::NotImplemented => &[0, 1, 0, 0],
+            Rcode::Refused => &[0, 1, 0, 1],
             _ => &[1, 1, 1, 1],
         }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,516 @@
+mod canonical;
+mod denial;
+mod rdata;
+
+pub use self::canonical::{canonical_cmp, is_subdomain};
+pub use self::denial::*;
+pub use self::rdata::*;
+
+use self::canonical::{canonical_name, canonical_rrset, labels};
+use crate::dns::{ExtendedErrorCode, ResourceRecord};
+use crate::qclass::as_u16 as qclass_as_u16;
+use crate::qtype::{as_u16 as qtype_as_u16, QType};
+use crate::writer::Writer;
+
+use ring::{digest, signature};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Reasons a signature, a DS record or a denial of existence could not be
+/// validated
+#[derive(Clone, Debug, Hash, Eq, PartialEq)]
+pub enum DnssecError {
+    /// The algorithm of the signature or key is not supported
+    UnsupportedAlgorithm(u8),
+    /// The digest type of the DS record is not supported
+    UnsupportedDigestType(u8),
+    /// The validity period of the signature ended
+    SignatureExpired,
+    /// The validity period of the signature did not start yet
+    SignatureNotYetValid,
+    /// No DNSKEY matches the signer, algorithm and key tag
+    DnskeyMissing,
+    /// There is no RRSIG covering the RRset
+    RrsigsMissing,
+    /// The matching DNSKEY is not allowed to sign the zone
+    NoZoneKeyBitSet,
+    /// The records do not form a RRset, or the RRSIG does not fit to them
+    InvalidRrset,
+    /// A DNSKEY, RRSIG or DS record is malformed
+    InvalidRdata,
+    /// The signature does not match the signed data
+    InvalidSignature,
+    /// The digest of the DS record does not match the DNSKEY
+    DigestMismatch,
+    /// The NSEC or NSEC3 records do not prove the denial of existence
+    NsecMissing,
+}
+
+impl DnssecError {
+    /// Extended DNS error that describes the failure best
+    pub fn extended_error_code(&self) -> ExtendedErrorCode {
+        match self {
+            Self::UnsupportedAlgorithm(_) => ExtendedErrorCode::UnsupportedDnskeyAlgorithm,
+            Self::UnsupportedDigestType(_) => ExtendedErrorCode::UnsupportedDsDigestType,
+            Self::SignatureExpired => ExtendedErrorCode::SignatureExpired,
+            Self::SignatureNotYetValid => ExtendedErrorCode::SignatureNotYetValid,
+            Self::DnskeyMissing => ExtendedErrorCode::DnskeyMissing,
+            Self::RrsigsMissing => ExtendedErrorCode::RrsigsMissing,
+            Self::NoZoneKeyBitSet => ExtendedErrorCode::NoZoneKeyBitSet,
+            Self::NsecMissing => ExtendedErrorCode::NsecMissing,
+            _ => ExtendedErrorCode::DnssecBogus,
+        }
+    }
+}
+
+/// Verifies the RRset with one of the RRSIG records covering it
+///
+/// `rrsigs` and `dnskeys` may contain unrelated records, only RRSIGs of the
+/// RRset type and DNSKEYs of the signer are considered. If no signature
+/// verifies, the most meaningful error is returned.
+pub fn verify_rrset(
+    rrset: &[ResourceRecord],
+    rrsigs: &[ResourceRecord],
+    dnskeys: &[ResourceRecord],
+    now: SystemTime,
+) -> Result<(), DnssecError> {
+    let rtype = rrset
+        .first()
+        .map(|x| qtype_as_u16(x.rtype))
+        .ok_or(DnssecError::InvalidRrset)?;
+
+    let mut error = DnssecError::RrsigsMissing;
+    for rrsig in rrsigs.iter().filter(|x| x.rtype == QType::RRSIG) {
+        match Rrsig::parse(&rrsig.rdata) {
+            Ok(x) if x.type_covered == rtype => (),
+            _ => continue,
+        }
+
+        match verify_rrsig(rrset, rrsig, dnskeys, now) {
+            Ok(()) => return Ok(()),
+            // a failed signature of a supported algorithm is more telling
+            Err(e) => {
+                if let DnssecError::RrsigsMissing | DnssecError::UnsupportedAlgorithm(_) = error {
+                    error = e;
+                }
+            }
+        }
+    }
+
+    Err(error)
+}
+
+/// Verifies a single RRSIG over the RRset (RFC 4035 5.3)
+///
+/// The records must share name, type and class. The owner name may be
+/// expanded from a wildcard, in that case `Rrsig::expanded_from` returns
+/// the wildcard. `now` is compared against the validity period.
+pub fn verify_rrsig(
+    rrset: &[ResourceRecord],
+    rrsig: &ResourceRecord,
+    dnskeys: &[ResourceRecord],
+    now: SystemTime,
+) -> Result<(), DnssecError> {
+    let first = rrset.first().ok_or(DnssecError::InvalidRrset)?;
+    let parsed = Rrsig::parse(&rrsig.rdata).map_err(|_| DnssecError::InvalidRdata)?;
+
+    let same_rrset = |x: &ResourceRecord| {
+        x.name.eq_ignore_ascii_case(&first.name)
+            && x.rtype == first.rtype
+            && x.rclass == first.rclass
+    };
+    let owner_labels = labels(&first.name)
+        .into_iter()
+        .skip_while(|x| *x == "*")
+        .count();
+    if !rrset.iter().all(same_rrset)
+        || !rrsig.name.eq_ignore_ascii_case(&first.name)
+        || rrsig.rclass != first.rclass
+        || parsed.type_covered != qtype_as_u16(first.rtype)
+        || !is_subdomain(&first.name, &parsed.signer_name)
+        || usize::from(parsed.labels) > owner_labels
+    {
+        return Err(DnssecError::InvalidRrset);
+    }
+
+    check_validity(&parsed, now)?;
+
+    if let Algorithm::Unsupported(x) = parsed.algorithm {
+        return Err(DnssecError::UnsupportedAlgorithm(x));
+    }
+
+    let mut candidates = dnskeys
+        .iter()
+        .filter(|x| x.rtype == QType::DNSKEY && x.name.eq_ignore_ascii_case(&parsed.signer_name))
+        .filter_map(|x| Dnskey::parse(&x.rdata).ok())
+        .filter(|x| {
+            x.protocol == 3 && x.algorithm == parsed.algorithm && x.key_tag() == parsed.key_tag
+        })
+        .peekable();
+    if candidates.peek().is_none() {
+        return Err(DnssecError::DnskeyMissing);
+    }
+
+    let data = signed_data(rrset, &parsed);
+    let mut error = DnssecError::NoZoneKeyBitSet;
+    // key tags are not unique, so every matching key has to be tried
+    for dnskey in candidates {
+        if !dnskey.is_zone_key() {
+            continue;
+        }
+
+        match verify_signature(&dnskey, &data, &parsed.signature) {
+            Ok(()) => return Ok(()),
+            Err(e) => error = e,
+        }
+    }
+
+    Err(error)
+}
+
+/// Verifies that the DS record references the DNSKEY of `owner`
+pub fn verify_ds(ds: &Ds, owner: &str, dnskey: &Dnskey) -> Result<(), DnssecError> {
+    if ds.key_tag != dnskey.key_tag() || ds.algorithm != dnskey.algorithm {
+        return Err(DnssecError::DnskeyMissing);
+    }
+
+    let algorithm = match ds.digest_type {
+        DigestType::Sha1 => &digest::SHA1_FOR_LEGACY_USE_ONLY,
+        DigestType::Sha256 => &digest::SHA256,
+        DigestType::Sha384 => &digest::SHA384,
+        DigestType::Unsupported(x) => return Err(DnssecError::UnsupportedDigestType(x)),
+    };
+
+    let mut context = digest::Context::new(algorithm);
+    context.update(&canonical_name(owner));
+    context.update(&dnskey.rdata());
+    if context.finish().as_ref() == ds.digest.as_slice() {
+        Ok(())
+    } else {
+        Err(DnssecError::DigestMismatch)
+    }
+}
+
+/// Chain of trust step from a parent to the child zone
+///
+/// The DS RRset of the parent has to be validated already. Succeeds if a
+/// DNSKEY referenced by one of the DS records signed the DNSKEY RRset.
+pub fn verify_dnskey_rrset(
+    dnskeys: &[ResourceRecord],
+    rrsigs: &[ResourceRecord],
+    ds_records: &[ResourceRecord],
+    now: SystemTime,
+) -> Result<(), DnssecError> {
+    let mut error = DnssecError::DnskeyMissing;
+
+    for ds_record in ds_records.iter().filter(|x| x.rtype == QType::DS) {
+        let ds = Ds::parse(&ds_record.rdata).map_err(|_| DnssecError::InvalidRdata)?;
+
+        for dnskey_record in dnskeys.iter().filter(|x| x.rtype == QType::DNSKEY) {
+            let dnskey =
+                Dnskey::parse(&dnskey_record.rdata).map_err(|_| DnssecError::InvalidRdata)?;
+            if let Err(e) = verify_ds(&ds, &ds_record.name, &dnskey) {
+                if e != DnssecError::DnskeyMissing {
+                    error = e;
+                }
+                continue;
+            }
+
+            // only this key is trusted so far, it has to sign the RRset
+            let trusted = [dnskey_record.clone()];
+            match verify_rrset(dnskeys, rrsigs, &trusted, now) {
+                Ok(()) => return Ok(()),
+                Err(e) => error = e,
+            }
+        }
+    }
+
+    Err(error)
+}
+
+/// Compares the validity period in serial number arithmetic (RFC 4034 3.1.5)
+fn check_validity(rrsig: &Rrsig, now: SystemTime) -> Result<(), DnssecError> {
+    let now = now
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs() as u32)
+        .unwrap_or_default();
+
+    if (now.wrapping_sub(rrsig.inception) as i32) < 0 {
+        Err(DnssecError::SignatureNotYetValid)
+    } else if (rrsig.expiration.wrapping_sub(now) as i32) < 0 {
+        Err(DnssecError::SignatureExpired)
+    } else {
+        Ok(())
+    }
+}
+
+/// Data covered by the signature (RFC 4034 3.1.8.1)
+fn signed_data(rrset: &[ResourceRecord], rrsig: &Rrsig) -> Vec<u8> {
+    let first = &rrset[0];
+    let owner = rrsig
+        .expanded_from(&first.name)
+        .unwrap_or_else(|| first.name.clone());
+    let owner = canonical_name(&owner);
+
+    let mut writer = Writer::new().write_vec(rrsig.signed_rdata());
+    for rdata in canonical_rrset(rrset) {
+        writer = writer
+            .write_vec(owner.clone())
+            .write_u16_be(qtype_as_u16(first.rtype))
+            .write_u16_be(qclass_as_u16(first.rclass))
+            .write_u32_be(rrsig.original_ttl)
+            .write_u16_be(rdata.len() as u16)
+            .write_vec(rdata);
+    }
+    writer.build()
+}
+
+fn verify_signature(dnskey: &Dnskey, data: &[u8], sig: &[u8]) -> Result<(), DnssecError> {
+    let key = dnskey.public_key.as_slice();
+
+    let result = match dnskey.algorithm {
+        Algorithm::RsaSha256 | Algorithm::RsaSha512 => {
+            // exponent length in one byte, or in two following a zero
+            let (length, offset) = match key {
+                [0, a, b, ..] => (usize::from(u16::from_be_bytes([*a, *b])), 3),
+                [a, ..] => (usize::from(*a), 1),
+                [] => return Err(DnssecError::InvalidRdata),
+            };
+            if key.len() <= offset + length {
+                return Err(DnssecError::InvalidRdata);
+            }
+
+            let strip = |x: &[u8]| {
+                x.iter()
+                    .skip_while(|x| **x == 0)
+                    .copied()
+                    .collect::<Vec<u8>>()
+            };
+            let components = signature::RsaPublicKeyComponents {
+                n: strip(&key[offset + length..]),
+                e: strip(&key[offset..offset + length]),
+            };
+            let parameters = if dnskey.algorithm == Algorithm::RsaSha256 {
+                &signature::RSA_PKCS1_1024_8192_SHA256_FOR_LEGACY_USE_ONLY
+            } else {
+                &signature::RSA_PKCS1_1024_8192_SHA512_FOR_LEGACY_USE_ONLY
+            };
+            components.verify(parameters, data, sig)
+        }
+        Algorithm::EcdsaP256Sha256 | Algorithm::EcdsaP384Sha384 => {
+            // keys are stored without the prefix of uncompressed points
+            let mut point = vec![0x04];
+            point.extend_from_slice(key);
+            let algorithm = if dnskey.algorithm == Algorithm::EcdsaP256Sha256 {
+                &signature::ECDSA_P256_SHA256_FIXED
+            } else {
+                &signature::ECDSA_P384_SHA384_FIXED
+            };
+            signature::UnparsedPublicKey::new(algorithm, point).verify(data, sig)
+        }
+        Algorithm::Ed25519 => {
+            signature::UnparsedPublicKey::new(&signature::ED25519, key).verify(data, sig)
+        }
+        Algorithm::Unsupported(x) => return Err(DnssecError::UnsupportedAlgorithm(x)),
+    };
+
+    result.map_err(|_| DnssecError::InvalidSignature)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns::DNS;
+
+    use std::time::Duration;
+
+    const FIXTURES: [&str; 5] = [
+        include_str!("dnssec/fixtures/rsasha256.hex"),
+        include_str!("dnssec/fixtures/rsasha512.hex"),
+        include_str!("dnssec/fixtures/ecdsap256sha256.hex"),
+        include_str!("dnssec/fixtures/ecdsap384sha384.hex"),
+        include_str!("dnssec/fixtures/ed25519.hex"),
+    ];
+
+    fn fixture(content: &str) -> DNS {
+        let hex = content
+            .lines()
+            .filter(|x| !x.starts_with('#'))
+            .collect::<String>();
+        DNS::from_hex(&hex).unwrap()
+    }
+
+    fn select(records: &[ResourceRecord], name: &str, rtype: QType) -> Vec<ResourceRecord> {
+        records
+            .iter()
+            .filter(|x| x.name.eq_ignore_ascii_case(name) && x.rtype == rtype)
+            .cloned()
+            .collect()
+    }
+
+    /// 2024-01-01
+    fn now() -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(1_704_067_200)
+    }
+
+    #[test]
+    pub fn test_verify_fixtures() {
+        for content in FIXTURES.iter() {
+            let dns = fixture(content);
+            let answers = &dns.resource_records;
+            let dnskeys = select(answers, "example", QType::DNSKEY);
+
+            for (name, rtype) in [
+                ("example", QType::DNSKEY),
+                ("www.example", QType::A),
+                ("example", QType::MX),
+                ("foo.bar.example", QType::A),
+            ]
+            .iter()
+            {
+                let rrset = select(answers, name, *rtype);
+                let rrsigs = select(answers, name, QType::RRSIG);
+                assert_eq!(verify_rrset(&rrset, &rrsigs, &dnskeys, now()), Ok(()));
+            }
+
+            assert_eq!(
+                verify_dnskey_rrset(&dnskeys, answers, &dns.authorities, now()),
+                Ok(())
+            );
+            for ds in dns.authorities.iter() {
+                let ds = Ds::parse(&ds.rdata).unwrap();
+                let ksk = dnskeys
+                    .iter()
+                    .map(|x| Dnskey::parse(&x.rdata).unwrap())
+                    .find(|x| x.is_secure_entry_point())
+                    .unwrap();
+                assert_eq!(verify_ds(&ds, "Example.", &ksk), Ok(()));
+            }
+        }
+    }
+
+    #[test]
+    pub fn test_wildcard_expansion() {
+        let dns = fixture(FIXTURES[4]);
+        let rrsig = &select(&dns.resource_records, "foo.bar.example", QType::RRSIG)[0];
+        let rrsig = Rrsig::parse(&rrsig.rdata).unwrap();
+        assert_eq!(
+            rrsig.expanded_from("foo.bar.example"),
+            Some("*.example".into())
+        );
+
+        // more labels than the owner name has
+        let rrset = select(&dns.resource_records, "foo.bar.example", QType::A);
+        let mut rrsigs = select(&dns.resource_records, "foo.bar.example", QType::RRSIG);
+        rrsigs[0].rdata[3] = 4;
+        assert_eq!(
+            verify_rrset(&rrset, &rrsigs, &dns.resource_records, now()),
+            Err(DnssecError::InvalidRrset)
+        );
+    }
+
+    #[test]
+    pub fn test_validity_period() {
+        let dns = fixture(FIXTURES[4]);
+        let rrset = select(&dns.resource_records, "www.example", QType::A);
+        let verify = |now| verify_rrset(&rrset, &dns.resource_records, &dns.resource_records, now);
+
+        // 2019-12-31 and 2030-01-02
+        assert_eq!(
+            verify(UNIX_EPOCH + Duration::from_secs(1_577_750_400)),
+            Err(DnssecError::SignatureNotYetValid)
+        );
+        assert_eq!(
+            verify(UNIX_EPOCH + Duration::from_secs(1_893_542_400)),
+            Err(DnssecError::SignatureExpired)
+        );
+        assert_eq!(
+            verify(UNIX_EPOCH + Duration::from_secs(1_893_456_000)),
+            Ok(())
+        );
+    }
+
+    #[test]
+    pub fn test_verify_invalid() {
+        let dns = fixture(FIXTURES[2]);
+        let answers = &dns.resource_records;
+        let dnskeys = select(answers, "example", QType::DNSKEY);
+        let rrsigs = select(answers, "www.example", QType::RRSIG);
+
+        let mut rrset = select(answers, "www.example", QType::A);
+        assert_eq!(
+            verify_rrset(&rrset, &[], &dnskeys, now()),
+            Err(DnssecError::RrsigsMissing)
+        );
+        assert_eq!(
+            verify_rrset(&rrset, &rrsigs, &[], now()),
+            Err(DnssecError::DnskeyMissing)
+        );
+
+        // zone key bit removed, this changes the key tag as well
+        let mut without_zone_key = dnskeys.clone();
+        for dnskey in without_zone_key.iter_mut() {
+            let mut parsed = Dnskey::parse(&dnskey.rdata).unwrap();
+            parsed.flags &= !Dnskey::ZONE_KEY;
+            dnskey.rdata = parsed.rdata();
+        }
+        assert_eq!(
+            verify_rrset(&rrset, &rrsigs, &without_zone_key, now()),
+            Err(DnssecError::DnskeyMissing)
+        );
+
+        rrset[0].rdata[3] ^= 1;
+        assert_eq!(
+            verify_rrset(&rrset, &rrsigs, &dnskeys, now()),
+            Err(DnssecError::InvalidSignature)
+        );
+
+        let mut unsupported = rrsigs.clone();
+        unsupported[0].rdata[2] = 5;
+        assert_eq!(
+            verify_rrset(&rrset, &unsupported, &dnskeys, now()),
+            Err(DnssecError::UnsupportedAlgorithm(5))
+        );
+    }
+
+    #[test]
+    pub fn test_verify_ds_invalid() {
+        let dns = fixture(FIXTURES[4]);
+        let dnskeys = select(&dns.resource_records, "example", QType::DNSKEY);
+        let ksk = dnskeys
+            .iter()
+            .map(|x| Dnskey::parse(&x.rdata).unwrap())
+            .find(|x| x.is_secure_entry_point())
+            .unwrap();
+
+        let mut ds = Ds::parse(&dns.authorities[1].rdata).unwrap();
+        assert_eq!(ds.digest_type, DigestType::Sha256);
+        assert_eq!(
+            verify_ds(&ds, "example.org", &ksk),
+            Err(DnssecError::DigestMismatch)
+        );
+
+        ds.digest_type = DigestType::Unsupported(3);
+        assert_eq!(
+            verify_ds(&ds, "example", &ksk),
+            Err(DnssecError::UnsupportedDigestType(3))
+        );
+
+        ds.key_tag = ds.key_tag.wrapping_add(1);
+        assert_eq!(
+            verify_ds(&ds, "example", &ksk),
+            Err(DnssecError::DnskeyMissing)
+        );
+
+        // DS records of another zone do not establish trust
+        let mut other = dns.authorities.clone();
+        other[1].rdata[5] ^= 1;
+        assert_eq!(
+            verify_dnskey_rrset(&dnskeys, &dns.resource_records, &other[1..2], now()),
+            Err(DnssecError::DigestMismatch)
+        );
+        assert_eq!(
+            DnssecError::DigestMismatch.extended_error_code(),
+            ExtendedErrorCode::DnssecBogus
+        );
+    }
+}
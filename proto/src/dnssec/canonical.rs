@@ -0,0 +1,145 @@
+use crate::dns::{rdata_layout, RdataPart, ResourceRecord};
+use crate::qtype::QType;
+use crate::reader::{read_name, ByteReader};
+use crate::writer::Writer;
+
+use std::cmp::Ordering;
+use std::io::Cursor;
+
+/// Labels of the name without the root label
+pub(crate) fn labels(name: &str) -> Vec<&str> {
+    let name = name.trim_end_matches('.');
+    if name.is_empty() {
+        Vec::new()
+    } else {
+        name.split('.').collect()
+    }
+}
+
+/// Lowercase wire format of the name without compression (RFC 4034 6.2)
+pub(crate) fn canonical_name(name: &str) -> Vec<u8> {
+    Writer::new()
+        .write_name_uncompressed(&name.to_ascii_lowercase())
+        .build()
+}
+
+/// Whether `name` is equal to or below `zone`, ignoring case
+pub fn is_subdomain(name: &str, zone: &str) -> bool {
+    let name = labels(name);
+    let zone = labels(zone);
+    name.len() >= zone.len()
+        && name[name.len() - zone.len()..]
+            .iter()
+            .zip(zone.iter())
+            .all(|(a, b)| a.eq_ignore_ascii_case(b))
+}
+
+/// Canonical ordering of names (RFC 4034 6.1), labels are compared from
+/// the right as lowercase octet sequences
+pub fn canonical_cmp(a: &str, b: &str) -> Ordering {
+    let key = |x: &str| {
+        labels(x)
+            .into_iter()
+            .rev()
+            .map(|x| x.to_ascii_lowercase().into_bytes())
+            .collect::<Vec<Vec<u8>>>()
+    };
+    key(a).cmp(&key(b))
+}
+
+/// RDATA with all embedded names lowercased, for the types listed in
+/// RFC 4034 6.2
+///
+/// RDATA that does not match the layout of its type is returned as it is.
+pub(crate) fn canonical_rdata(rtype: QType, rdata: &[u8]) -> Vec<u8> {
+    let layout = rdata_layout(rtype);
+    if layout.is_empty() {
+        return rdata.to_vec();
+    }
+
+    let mut reader = Cursor::new(rdata);
+    let mut writer = Writer::new();
+    for part in layout {
+        writer = match part {
+            RdataPart::Name => match read_name(&mut reader) {
+                Ok(x) => writer.write_vec(canonical_name(&x)),
+                Err(_) => return rdata.to_vec(),
+            },
+            RdataPart::U16 => match reader.read_u16() {
+                Ok(x) => writer.write_u16_be(x),
+                Err(_) => return rdata.to_vec(),
+            },
+            RdataPart::U32 => match reader.read_u32() {
+                Ok(x) => writer.write_u32_be(x),
+                Err(_) => return rdata.to_vec(),
+            },
+        };
+    }
+
+    if reader.position() as usize != rdata.len() {
+        return rdata.to_vec();
+    }
+    writer.build()
+}
+
+/// Canonical RDATA of all records, sorted and without duplicates
+/// (RFC 4034 6.3)
+pub(crate) fn canonical_rrset(rrset: &[ResourceRecord]) -> Vec<Vec<u8>> {
+    let mut rdata = rrset
+        .iter()
+        .map(|x| canonical_rdata(x.rtype, &x.rdata))
+        .collect::<Vec<Vec<u8>>>();
+    rdata.sort();
+    rdata.dedup();
+    rdata
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_canonical_cmp() {
+        // example of RFC 4034 6.1
+        let ordered = [
+            "example",
+            "a.example",
+            "yljkjljk.a.example",
+            "Z.a.example",
+            "zABC.a.EXAMPLE",
+            "z.example",
+            "\u{1}.z.example",
+            "*.z.example",
+            "\u{c8}.z.example",
+        ];
+
+        for window in ordered.windows(2) {
+            assert_eq!(canonical_cmp(window[0], window[1]), Ordering::Less);
+        }
+        assert_eq!(canonical_cmp("Example.", "example"), Ordering::Equal);
+    }
+
+    #[test]
+    pub fn test_canonical_rdata() {
+        let rdata = Writer::new()
+            .write_u16_be(10)
+            .write_name_uncompressed("Mail.Example")
+            .build();
+        let expected = Writer::new()
+            .write_u16_be(10)
+            .write_name_uncompressed("mail.example")
+            .build();
+        assert_eq!(canonical_rdata(QType::MX, &rdata), expected);
+        assert_eq!(canonical_rdata(QType::TXT, &rdata), rdata);
+        assert_eq!(canonical_rdata(QType::MX, &rdata[..4]), rdata[..4].to_vec());
+    }
+
+    #[test]
+    pub fn test_is_subdomain() {
+        assert!(is_subdomain("www.Example.", "example"));
+        assert!(is_subdomain("example", "example."));
+        assert!(is_subdomain("example", ""));
+        assert!(!is_subdomain("example", "www.example"));
+        assert!(!is_subdomain("wwwexample", "example"));
+    }
+}
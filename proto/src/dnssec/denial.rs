@@ -0,0 +1,692 @@
+use super::canonical::{canonical_cmp, canonical_name, is_subdomain, labels};
+use super::rdata::{Nsec, Nsec3};
+use super::{verify_rrset, DnssecError};
+use crate::dns::{ExtendedError, Rcode, ResourceRecord, DNS};
+use crate::encoding::base32hex_decode;
+use crate::qtype::{as_u16 as qtype_as_u16, QType};
+
+use ring::digest;
+use std::cmp::Ordering;
+use std::time::SystemTime;
+
+/// NSEC3 records with more iterations are treated as insecure (RFC 9276 3.2)
+pub const MAX_NSEC3_ITERATIONS: u16 = 150;
+
+/// Outcome of a denial of existence proof
+#[derive(Clone, Debug, Hash, Eq, PartialEq)]
+pub enum DenialVerdict {
+    /// The proof holds, the response may be marked as authenticated
+    Secure,
+    /// The proof relies on an opt-out span or on NSEC3 parameters that are
+    /// not validated, the response may be used but is not authenticated
+    Insecure,
+    /// The proof is missing or invalid, the response must not be used
+    Bogus(DnssecError),
+}
+
+impl DenialVerdict {
+    pub fn is_secure(&self) -> bool {
+        *self == Self::Secure
+    }
+
+    /// Marks the response accordingly: secure proofs set the AD bit,
+    /// insecure ones clear it and bogus ones turn it into a SERVFAIL with
+    /// an extended error
+    ///
+    /// Secure proofs only make the response authenticated if all of its
+    /// other records were validated as well.
+    pub fn apply(&self, response: &mut DNS) {
+        match self {
+            Self::Secure => response.ad = 1,
+            Self::Insecure => response.ad = 0,
+            Self::Bogus(e) => {
+                response.ad = 0;
+                response.rcode = Rcode::ServerFailure;
+                response.resource_records.clear();
+                response.authorities.clear();
+                response.additionals.clear();
+                response.add_extended_error(ExtendedError::new(e.extended_error_code(), ""));
+            }
+        }
+    }
+}
+
+/// Hashed owner name of NSEC3 records (RFC 5155 5)
+pub fn nsec3_hash(name: &str, salt: &[u8], iterations: u16) -> Vec<u8> {
+    let mut hash = canonical_name(name);
+    for _ in 0..=iterations {
+        let mut context = digest::Context::new(&digest::SHA1_FOR_LEGACY_USE_ONLY);
+        context.update(&hash);
+        context.update(salt);
+        hash = context.finish().as_ref().to_vec();
+    }
+    hash
+}
+
+/// Proves that `qname` does not exist, as claimed by a NXDOMAIN response
+///
+/// `authorities` contains the NSEC or NSEC3 records together with their
+/// RRSIGs, `dnskeys` the validated DNSKEY RRset of the zone.
+pub fn verify_name_error(
+    qname: &str,
+    authorities: &[ResourceRecord],
+    dnskeys: &[ResourceRecord],
+    now: SystemTime,
+) -> DenialVerdict {
+    match Proof::collect(authorities, dnskeys, now) {
+        Ok(Proof::Nsec(x)) => nsec_name_error(&x, qname),
+        Ok(Proof::Nsec3(x)) => nsec3_name_error(&x, qname),
+        Err(verdict) => verdict,
+    }
+}
+
+/// Proves that `qname` has no records of `qtype`, as claimed by a NOERROR
+/// response without answers
+pub fn verify_no_data(
+    qname: &str,
+    qtype: QType,
+    authorities: &[ResourceRecord],
+    dnskeys: &[ResourceRecord],
+    now: SystemTime,
+) -> DenialVerdict {
+    match Proof::collect(authorities, dnskeys, now) {
+        Ok(Proof::Nsec(x)) => nsec_no_data(&x, qname, qtype),
+        Ok(Proof::Nsec3(x)) => nsec3_no_data(&x, qname, qtype),
+        Err(verdict) => verdict,
+    }
+}
+
+/// Proves that no closer match than `wildcard` exists for `qname`, required
+/// for answers expanded from a wildcard (see `Rrsig::expanded_from`)
+pub fn verify_wildcard_answer(
+    qname: &str,
+    wildcard: &str,
+    authorities: &[ResourceRecord],
+    dnskeys: &[ResourceRecord],
+    now: SystemTime,
+) -> DenialVerdict {
+    let encloser = match labels(wildcard).split_first() {
+        Some((&"*", x)) if is_subdomain(qname, &x.join(".")) => x.join("."),
+        _ => return DenialVerdict::Bogus(DnssecError::InvalidRrset),
+    };
+
+    match Proof::collect(authorities, dnskeys, now) {
+        Ok(Proof::Nsec(x)) => verdict(x.iter().any(|x| x.covers(qname))),
+        Ok(Proof::Nsec3(x)) => match next_closer(qname, &encloser) {
+            Some(next_closer) => nsec3_covered(&x, &next_closer),
+            None => DenialVerdict::Bogus(DnssecError::InvalidRrset),
+        },
+        Err(verdict) => verdict,
+    }
+}
+
+/// Validated NSEC or NSEC3 records of a response
+enum Proof {
+    Nsec(Vec<NsecRecord>),
+    Nsec3(Vec<Nsec3Record>),
+}
+
+impl Proof {
+    /// Validates the signatures of all NSEC and NSEC3 records, NSEC records
+    /// are preferred if a response contains both
+    fn collect(
+        authorities: &[ResourceRecord],
+        dnskeys: &[ResourceRecord],
+        now: SystemTime,
+    ) -> Result<Self, DenialVerdict> {
+        let validated = |rtype: QType| {
+            let mut records = Vec::new();
+            for record in authorities.iter().filter(|x| x.rtype == rtype) {
+                let rrsigs = authorities
+                    .iter()
+                    .filter(|x| x.name.eq_ignore_ascii_case(&record.name))
+                    .cloned()
+                    .collect::<Vec<ResourceRecord>>();
+                verify_rrset(std::slice::from_ref(record), &rrsigs, dnskeys, now)
+                    .map_err(DenialVerdict::Bogus)?;
+                records.push(record);
+            }
+            Ok(records)
+        };
+
+        let nsecs = validated(QType::NSEC)?;
+        if !nsecs.is_empty() {
+            return nsecs
+                .into_iter()
+                .map(|x| {
+                    Nsec::parse(&x.rdata).map(|nsec| NsecRecord {
+                        owner: x.name.clone(),
+                        nsec,
+                    })
+                })
+                .collect::<Result<Vec<NsecRecord>, _>>()
+                .map(Proof::Nsec)
+                .map_err(|_| DenialVerdict::Bogus(DnssecError::InvalidRdata));
+        }
+
+        let nsec3s = validated(QType::NSEC3)?;
+        if nsec3s.is_empty() {
+            return Err(DenialVerdict::Bogus(DnssecError::NsecMissing));
+        }
+
+        let mut records = Vec::new();
+        for record in nsec3s {
+            let nsec3 = Nsec3::parse(&record.rdata)
+                .map_err(|_| DenialVerdict::Bogus(DnssecError::InvalidRdata))?;
+            let owner = labels(&record.name);
+            let owner_hash = owner
+                .first()
+                .and_then(|x| base32hex_decode(x).ok())
+                .ok_or(DenialVerdict::Bogus(DnssecError::InvalidRdata))?;
+
+            // records that can not be used are ignored (RFC 5155 8.1)
+            if nsec3.hash_algorithm == 1 && nsec3.iterations <= MAX_NSEC3_ITERATIONS {
+                records.push(Nsec3Record {
+                    owner_hash,
+                    zone: owner[1..].join("."),
+                    nsec3,
+                });
+            }
+        }
+
+        if records.is_empty() {
+            Err(DenialVerdict::Insecure)
+        } else {
+            Ok(Proof::Nsec3(records))
+        }
+    }
+}
+
+struct NsecRecord {
+    owner: String,
+    nsec: Nsec,
+}
+
+impl NsecRecord {
+    fn matches(&self, name: &str) -> bool {
+        canonical_cmp(&self.owner, name) == Ordering::Equal
+    }
+
+    /// `name` lies between the owner and the next name, so it does not exist
+    fn covers(&self, name: &str) -> bool {
+        // names below a delegation belong to the child zone
+        if is_subdomain(name, &self.owner) && is_delegation(&self.nsec.types) {
+            return false;
+        }
+
+        let next = &self.nsec.next_domain;
+        canonical_cmp(&self.owner, name) == Ordering::Less
+            && if canonical_cmp(&self.owner, next) == Ordering::Less {
+                canonical_cmp(name, next) == Ordering::Less
+            } else {
+                // the last NSEC of the zone points back to the apex
+                is_subdomain(name, next)
+            }
+    }
+
+    /// Closest encloser of a covered name, the longest ancestor it shares
+    /// with either end of the span (RFC 4035 5.4)
+    fn closest_encloser(&self, name: &str) -> String {
+        let owner = common_ancestor(name, &self.owner);
+        let next = common_ancestor(name, &self.nsec.next_domain);
+        if labels(&owner).len() >= labels(&next).len() {
+            owner
+        } else {
+            next
+        }
+    }
+}
+
+struct Nsec3Record {
+    owner_hash: Vec<u8>,
+    zone: String,
+    nsec3: Nsec3,
+}
+
+impl Nsec3Record {
+    fn hash(&self, name: &str) -> Vec<u8> {
+        nsec3_hash(name, &self.nsec3.salt, self.nsec3.iterations)
+    }
+
+    fn matches(&self, name: &str) -> bool {
+        is_subdomain(name, &self.zone) && self.hash(name) == self.owner_hash
+    }
+
+    /// The hash of `name` lies between the owner and the next hash
+    fn covers(&self, name: &str) -> bool {
+        if !is_subdomain(name, &self.zone) {
+            return false;
+        }
+
+        let hash = self.hash(name);
+        let next = &self.nsec3.next_hashed_owner;
+        if self.owner_hash < *next {
+            self.owner_hash < hash && hash < *next
+        } else {
+            // the last NSEC3 of the zone points back to the first one
+            self.owner_hash < hash || hash < *next
+        }
+    }
+}
+
+fn nsec_name_error(records: &[NsecRecord], qname: &str) -> DenialVerdict {
+    let encloser = match records.iter().find(|x| x.covers(qname)) {
+        Some(x) => x.closest_encloser(qname),
+        None => return DenialVerdict::Bogus(DnssecError::NsecMissing),
+    };
+
+    // the wildcard of the closest encloser must not exist either
+    let wildcard = wildcard_of(&encloser);
+    verdict(records.iter().any(|x| x.covers(&wildcard)))
+}
+
+fn nsec_no_data(records: &[NsecRecord], qname: &str, qtype: QType) -> DenialVerdict {
+    if let Some(x) = records.iter().find(|x| x.matches(qname)) {
+        return verdict(denies_type(&x.owner, &x.nsec.types, qtype));
+    }
+
+    let cover = match records.iter().find(|x| x.covers(qname)) {
+        Some(x) => x,
+        None => return DenialVerdict::Bogus(DnssecError::NsecMissing),
+    };
+
+    // empty non terminals have names below them, but no NSEC of their own
+    if is_subdomain(&cover.nsec.next_domain, qname) {
+        return DenialVerdict::Secure;
+    }
+
+    // the wildcard matches, but has no records of the type
+    let wildcard = wildcard_of(&cover.closest_encloser(qname));
+    verdict(
+        records
+            .iter()
+            .any(|x| x.matches(&wildcard) && denies_type(&x.owner, &x.nsec.types, qtype)),
+    )
+}
+
+/// Closest encloser proof (RFC 5155 8.3), returns the closest encloser and
+/// whether the span covering the next closer name is opt-out
+///
+/// `None` if the proof is incomplete or `qname` itself exists.
+fn closest_encloser(records: &[Nsec3Record], qname: &str) -> Option<(String, bool)> {
+    let names = labels(qname);
+    for i in 1..=names.len() {
+        let encloser = names[i..].join(".");
+        let matched = records
+            .iter()
+            .any(|x| x.matches(&encloser) && !is_delegation(&x.nsec3.types));
+        if !matched {
+            continue;
+        }
+
+        let next_closer = names[i - 1..].join(".");
+        return records
+            .iter()
+            .find(|x| x.covers(&next_closer))
+            .map(|x| (encloser, x.nsec3.is_opt_out()));
+    }
+    None
+}
+
+fn nsec3_name_error(records: &[Nsec3Record], qname: &str) -> DenialVerdict {
+    if records.iter().any(|x| x.matches(qname)) {
+        return DenialVerdict::Bogus(DnssecError::NsecMissing);
+    }
+
+    let (encloser, opt_out) = match closest_encloser(records, qname) {
+        Some(x) => x,
+        None => return DenialVerdict::Bogus(DnssecError::NsecMissing),
+    };
+
+    let wildcard = wildcard_of(&encloser);
+    match records.iter().find(|x| x.covers(&wildcard)) {
+        // an unsigned delegation may exist in the span (RFC 5155 9.2)
+        Some(_) if opt_out => DenialVerdict::Insecure,
+        Some(_) => DenialVerdict::Secure,
+        None => DenialVerdict::Bogus(DnssecError::NsecMissing),
+    }
+}
+
+fn nsec3_no_data(records: &[Nsec3Record], qname: &str, qtype: QType) -> DenialVerdict {
+    if let Some(x) = records.iter().find(|x| x.matches(qname)) {
+        return verdict(denies_type(qname, &x.nsec3.types, qtype));
+    }
+
+    let (encloser, opt_out) = match closest_encloser(records, qname) {
+        Some(x) => x,
+        None => return DenialVerdict::Bogus(DnssecError::NsecMissing),
+    };
+
+    // no DS for an unsigned delegation (RFC 5155 8.6)
+    if qtype == QType::DS && opt_out {
+        return DenialVerdict::Insecure;
+    }
+
+    // the wildcard matches, but has no records of the type (RFC 5155 8.7)
+    let wildcard = wildcard_of(&encloser);
+    verdict(
+        records
+            .iter()
+            .any(|x| x.matches(&wildcard) && denies_type(&wildcard, &x.nsec3.types, qtype)),
+    )
+}
+
+fn nsec3_covered(records: &[Nsec3Record], name: &str) -> DenialVerdict {
+    match records.iter().find(|x| x.covers(name)) {
+        Some(x) if x.nsec3.is_opt_out() => DenialVerdict::Insecure,
+        Some(_) => DenialVerdict::Secure,
+        None => DenialVerdict::Bogus(DnssecError::NsecMissing),
+    }
+}
+
+fn verdict(proven: bool) -> DenialVerdict {
+    if proven {
+        DenialVerdict::Secure
+    } else {
+        DenialVerdict::Bogus(DnssecError::NsecMissing)
+    }
+}
+
+/// Whether the type bitmap of `owner` proves the absence of `qtype`
+/// (RFC 4035 5.4, RFC 5155 8.5)
+fn denies_type(owner: &str, types: &[u16], qtype: QType) -> bool {
+    let has = |x: QType| types.contains(&qtype_as_u16(x));
+    if has(qtype) || has(QType::CNAME) {
+        return false;
+    }
+
+    if qtype == QType::DS {
+        // the DS RRset lives in the parent, not at the apex of the child
+        !has(QType::SOA) || labels(owner).is_empty()
+    } else {
+        // the parent side of a delegation knows nothing about the child
+        !is_delegation(types)
+    }
+}
+
+fn is_delegation(types: &[u16]) -> bool {
+    let has = |x: QType| types.contains(&qtype_as_u16(x));
+    has(QType::NS) && !has(QType::SOA)
+}
+
+fn common_ancestor(a: &str, b: &str) -> String {
+    let a = labels(a);
+    let b = labels(b);
+    let common = a
+        .iter()
+        .rev()
+        .zip(b.iter().rev())
+        .take_while(|(a, b)| a.eq_ignore_ascii_case(b))
+        .count();
+    a[a.len() - common..].join(".")
+}
+
+fn next_closer(qname: &str, encloser: &str) -> Option<String> {
+    let names = labels(qname);
+    let depth = labels(encloser).len() + 1;
+    if names.len() < depth {
+        return None;
+    }
+    Some(names[names.len() - depth..].join("."))
+}
+
+fn wildcard_of(encloser: &str) -> String {
+    if encloser.is_empty() {
+        "*".into()
+    } else {
+        format!("*.{}", encloser)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoding::base32hex_encode;
+
+    use std::time::{Duration, UNIX_EPOCH};
+
+    const NSEC: &str = include_str!("fixtures/nsec.hex");
+    const NSEC3: &str = include_str!("fixtures/nsec3.hex");
+    const NSEC3_OPT_OUT: &str = include_str!("fixtures/nsec3_opt_out.hex");
+
+    fn fixture(content: &str) -> (Vec<ResourceRecord>, Vec<ResourceRecord>) {
+        let hex = content
+            .lines()
+            .filter(|x| !x.starts_with('#'))
+            .collect::<String>();
+        let dns = DNS::from_hex(&hex).unwrap();
+        (dns.authorities, dns.resource_records)
+    }
+
+    /// 2024-01-01
+    fn now() -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(1_704_067_200)
+    }
+
+    /// Keeps the records of the named NSEC records and their RRSIGs
+    fn only(authorities: &[ResourceRecord], names: &[&str]) -> Vec<ResourceRecord> {
+        authorities
+            .iter()
+            .filter(|x| names.iter().any(|y| x.name.eq_ignore_ascii_case(y)))
+            .cloned()
+            .collect()
+    }
+
+    #[test]
+    pub fn test_nsec3_hash() {
+        // examples of RFC 5155 Appendix A
+        let salt = [0xaa, 0xbb, 0xcc, 0xdd];
+        assert_eq!(
+            base32hex_encode(&nsec3_hash("example", &salt, 12)),
+            "0p9mhaveqvm6t7vbl5lop2u3t2rp3tom"
+        );
+        assert_eq!(
+            base32hex_encode(&nsec3_hash("A.Example.", &salt, 12)),
+            "35mthgpgcu1qg68fab165klnsnk3dpvl"
+        );
+    }
+
+    #[test]
+    pub fn test_nsec() {
+        let (authorities, dnskeys) = fixture(NSEC);
+        let name_error = |x| verify_name_error(x, &authorities, &dnskeys, now());
+        let no_data = |x, y| verify_no_data(x, y, &authorities, &dnskeys, now());
+
+        assert_eq!(name_error("b.example"), DenialVerdict::Secure);
+        assert_eq!(name_error("b.a.example"), DenialVerdict::Secure);
+        assert_eq!(
+            name_error("a.example"),
+            DenialVerdict::Bogus(DnssecError::NsecMissing)
+        );
+        // covered by the wildcard
+        assert_eq!(
+            name_error("foo.w.example"),
+            DenialVerdict::Bogus(DnssecError::NsecMissing)
+        );
+        // below the unsigned delegation
+        assert_eq!(
+            name_error("www.d.example"),
+            DenialVerdict::Bogus(DnssecError::NsecMissing)
+        );
+
+        assert_eq!(no_data("a.example", QType::MX), DenialVerdict::Secure);
+        assert_eq!(
+            no_data("a.example", QType::A),
+            DenialVerdict::Bogus(DnssecError::NsecMissing)
+        );
+        // empty non terminal
+        assert_eq!(no_data("y.example", QType::A), DenialVerdict::Secure);
+        // wildcard without the type
+        assert_eq!(no_data("foo.w.example", QType::MX), DenialVerdict::Secure);
+        assert_eq!(
+            no_data("foo.w.example", QType::A),
+            DenialVerdict::Bogus(DnssecError::NsecMissing)
+        );
+        // the parent proves that the delegation is unsigned, nothing else
+        assert_eq!(no_data("d.example", QType::DS), DenialVerdict::Secure);
+        assert_eq!(
+            no_data("d.example", QType::A),
+            DenialVerdict::Bogus(DnssecError::NsecMissing)
+        );
+    }
+
+    #[test]
+    pub fn test_nsec_incomplete() {
+        let (authorities, dnskeys) = fixture(NSEC);
+
+        // the wildcard *.example is not proven to be absent
+        assert_eq!(
+            verify_name_error(
+                "b.example",
+                &only(&authorities, &["a.example"]),
+                &dnskeys,
+                now()
+            ),
+            DenialVerdict::Bogus(DnssecError::NsecMissing)
+        );
+        assert_eq!(
+            verify_name_error("b.example", &[], &dnskeys, now()),
+            DenialVerdict::Bogus(DnssecError::NsecMissing)
+        );
+
+        let mut tampered = authorities.clone();
+        let nsec = tampered
+            .iter_mut()
+            .find(|x| x.rtype == QType::NSEC && x.name == "a.example")
+            .unwrap();
+        nsec.rdata[1] = b'e';
+        assert_eq!(
+            verify_name_error("b.example", &tampered, &dnskeys, now()),
+            DenialVerdict::Bogus(DnssecError::InvalidSignature)
+        );
+    }
+
+    #[test]
+    pub fn test_nsec_wildcard_answer() {
+        let (authorities, dnskeys) = fixture(NSEC);
+        let answer = |x, y| verify_wildcard_answer(x, y, &authorities, &dnskeys, now());
+
+        assert_eq!(
+            answer("foo.w.example", "*.w.example"),
+            DenialVerdict::Secure
+        );
+        assert_eq!(
+            answer("a.b.w.example", "*.w.example"),
+            DenialVerdict::Secure
+        );
+        // the name exists, so it can not be expanded from a wildcard
+        assert_eq!(
+            answer("a.example", "*.example"),
+            DenialVerdict::Bogus(DnssecError::NsecMissing)
+        );
+        assert_eq!(
+            answer("foo.w.example", "*.z.example"),
+            DenialVerdict::Bogus(DnssecError::InvalidRrset)
+        );
+    }
+
+    #[test]
+    pub fn test_nsec3() {
+        let (authorities, dnskeys) = fixture(NSEC3);
+        let name_error = |x| verify_name_error(x, &authorities, &dnskeys, now());
+        let no_data = |x, y| verify_no_data(x, y, &authorities, &dnskeys, now());
+
+        assert_eq!(name_error("b.example"), DenialVerdict::Secure);
+        assert_eq!(name_error("b.a.example"), DenialVerdict::Secure);
+        assert_eq!(
+            name_error("a.example"),
+            DenialVerdict::Bogus(DnssecError::NsecMissing)
+        );
+        assert_eq!(
+            name_error("foo.w.example"),
+            DenialVerdict::Bogus(DnssecError::NsecMissing)
+        );
+
+        assert_eq!(no_data("a.example", QType::MX), DenialVerdict::Secure);
+        assert_eq!(
+            no_data("a.example", QType::A),
+            DenialVerdict::Bogus(DnssecError::NsecMissing)
+        );
+        assert_eq!(no_data("y.example", QType::A), DenialVerdict::Secure);
+        assert_eq!(no_data("foo.w.example", QType::MX), DenialVerdict::Secure);
+        assert_eq!(no_data("d.example", QType::DS), DenialVerdict::Secure);
+        assert_eq!(
+            no_data("d.example", QType::A),
+            DenialVerdict::Bogus(DnssecError::NsecMissing)
+        );
+
+        let answer = |x, y| verify_wildcard_answer(x, y, &authorities, &dnskeys, now());
+        assert_eq!(
+            answer("foo.w.example", "*.w.example"),
+            DenialVerdict::Secure
+        );
+        assert_eq!(
+            answer("a.example", "*.example"),
+            DenialVerdict::Bogus(DnssecError::NsecMissing)
+        );
+    }
+
+    #[test]
+    pub fn test_nsec3_opt_out() {
+        let (authorities, dnskeys) = fixture(NSEC3_OPT_OUT);
+
+        // d.example is not part of the chain, it may be an unsigned delegation
+        assert_eq!(
+            verify_no_data("d.example", QType::DS, &authorities, &dnskeys, now()),
+            DenialVerdict::Insecure
+        );
+        assert_eq!(
+            verify_name_error("b.example", &authorities, &dnskeys, now()),
+            DenialVerdict::Insecure
+        );
+        assert_eq!(
+            verify_no_data("a.example", QType::MX, &authorities, &dnskeys, now()),
+            DenialVerdict::Secure
+        );
+    }
+
+    #[test]
+    pub fn test_nsec3_incomplete() {
+        let (authorities, dnskeys) = fixture(NSEC3);
+
+        // without the closest encloser there is no proof
+        let apex = base32hex_encode(&nsec3_hash("example", &[0xaa, 0xbb, 0xcc, 0xdd], 1));
+        let incomplete = authorities
+            .iter()
+            .filter(|x| !x.name.starts_with(&apex))
+            .cloned()
+            .collect::<Vec<ResourceRecord>>();
+        assert_eq!(
+            verify_name_error("b.example", &incomplete, &dnskeys, now()),
+            DenialVerdict::Bogus(DnssecError::NsecMissing)
+        );
+
+        let mut tampered = authorities.clone();
+        for record in tampered.iter_mut().filter(|x| x.rtype == QType::NSEC3) {
+            record.rdata[2] = 1;
+        }
+        assert_eq!(
+            verify_name_error("b.example", &tampered, &dnskeys, now()),
+            DenialVerdict::Bogus(DnssecError::InvalidSignature)
+        );
+    }
+
+    #[test]
+    pub fn test_apply() {
+        let (authorities, _) = fixture(NSEC);
+        let mut response = DNS::default();
+        DenialVerdict::Secure.apply(&mut response);
+        assert_eq!(response.ad, 1);
+        DenialVerdict::Insecure.apply(&mut response);
+        assert_eq!(response.ad, 0);
+
+        response.authorities = authorities;
+        DenialVerdict::Bogus(DnssecError::NsecMissing).apply(&mut response);
+        assert_eq!(response.rcode, Rcode::ServerFailure);
+        assert!(response.authorities.is_empty());
+        assert_eq!(
+            response.extended_errors()[0].info_code,
+            crate::dns::ExtendedErrorCode::NsecMissing
+        );
+    }
+}
@@ -0,0 +1,450 @@
+use super::canonical::{canonical_name, labels};
+use crate::error::*;
+use crate::qtype::{as_u16 as qtype_as_u16, QType};
+use crate::reader::{read_name, ByteReader};
+use crate::writer::Writer;
+
+use std::io::Cursor;
+
+/// DNSSEC algorithm numbers (RFC 8624)
+#[derive(Copy, Clone, Debug, Hash, Eq, PartialEq)]
+pub enum Algorithm {
+    /// 8 -> RSA/SHA-256, RFC 5702
+    RsaSha256,
+    /// 10 -> RSA/SHA-512, RFC 5702
+    RsaSha512,
+    /// 13 -> ECDSA P-256 with SHA-256, RFC 6605
+    EcdsaP256Sha256,
+    /// 14 -> ECDSA P-384 with SHA-384, RFC 6605
+    EcdsaP384Sha384,
+    /// 15 -> Ed25519, RFC 8080
+    Ed25519,
+    /// Every algorithm that can not be validated
+    Unsupported(u8),
+}
+
+impl From<u8> for Algorithm {
+    fn from(x: u8) -> Self {
+        match x {
+            8 => Self::RsaSha256,
+            10 => Self::RsaSha512,
+            13 => Self::EcdsaP256Sha256,
+            14 => Self::EcdsaP384Sha384,
+            15 => Self::Ed25519,
+            x => Self::Unsupported(x),
+        }
+    }
+}
+
+impl From<Algorithm> for u8 {
+    fn from(x: Algorithm) -> Self {
+        match x {
+            Algorithm::RsaSha256 => 8,
+            Algorithm::RsaSha512 => 10,
+            Algorithm::EcdsaP256Sha256 => 13,
+            Algorithm::EcdsaP384Sha384 => 14,
+            Algorithm::Ed25519 => 15,
+            Algorithm::Unsupported(x) => x,
+        }
+    }
+}
+
+/// Digest types of DS records
+#[derive(Copy, Clone, Debug, Hash, Eq, PartialEq)]
+pub enum DigestType {
+    /// 1 -> SHA-1, RFC 4034
+    Sha1,
+    /// 2 -> SHA-256, RFC 4509
+    Sha256,
+    /// 4 -> SHA-384, RFC 6605
+    Sha384,
+    /// Every digest type that can not be validated
+    Unsupported(u8),
+}
+
+impl From<u8> for DigestType {
+    fn from(x: u8) -> Self {
+        match x {
+            1 => Self::Sha1,
+            2 => Self::Sha256,
+            4 => Self::Sha384,
+            x => Self::Unsupported(x),
+        }
+    }
+}
+
+impl From<DigestType> for u8 {
+    fn from(x: DigestType) -> Self {
+        match x {
+            DigestType::Sha1 => 1,
+            DigestType::Sha256 => 2,
+            DigestType::Sha384 => 4,
+            DigestType::Unsupported(x) => x,
+        }
+    }
+}
+
+/// RDATA of a DNSKEY record, RFC 4034 2
+#[derive(Clone, Debug, Hash, Eq, PartialEq)]
+pub struct Dnskey {
+    pub flags: u16,
+    /// Always 3
+    pub protocol: u8,
+    pub algorithm: Algorithm,
+    pub public_key: Vec<u8>,
+}
+
+impl Dnskey {
+    /// The key may be used to verify signatures of the zone
+    pub const ZONE_KEY: u16 = 0x0100;
+    /// The key is meant to be referenced by DS records
+    pub const SECURE_ENTRY_POINT: u16 = 0x0001;
+
+    pub fn parse(rdata: &[u8]) -> Result<Self> {
+        if rdata.len() < 4 {
+            return Err(DnsParseError::InvalidRdata);
+        }
+
+        Ok(Self {
+            flags: u16::from_be_bytes([rdata[0], rdata[1]]),
+            protocol: rdata[2],
+            algorithm: Algorithm::from(rdata[3]),
+            public_key: rdata[4..].to_vec(),
+        })
+    }
+
+    pub fn rdata(&self) -> Vec<u8> {
+        Writer::new()
+            .write_u16_be(self.flags)
+            .write_u8(self.protocol)
+            .write_u8(self.algorithm.into())
+            .write_vec(self.public_key.clone())
+            .build()
+    }
+
+    /// Key tag as referenced by RRSIG and DS records (RFC 4034 Appendix B)
+    pub fn key_tag(&self) -> u16 {
+        let mut sum = self
+            .rdata()
+            .iter()
+            .enumerate()
+            .map(|(i, x)| {
+                if i % 2 == 0 {
+                    u32::from(*x) << 8
+                } else {
+                    u32::from(*x)
+                }
+            })
+            .sum::<u32>();
+        sum += (sum >> 16) & 0xFFFF;
+        sum as u16
+    }
+
+    pub fn is_zone_key(&self) -> bool {
+        self.flags & Self::ZONE_KEY != 0
+    }
+
+    pub fn is_secure_entry_point(&self) -> bool {
+        self.flags & Self::SECURE_ENTRY_POINT != 0
+    }
+}
+
+/// RDATA of a RRSIG record, RFC 4034 3
+#[derive(Clone, Debug, Hash, Eq, PartialEq)]
+pub struct Rrsig {
+    /// Type of the signed RRset, kept as number as it may be any type
+    pub type_covered: u16,
+    pub algorithm: Algorithm,
+    /// Number of labels of the signed owner name, without a wildcard label
+    pub labels: u8,
+    pub original_ttl: u32,
+    /// Seconds since the Unix epoch in serial number arithmetic
+    pub expiration: u32,
+    /// Seconds since the Unix epoch in serial number arithmetic
+    pub inception: u32,
+    pub key_tag: u16,
+    /// Zone that created the signature
+    pub signer_name: String,
+    pub signature: Vec<u8>,
+}
+
+impl Rrsig {
+    pub fn parse(rdata: &[u8]) -> Result<Self> {
+        let mut reader = Cursor::new(rdata);
+        let type_covered = reader.read_u16()?;
+        let algorithm = Algorithm::from(reader.read_u8()?);
+        let labels = reader.read_u8()?;
+        let original_ttl = reader.read_u32()?;
+        let expiration = reader.read_u32()?;
+        let inception = reader.read_u32()?;
+        let key_tag = reader.read_u16()?;
+        let signer_name = read_name(&mut reader)?;
+        let signature = rdata[reader.position() as usize..].to_vec();
+
+        Ok(Self {
+            type_covered,
+            algorithm,
+            labels,
+            original_ttl,
+            expiration,
+            inception,
+            key_tag,
+            signer_name,
+            signature,
+        })
+    }
+
+    /// RDATA without the signature and with the canonical signer name, the
+    /// start of the signed data (RFC 4034 3.1.8.1)
+    pub(crate) fn signed_rdata(&self) -> Vec<u8> {
+        Writer::new()
+            .write_u16_be(self.type_covered)
+            .write_u8(self.algorithm.into())
+            .write_u8(self.labels)
+            .write_u32_be(self.original_ttl)
+            .write_u32_be(self.expiration)
+            .write_u32_be(self.inception)
+            .write_u16_be(self.key_tag)
+            .write_vec(canonical_name(&self.signer_name))
+            .build()
+    }
+
+    /// Wildcard the owner name was expanded from, `None` if the signature
+    /// covers the owner name itself or does not fit to it
+    pub fn expanded_from(&self, owner: &str) -> Option<String> {
+        let mut owner = labels(owner);
+        if owner.first() == Some(&"*") {
+            owner.remove(0);
+        }
+
+        let labels = usize::from(self.labels);
+        if labels >= owner.len() {
+            return None;
+        }
+
+        let mut wildcard = vec!["*"];
+        wildcard.extend_from_slice(&owner[owner.len() - labels..]);
+        Some(wildcard.join("."))
+    }
+}
+
+/// RDATA of a DS record, RFC 4034 5
+#[derive(Clone, Debug, Hash, Eq, PartialEq)]
+pub struct Ds {
+    pub key_tag: u16,
+    pub algorithm: Algorithm,
+    pub digest_type: DigestType,
+    pub digest: Vec<u8>,
+}
+
+impl Ds {
+    pub fn parse(rdata: &[u8]) -> Result<Self> {
+        if rdata.len() < 4 {
+            return Err(DnsParseError::InvalidRdata);
+        }
+
+        Ok(Self {
+            key_tag: u16::from_be_bytes([rdata[0], rdata[1]]),
+            algorithm: Algorithm::from(rdata[2]),
+            digest_type: DigestType::from(rdata[3]),
+            digest: rdata[4..].to_vec(),
+        })
+    }
+}
+
+/// RDATA of a NSEC record, RFC 4034 4
+#[derive(Clone, Debug, Hash, Eq, PartialEq)]
+pub struct Nsec {
+    /// Next owner name of the zone in canonical order
+    pub next_domain: String,
+    /// Types present at the owner name, kept as numbers as they may be any
+    /// type
+    pub types: Vec<u16>,
+}
+
+impl Nsec {
+    pub fn parse(rdata: &[u8]) -> Result<Self> {
+        let mut reader = Cursor::new(rdata);
+        let next_domain = read_name(&mut reader)?;
+        let types = parse_type_bitmap(&rdata[reader.position() as usize..])?;
+
+        Ok(Self { next_domain, types })
+    }
+
+    pub fn has_type(&self, rtype: QType) -> bool {
+        self.types.contains(&qtype_as_u16(rtype))
+    }
+}
+
+/// RDATA of a NSEC3 record, RFC 5155 3
+#[derive(Clone, Debug, Hash, Eq, PartialEq)]
+pub struct Nsec3 {
+    /// 1 -> SHA-1, the only one defined
+    pub hash_algorithm: u8,
+    pub flags: u8,
+    /// Additional hash iterations
+    pub iterations: u16,
+    pub salt: Vec<u8>,
+    /// Next hashed owner name of the zone in hash order
+    pub next_hashed_owner: Vec<u8>,
+    /// Types present at the original owner name, kept as numbers as they
+    /// may be any type
+    pub types: Vec<u16>,
+}
+
+impl Nsec3 {
+    /// The span may contain unsigned delegations
+    pub const OPT_OUT: u8 = 0x01;
+
+    pub fn parse(rdata: &[u8]) -> Result<Self> {
+        let mut reader = Cursor::new(rdata);
+        let hash_algorithm = reader.read_u8()?;
+        let flags = reader.read_u8()?;
+        let iterations = reader.read_u16()?;
+        let salt = read_sized(&mut reader, rdata)?;
+        let next_hashed_owner = read_sized(&mut reader, rdata)?;
+        let types = parse_type_bitmap(&rdata[reader.position() as usize..])?;
+
+        Ok(Self {
+            hash_algorithm,
+            flags,
+            iterations,
+            salt,
+            next_hashed_owner,
+            types,
+        })
+    }
+
+    pub fn has_type(&self, rtype: QType) -> bool {
+        self.types.contains(&qtype_as_u16(rtype))
+    }
+
+    pub fn is_opt_out(&self) -> bool {
+        self.flags & Self::OPT_OUT != 0
+    }
+}
+
+/// Field prefixed by its length in one byte
+fn read_sized(reader: &mut Cursor<&[u8]>, rdata: &[u8]) -> Result<Vec<u8>> {
+    let length = usize::from(reader.read_u8()?);
+    let start = reader.position() as usize;
+    let field = rdata
+        .get(start..start + length)
+        .ok_or(DnsParseError::InvalidRdata)?;
+    reader.set_position((start + length) as u64);
+    Ok(field.to_vec())
+}
+
+/// Type bit maps field of NSEC and NSEC3 records (RFC 4034 4.1.2)
+fn parse_type_bitmap(mut bitmap: &[u8]) -> Result<Vec<u16>> {
+    let mut types = Vec::new();
+
+    while let [window, length, rest @ ..] = bitmap {
+        let length = usize::from(*length);
+        if length == 0 || length > 32 || rest.len() < length {
+            return Err(DnsParseError::InvalidRdata);
+        }
+
+        for (i, byte) in rest[..length].iter().enumerate() {
+            for bit in 0..8 {
+                if byte & (0x80 >> bit) != 0 {
+                    types.push(u16::from(*window) << 8 | (i * 8 + bit) as u16);
+                }
+            }
+        }
+        bitmap = &rest[length..];
+    }
+
+    if bitmap.is_empty() {
+        Ok(types)
+    } else {
+        Err(DnsParseError::InvalidRdata)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_dnskey_rdata() {
+        let dnskey = Dnskey {
+            flags: 256,
+            protocol: 3,
+            algorithm: Algorithm::Ed25519,
+            public_key: vec![1; 32],
+        };
+        assert_eq!(Dnskey::parse(&dnskey.rdata()).unwrap(), dnskey);
+        assert_eq!(dnskey.rdata()[..4], [1, 0, 3, 15]);
+        assert!(dnskey.is_zone_key());
+        assert!(!dnskey.is_secure_entry_point());
+    }
+
+    #[test]
+    pub fn test_expanded_from() {
+        let rrsig = Rrsig {
+            type_covered: 1,
+            algorithm: Algorithm::Ed25519,
+            labels: 2,
+            original_ttl: 3600,
+            expiration: 0,
+            inception: 0,
+            key_tag: 0,
+            signer_name: "example.com".into(),
+            signature: Vec::new(),
+        };
+
+        assert_eq!(rrsig.expanded_from("*.example.com"), None);
+        assert_eq!(
+            rrsig.expanded_from("www.example.com"),
+            Some("*.example.com".into())
+        );
+        assert_eq!(
+            rrsig.expanded_from("a.b.example.com"),
+            Some("*.example.com".into())
+        );
+        assert_eq!(rrsig.expanded_from("example.com"), None);
+    }
+
+    #[test]
+    pub fn test_parse_invalid() {
+        assert!(Dnskey::parse(&[1, 1, 3]).is_err());
+        assert!(Ds::parse(&[0, 1, 8]).is_err());
+        assert!(Rrsig::parse(&[0, 1, 8, 2, 0, 0, 14, 16]).is_err());
+        assert!(Nsec::parse(&[0, 0, 0]).is_err());
+        assert!(Nsec::parse(&[0, 0, 1, 64, 0]).is_err());
+        assert!(Nsec3::parse(&[1, 0, 0, 12, 4, 170, 187]).is_err());
+    }
+
+    #[test]
+    pub fn test_type_bitmap() {
+        // example of RFC 4034 4.3
+        let mut rdata = Writer::new()
+            .write_name_uncompressed("host.example.com")
+            .build();
+        rdata.extend_from_slice(&[0, 6, 64, 1, 0, 0, 0, 3, 4, 27, 0, 0, 0, 0, 0, 0]);
+        rdata.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        rdata.extend_from_slice(&[0, 0, 0, 0, 32]);
+
+        let nsec = Nsec::parse(&rdata).unwrap();
+        assert_eq!(nsec.next_domain, "host.example.com");
+        assert_eq!(nsec.types, vec![1, 15, 46, 47, 1234]);
+        assert!(nsec.has_type(QType::MX));
+        assert!(!nsec.has_type(QType::AAAA));
+    }
+
+    #[test]
+    pub fn test_nsec3_rdata() {
+        let rdata = [1, 1, 0, 12, 4, 170, 187, 204, 221, 2, 1, 2, 0, 1, 64];
+        let nsec3 = Nsec3::parse(&rdata).unwrap();
+        assert_eq!(nsec3.iterations, 12);
+        assert_eq!(nsec3.salt, vec![170, 187, 204, 221]);
+        assert_eq!(nsec3.next_hashed_owner, vec![1, 2]);
+        assert_eq!(nsec3.types, vec![1]);
+        assert!(nsec3.is_opt_out());
+
+        // empty non terminals have no types at all
+        let nsec3 = Nsec3::parse(&rdata[..12]).unwrap();
+        assert!(nsec3.types.is_empty());
+    }
+}
@@ -0,0 +1,230 @@
+use crate::dns::DNS;
+use crate::error::*;
+
+const BASE32HEX_ALPHABET: &[u8; 32] = b"0123456789abcdefghijklmnopqrstuv";
+const BASE64URL_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+/// Encodes the bytes as lowercase hex string
+pub fn hex_encode(bytes: &[u8]) -> String {
+    bytes.iter().map(|x| format!("{:02x}", x)).collect()
+}
+
+/// Decodes a hex string, whitespace is ignored so that multi line dumps
+/// can be pasted as they are
+pub fn hex_decode(hex: &str) -> Result<Vec<u8>> {
+    let digits = hex
+        .chars()
+        .filter(|x| !x.is_whitespace())
+        .map(|x| x.to_digit(16).map(|x| x as u8))
+        .collect::<Option<Vec<u8>>>()
+        .ok_or(DnsParseError::InvalidEncoding)?;
+
+    if digits.len() % 2 != 0 {
+        return Err(DnsParseError::InvalidEncoding);
+    }
+
+    Ok(digits.chunks(2).map(|x| x[0] << 4 | x[1]).collect())
+}
+
+/// Encodes the bytes as base64url without padding (RFC 4648 5), as used by
+/// the `dns` parameter of DNS over HTTPS GET requests (RFC 8484 4.1)
+pub fn base64url_encode(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+
+    for chunk in bytes.chunks(3) {
+        let value = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |acc, (i, x)| acc | u32::from(*x) << (16 - i * 8));
+
+        // every byte of input results in at least two characters
+        for i in 0..=chunk.len() {
+            let index = (value >> (18 - i * 6)) & 0x3F;
+            encoded.push(BASE64URL_ALPHABET[index as usize] as char);
+        }
+    }
+
+    encoded
+}
+
+/// Decodes base64url, trailing padding is accepted but not required
+pub fn base64url_decode(encoded: &str) -> Result<Vec<u8>> {
+    let encoded = encoded.trim_end_matches('=');
+    if encoded.len() % 4 == 1 {
+        return Err(DnsParseError::InvalidEncoding);
+    }
+
+    let mut decoded = Vec::with_capacity(encoded.len() * 3 / 4);
+    for chunk in encoded.as_bytes().chunks(4) {
+        let mut value = 0u32;
+        for (i, x) in chunk.iter().enumerate() {
+            let index = BASE64URL_ALPHABET
+                .iter()
+                .position(|y| y == x)
+                .ok_or(DnsParseError::InvalidEncoding)?;
+            value |= (index as u32) << (18 - i * 6);
+        }
+
+        for i in 0..chunk.len() - 1 {
+            decoded.push((value >> (16 - i * 8)) as u8);
+        }
+    }
+
+    Ok(decoded)
+}
+
+/// Encodes the bytes as lowercase base32hex without padding (RFC 4648 7),
+/// as used by the hashed owner names of NSEC3 records (RFC 5155 3.3)
+pub fn base32hex_encode(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(5) * 8);
+
+    for chunk in bytes.chunks(5) {
+        let value = chunk
+            .iter()
+            .enumerate()
+            .fold(0u64, |acc, (i, x)| acc | u64::from(*x) << (32 - i * 8));
+
+        for i in 0..(chunk.len() * 8).div_ceil(5) {
+            let index = (value >> (35 - i * 5)) & 0x1F;
+            encoded.push(BASE32HEX_ALPHABET[index as usize] as char);
+        }
+    }
+
+    encoded
+}
+
+/// Decodes base32hex case insensitive, trailing padding is accepted but not
+/// required
+pub fn base32hex_decode(encoded: &str) -> Result<Vec<u8>> {
+    let encoded = encoded.trim_end_matches('=');
+    if let 1 | 3 | 6 = encoded.len() % 8 {
+        return Err(DnsParseError::InvalidEncoding);
+    }
+
+    let mut decoded = Vec::with_capacity(encoded.len() * 5 / 8);
+    for chunk in encoded.as_bytes().chunks(8) {
+        let mut value = 0u64;
+        for (i, x) in chunk.iter().enumerate() {
+            let index = BASE32HEX_ALPHABET
+                .iter()
+                .position(|y| y.eq_ignore_ascii_case(x))
+                .ok_or(DnsParseError::InvalidEncoding)?;
+            value |= (index as u64) << (35 - i * 5);
+        }
+
+        for i in 0..chunk.len() * 5 / 8 {
+            decoded.push((value >> (32 - i * 8)) as u8);
+        }
+    }
+
+    Ok(decoded)
+}
+
+impl DNS {
+    /// Wire format of the message as hex string
+    pub fn to_hex(&self) -> String {
+        hex_encode(&self.clone().build())
+    }
+
+    /// Parses a message from its wire format given as hex string
+    pub fn from_hex(hex: &str) -> Result<Self> {
+        Self::parse(hex_decode(hex)?)
+    }
+
+    /// Wire format of the message as unpadded base64url string, ready to be
+    /// used as `dns` parameter of a DNS over HTTPS GET request
+    pub fn to_base64url(&self) -> String {
+        base64url_encode(&self.clone().build())
+    }
+
+    /// Parses a message from its wire format given as base64url string
+    pub fn from_base64url(encoded: &str) -> Result<Self> {
+        Self::parse(base64url_decode(encoded)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_hex() {
+        assert_eq!(hex_encode(&[0, 15, 16, 255]), "000f10ff");
+        assert_eq!(hex_decode("000f10ff").unwrap(), vec![0, 15, 16, 255]);
+        assert_eq!(hex_decode("00 0F\n10 fF").unwrap(), vec![0, 15, 16, 255]);
+        assert_eq!(hex_decode("").unwrap(), Vec::<u8>::new());
+        assert!(hex_decode("0").is_err());
+        assert!(hex_decode("0g").is_err());
+    }
+
+    #[test]
+    pub fn test_base64url() {
+        // test vectors of RFC 4648 10
+        let vectors = [
+            ("", ""),
+            ("f", "Zg"),
+            ("fo", "Zm8"),
+            ("foo", "Zm9v"),
+            ("foob", "Zm9vYg"),
+            ("fooba", "Zm9vYmE"),
+            ("foobar", "Zm9vYmFy"),
+        ];
+        for (decoded, encoded) in vectors.iter() {
+            assert_eq!(base64url_encode(decoded.as_bytes()), *encoded);
+            assert_eq!(base64url_decode(encoded).unwrap(), decoded.as_bytes());
+        }
+
+        assert_eq!(base64url_encode(&[251, 255]), "-_8");
+        assert_eq!(base64url_decode("-_8=").unwrap(), vec![251, 255]);
+        assert!(base64url_decode("Zm9vY").is_err());
+        assert!(base64url_decode("Zm9v+g").is_err());
+    }
+
+    #[test]
+    pub fn test_base32hex() {
+        // test vectors of RFC 4648 10
+        let vectors = [
+            ("", ""),
+            ("f", "CO======"),
+            ("fo", "CPNG===="),
+            ("foo", "CPNMU==="),
+            ("foob", "CPNMUOG="),
+            ("fooba", "CPNMUOJ1"),
+            ("foobar", "CPNMUOJ1E8======"),
+        ];
+        for (decoded, encoded) in vectors.iter() {
+            let unpadded = encoded.trim_end_matches('=').to_ascii_lowercase();
+            assert_eq!(base32hex_encode(decoded.as_bytes()), unpadded);
+            assert_eq!(base32hex_decode(encoded).unwrap(), decoded.as_bytes());
+            assert_eq!(base32hex_decode(&unpadded).unwrap(), decoded.as_bytes());
+        }
+
+        assert!(base32hex_decode("CPNMUOJ1E").is_err());
+        assert!(base32hex_decode("CPNMUOJW").is_err());
+    }
+
+    #[test]
+    pub fn test_dns_base64url() {
+        // example query of RFC 8484 4.1.1
+        let encoded = "AAABAAABAAAAAAAAA3d3dwdleGFtcGxlA2NvbQAAAQAB";
+        let dns = DNS::from_base64url(encoded).unwrap();
+
+        assert_eq!(dns.questions[0].qname, "www.example.com");
+        assert_eq!(dns.rd, 1);
+        assert_eq!(dns.to_base64url(), encoded);
+    }
+
+    #[test]
+    pub fn test_dns_hex() {
+        let hex = "349e0100 00010000 00000000
+                   0377 7777 0667 6f6f 676c 6502 6465 0000 0100 01";
+        let dns = DNS::from_hex(hex).unwrap();
+
+        assert_eq!(dns.questions[0].qname, "www.google.de");
+        assert_eq!(
+            dns.to_hex(),
+            "349e010000010000000000000377777706676f6f676c650264650000010001"
+        );
+    }
+}
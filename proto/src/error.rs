@@ -7,6 +7,23 @@ pub(crate) type Result<T> = std::result::Result<T, DnsParseError>;
 pub enum DnsParseError {
     IoError(std::io::Error),
     StringParseError(std::string::FromUtf8Error),
+    /// A domain name uses a reserved label type, a pointer that does not
+    /// point backwards or exceeds the maximum length of 255 bytes
+    InvalidName,
+    /// The length of an EDNS option does not fit into the OPT record
+    InvalidOption,
+    /// The RDATA does not match the layout of its type
+    InvalidRdata,
+    /// The name can not be converted between its Unicode and ASCII form
+    InvalidIdn(String),
+    /// The message is longer than allowed by the length prefix or limit
+    MessageTooLong(usize),
+    /// The input is not valid hex or base64url
+    InvalidEncoding,
+    /// The JSON representation of a message is invalid or incomplete
+    InvalidJson(String),
+    /// The pcap or pcapng file is malformed
+    InvalidCapture(String),
 }
 
 impl Error for DnsParseError {}
@@ -0,0 +1,80 @@
+use crate::error::*;
+
+use idna::uts46::{AsciiDenyList, DnsLength, Hyphens, Uts46};
+
+/// Converts a domain name into its ASCII form, as used on the wire
+///
+/// The name is mapped and normalized as described in UTS #46 using
+/// nontransitional processing, so it is compatible with IDNA2008. Every
+/// label containing non ASCII characters is encoded as A-label (`xn--`).
+/// The mapping also lowercases the name.
+///
+/// Underscores are allowed, as they are common in DNS (`_dmarc`, `_tcp`).
+pub fn domain_to_ascii(name: &str) -> Result<String> {
+    if is_root(name) {
+        return Ok(name.to_string());
+    }
+
+    Uts46::new()
+        .to_ascii(
+            name.as_bytes(),
+            AsciiDenyList::EMPTY,
+            Hyphens::Allow,
+            DnsLength::VerifyAllowRootDot,
+        )
+        .map(|x| x.into_owned())
+        .map_err(|_| DnsParseError::InvalidIdn(name.to_string()))
+}
+
+/// Converts a domain name into its Unicode form, decoding all A-labels
+///
+/// Fails if an A-label is not valid punycode or decodes to a label that is
+/// not allowed by UTS #46.
+pub fn domain_to_unicode(name: &str) -> Result<String> {
+    if is_root(name) {
+        return Ok(name.to_string());
+    }
+
+    let (unicode, result) =
+        Uts46::new().to_unicode(name.as_bytes(), AsciiDenyList::EMPTY, Hyphens::Allow);
+    result
+        .map(|_| unicode.into_owned())
+        .map_err(|_| DnsParseError::InvalidIdn(name.to_string()))
+}
+
+fn is_root(name: &str) -> bool {
+    name.is_empty() || name == "."
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_to_ascii() {
+        assert_eq!(domain_to_ascii("bücher.de").unwrap(), "xn--bcher-kva.de");
+        assert_eq!(domain_to_ascii("BÜCHER.de.").unwrap(), "xn--bcher-kva.de.");
+        assert_eq!(domain_to_ascii("www.google.de").unwrap(), "www.google.de");
+        assert_eq!(
+            domain_to_ascii("_dmarc.Example.COM").unwrap(),
+            "_dmarc.example.com"
+        );
+        assert_eq!(domain_to_ascii("").unwrap(), "");
+        // nontransitional processing keeps the sharp s
+        assert_eq!(domain_to_ascii("faß.de").unwrap(), "xn--fa-hia.de");
+    }
+
+    #[test]
+    pub fn test_to_ascii_invalid() {
+        assert!(domain_to_ascii("xn--a.de").is_err());
+        assert!(domain_to_ascii("www..de").is_err());
+        assert!(domain_to_ascii(&format!("{}.de", "a".repeat(64))).is_err());
+    }
+
+    #[test]
+    pub fn test_to_unicode() {
+        assert_eq!(domain_to_unicode("xn--bcher-kva.de").unwrap(), "bücher.de");
+        assert_eq!(domain_to_unicode("www.google.de").unwrap(), "www.google.de");
+        assert!(domain_to_unicode("xn--a.de").is_err());
+    }
+}
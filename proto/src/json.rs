@@ -0,0 +1,543 @@
+use crate::dns::*;
+use crate::encoding::{hex_decode, hex_encode};
+use crate::error::*;
+use crate::idn::domain_to_ascii;
+use crate::mdns::CLASS_FLAG;
+use crate::qclass::QClass;
+use crate::qtype::{as_u16 as qtype_as_u16, QType};
+use crate::reader::read_name;
+use crate::writer::Writer;
+
+use serde_json::{json, Map, Value};
+use std::io::Cursor;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+/// Conversion between messages and their JSON representation, RFC 8427
+///
+/// Every record carries its RDATA as `RDATAHEX`, so no information is lost.
+/// For A, AAAA, CNAME, NS and PTR records the decoded RDATA is added as
+/// well, for example `rdataA`.
+impl DNS {
+    pub fn to_json(&self) -> String {
+        let mut object = Map::new();
+        object.insert("ID".into(), json!(self.id));
+        object.insert("QR".into(), json!(self.qr));
+        object.insert("Opcode".into(), json!(opcode_as_u8(&self.opcode)));
+        object.insert("AA".into(), json!(self.aa));
+        object.insert("TC".into(), json!(self.tc));
+        object.insert("RD".into(), json!(self.rd));
+        object.insert("RA".into(), json!(self.ra));
+        object.insert("AD".into(), json!(self.ad));
+        object.insert("CD".into(), json!(self.cd));
+        object.insert("RCODE".into(), json!(rcode_as_u8(&self.rcode)));
+        object.insert("QDCOUNT".into(), json!(self.questions.len()));
+        object.insert("ANCOUNT".into(), json!(self.resource_records.len()));
+        object.insert("NSCOUNT".into(), json!(self.authorities.len()));
+        object.insert(
+            "ARCOUNT".into(),
+            json!(self.additionals.len() + self.edns.iter().count()),
+        );
+
+        if let [question] = self.questions.as_slice() {
+            object.insert("QNAME".into(), json!(presentation(&question.qname)));
+            object.insert("QTYPE".into(), json!(qtype_as_u16(question.qtype)));
+            object.insert("QTYPEname".into(), json!(type_mnemonic(question.qtype)));
+            object.insert(
+                "QCLASS".into(),
+                json!(join_class(question.qclass, question.unicast_response)),
+            );
+            object.insert("QCLASSname".into(), json!(class_mnemonic(question.qclass)));
+        } else if !self.questions.is_empty() {
+            let questions = self
+                .questions
+                .iter()
+                .map(|x| {
+                    json!({
+                        "NAME": presentation(&x.qname),
+                        "TYPE": qtype_as_u16(x.qtype),
+                        "TYPEname": type_mnemonic(x.qtype),
+                        "CLASS": join_class(x.qclass, x.unicast_response),
+                        "CLASSname": class_mnemonic(x.qclass),
+                    })
+                })
+                .collect();
+            object.insert("questionRRs".into(), Value::Array(questions));
+        }
+
+        let sections = [
+            ("answerRRs", &self.resource_records),
+            ("authorityRRs", &self.authorities),
+        ];
+        for (key, records) in sections.iter() {
+            if !records.is_empty() {
+                let records = records.iter().map(record_to_json).collect();
+                object.insert(key.to_string(), Value::Array(records));
+            }
+        }
+
+        let mut additionals = self
+            .additionals
+            .iter()
+            .map(record_to_json)
+            .collect::<Vec<Value>>();
+        if let Some(edns) = &self.edns {
+            additionals.push(edns_to_json(edns));
+        }
+        if !additionals.is_empty() {
+            object.insert("additionalRRs".into(), Value::Array(additionals));
+        }
+
+        Value::Object(object).to_string()
+    }
+
+    /// Parses the JSON representation of a message
+    ///
+    /// If `messageOctetsHEX` is present, the message is parsed from it and
+    /// all other members are ignored. Missing header members default to
+    /// zero, missing counts to the number of given entries. Without
+    /// `RDATAHEX`, RDATA is only taken from `rdataA`, `rdataAAAA`,
+    /// `rdataCNAME`, `rdataNS` and `rdataPTR`.
+    pub fn from_json(json: &str) -> Result<Self> {
+        let value: Value = serde_json::from_str(json).map_err(|e| invalid(&e.to_string()))?;
+        let object = value
+            .as_object()
+            .ok_or_else(|| invalid("expected an object"))?;
+
+        if let Some(hex) = object.get("messageOctetsHEX") {
+            let hex = hex
+                .as_str()
+                .ok_or_else(|| invalid("messageOctetsHEX: expected a string"))?;
+            return Self::parse(hex_decode(hex)?);
+        }
+
+        let mut questions = Vec::new();
+        if let Some(qname) = object.get("QNAME") {
+            let (qclass, unicast_response) = qclass(object.get("QCLASS"), "QCLASS")?;
+            questions.push(Question {
+                qname: name(qname, "QNAME")?,
+                qtype: qtype(object.get("QTYPE"), "QTYPE")?,
+                qclass,
+                unicast_response,
+            });
+        }
+        for entry in array(object, "questionRRs")? {
+            let (qclass, unicast_response) = qclass(entry.get("CLASS"), "CLASS")?;
+            questions.push(Question {
+                qname: name(member(entry, "NAME")?, "NAME")?,
+                qtype: qtype(entry.get("TYPE"), "TYPE")?,
+                qclass,
+                unicast_response,
+            });
+        }
+
+        let resource_records = array(object, "answerRRs")?
+            .iter()
+            .map(record_from_json)
+            .collect::<Result<Vec<ResourceRecord>>>()?;
+        let authorities = array(object, "authorityRRs")?
+            .iter()
+            .map(record_from_json)
+            .collect::<Result<Vec<ResourceRecord>>>()?;
+
+        let mut additionals = Vec::new();
+        let mut edns = None;
+        for entry in array(object, "additionalRRs")? {
+            if number(entry.get("TYPE"), "TYPE", u16::MAX.into())? == Some(u64::from(OPT)) {
+                edns = Some(edns_from_json(entry)?);
+            } else {
+                additionals.push(record_from_json(entry)?);
+            }
+        }
+
+        let count = |key: &str, default: usize| -> Result<u16> {
+            let count = number(object.get(key), key, u16::MAX.into())?;
+            Ok(count.unwrap_or(default as u64) as u16)
+        };
+        let field = |key: &str, max: u64| -> Result<u8> {
+            Ok(number(object.get(key), key, max)?.unwrap_or(0) as u8)
+        };
+        let flag = |key: &str| field(key, 1);
+
+        Ok(Self {
+            id: number(object.get("ID"), "ID", u16::MAX.into())?.unwrap_or(0) as u16,
+            qr: flag("QR")?,
+            opcode: Opcode::from(&bits(field("Opcode", 15)?)[..]),
+            aa: flag("AA")?,
+            tc: flag("TC")?,
+            rd: flag("RD")?,
+            ra: flag("RA")?,
+            z: 0,
+            ad: flag("AD")?,
+            cd: flag("CD")?,
+            rcode: Rcode::from(&bits(field("RCODE", 15)?)[..]),
+            qdcount: count("QDCOUNT", questions.len())?,
+            ancount: count("ANCOUNT", resource_records.len())?,
+            nscount: count("NSCOUNT", authorities.len())?,
+            arcount: count("ARCOUNT", additionals.len() + edns.iter().count())?,
+            questions,
+            resource_records,
+            authorities,
+            additionals,
+            edns,
+        })
+    }
+}
+
+fn record_to_json(record: &ResourceRecord) -> Value {
+    let mut object = Map::new();
+    object.insert("NAME".into(), json!(presentation(&record.name)));
+    object.insert("TYPE".into(), json!(qtype_as_u16(record.rtype)));
+    object.insert("TYPEname".into(), json!(type_mnemonic(record.rtype)));
+    object.insert(
+        "CLASS".into(),
+        json!(join_class(record.rclass, record.cache_flush)),
+    );
+    object.insert("CLASSname".into(), json!(class_mnemonic(record.rclass)));
+    object.insert("TTL".into(), json!(record.ttl));
+    object.insert("RDLENGTH".into(), json!(record.rdata.len()));
+    object.insert("RDATAHEX".into(), json!(hex_encode(&record.rdata)));
+
+    let rdata = &record.rdata;
+    match record.rtype {
+        QType::A if rdata.len() == 4 => {
+            let addr = Ipv4Addr::new(rdata[0], rdata[1], rdata[2], rdata[3]);
+            object.insert("rdataA".into(), json!(addr.to_string()));
+        }
+        QType::AAAA if rdata.len() == 16 => {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(rdata);
+            object.insert(
+                "rdataAAAA".into(),
+                json!(Ipv6Addr::from(octets).to_string()),
+            );
+        }
+        QType::CNAME | QType::NS | QType::PTR => {
+            if let Ok(target) = read_name(&mut Cursor::new(rdata.as_slice())) {
+                let key = format!("rdata{:?}", record.rtype);
+                object.insert(key, json!(presentation(&target)));
+            }
+        }
+        _ => (),
+    }
+
+    Value::Object(object)
+}
+
+fn record_from_json(value: &Value) -> Result<ResourceRecord> {
+    let rtype = qtype(value.get("TYPE"), "TYPE")?;
+
+    let rdata = if let Some(hex) = value.get("RDATAHEX") {
+        hex_decode(
+            hex.as_str()
+                .ok_or_else(|| invalid("RDATAHEX: expected a string"))?,
+        )?
+    } else {
+        let key = format!("rdata{:?}", rtype);
+        let data = value
+            .get(&key)
+            .and_then(|x| x.as_str())
+            .ok_or_else(|| invalid(&format!("{}: missing RDATAHEX", key)))?;
+        let invalid_data = || invalid(&format!("{}: invalid value {:?}", key, data));
+
+        match rtype {
+            QType::A => data
+                .parse::<Ipv4Addr>()
+                .map_err(|_| invalid_data())?
+                .octets()
+                .to_vec(),
+            QType::AAAA => data
+                .parse::<Ipv6Addr>()
+                .map_err(|_| invalid_data())?
+                .octets()
+                .to_vec(),
+            QType::CNAME | QType::NS | QType::PTR => Writer::new()
+                .write_name_uncompressed(&name(&json!(data), &key)?)
+                .build(),
+            _ => return Err(invalid(&format!("{}: missing RDATAHEX", key))),
+        }
+    };
+
+    let (rclass, cache_flush) = qclass(value.get("CLASS"), "CLASS")?;
+    Ok(ResourceRecord {
+        name: name(member(value, "NAME")?, "NAME")?,
+        rtype,
+        rclass,
+        cache_flush,
+        ttl: number(value.get("TTL"), "TTL", u32::MAX.into())?.unwrap_or(0) as u32,
+        rdlength: rdata.len() as u16,
+        rdata,
+    })
+}
+
+/// The OPT record is represented like any other record, with the payload
+/// size as CLASS and the flags as TTL
+fn edns_to_json(edns: &Edns) -> Value {
+    // root name and type are followed by class, ttl, rdlength and rdata
+    let record = edns.clone().write(Writer::new()).build();
+    let class = u16::from_be_bytes([record[3], record[4]]);
+    let ttl = u32::from_be_bytes([record[5], record[6], record[7], record[8]]);
+    let rdata = &record[11..];
+
+    json!({
+        "NAME": ".",
+        "TYPE": OPT,
+        "TYPEname": "OPT",
+        "CLASS": class,
+        "TTL": ttl,
+        "RDLENGTH": rdata.len(),
+        "RDATAHEX": hex_encode(rdata),
+    })
+}
+
+fn edns_from_json(value: &Value) -> Result<Edns> {
+    let rdata = match value.get("RDATAHEX") {
+        Some(x) => hex_decode(
+            x.as_str()
+                .ok_or_else(|| invalid("RDATAHEX: expected a string"))?,
+        )?,
+        None => Vec::new(),
+    };
+    let class = number(value.get("CLASS"), "CLASS", u16::MAX.into())?.unwrap_or(512) as u16;
+    let ttl = number(value.get("TTL"), "TTL", u32::MAX.into())?.unwrap_or(0) as u32;
+
+    let record = Writer::new()
+        .write_u16_be(class)
+        .write_u32_be(ttl)
+        .write_u16_be(rdata.len() as u16)
+        .write_vec(rdata)
+        .build();
+    Edns::parse(&mut Cursor::new(record.as_slice()))
+}
+
+fn opcode_as_u8(opcode: &Opcode) -> u8 {
+    let bits: &[u8] = opcode.clone().into();
+    bits.iter().fold(0, |acc, x| acc << 1 | x)
+}
+
+fn rcode_as_u8(rcode: &Rcode) -> u8 {
+    let bits: &[u8] = rcode.clone().into();
+    bits.iter().fold(0, |acc, x| acc << 1 | x)
+}
+
+/// Lower four bits of the value, most significant first
+fn bits(value: u8) -> [u8; 4] {
+    [value >> 3 & 1, value >> 2 & 1, value >> 1 & 1, value & 1]
+}
+
+/// Mnemonic of the type, `TYPEnnn` for types without one (RFC 3597)
+fn type_mnemonic(qtype: QType) -> String {
+    match qtype {
+        QType::Unknown(x) => format!("TYPE{}", x),
+        x => format!("{:?}", x),
+    }
+}
+
+/// Mnemonic of the class, `CLASSnnn` for classes without one (RFC 3597)
+fn class_mnemonic(qclass: QClass) -> String {
+    match qclass {
+        QClass::Unknown(x) => format!("CLASS{}", x),
+        x => format!("{:?}", x),
+    }
+}
+
+/// Names are written without trailing dot, except for the root name
+fn presentation(name: &str) -> &str {
+    if name.is_empty() {
+        "."
+    } else {
+        name
+    }
+}
+
+/// Names have to be valid as described for `domain_to_ascii`, so that
+/// they can be written
+fn name(value: &Value, key: &str) -> Result<String> {
+    let name = value
+        .as_str()
+        .ok_or_else(|| invalid(&format!("{}: expected a string", key)))?;
+    domain_to_ascii(name).map_err(|_| invalid(&format!("{}: invalid name {:?}", key, name)))?;
+    Ok(name.trim_end_matches('.').to_string())
+}
+
+/// Number of at most `max`, so that it fits into its field
+fn number(value: Option<&Value>, key: &str, max: u64) -> Result<Option<u64>> {
+    match value {
+        None => Ok(None),
+        Some(x) => x
+            .as_u64()
+            .filter(|x| *x <= max)
+            .map(Some)
+            .ok_or_else(|| invalid(&format!("{}: expected a number up to {}", key, max))),
+    }
+}
+
+fn qtype(value: Option<&Value>, key: &str) -> Result<QType> {
+    let number = number(value, key, u16::MAX.into())?
+        .ok_or_else(|| invalid(&format!("{}: missing", key)))?;
+    Ok(QType::from(number as u16))
+}
+
+/// Class and the mDNS flag of its top bit
+fn qclass(value: Option<&Value>, key: &str) -> Result<(QClass, bool)> {
+    let value = number(value, key, u16::MAX.into())?.unwrap_or(1) as u16;
+    Ok((QClass::from(value & !CLASS_FLAG), value & CLASS_FLAG != 0))
+}
+
+fn member<'a>(value: &'a Value, key: &str) -> Result<&'a Value> {
+    value
+        .get(key)
+        .ok_or_else(|| invalid(&format!("{}: missing", key)))
+}
+
+fn array<'a>(object: &'a Map<String, Value>, key: &str) -> Result<&'a [Value]> {
+    match object.get(key) {
+        None => Ok(&[]),
+        Some(Value::Array(x)) => Ok(x),
+        Some(_) => Err(invalid(&format!("{}: expected an array", key))),
+    }
+}
+
+fn invalid(message: &str) -> DnsParseError {
+    DnsParseError::InvalidJson(message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_to_json() {
+        let dns = DNS::from_hex(
+            "349e818000010001000000000377777706676f6f676c650264650000010001
+             c00c000100010000012b0004acd91017",
+        )
+        .unwrap();
+
+        assert_eq!(
+            dns.to_json(),
+            concat!(
+                r#"{"ID":13470,"QR":1,"Opcode":0,"AA":0,"TC":0,"RD":1,"RA":1,"AD":0,"CD":0,"#,
+                r#""RCODE":0,"QDCOUNT":1,"ANCOUNT":1,"NSCOUNT":0,"ARCOUNT":0,"#,
+                r#""QNAME":"www.google.de","QTYPE":1,"QTYPEname":"A","QCLASS":1,"#,
+                r#""QCLASSname":"IN","answerRRs":[{"NAME":"www.google.de","TYPE":1,"#,
+                r#""TYPEname":"A","CLASS":1,"CLASSname":"IN","TTL":299,"RDLENGTH":4,"#,
+                r#""RDATAHEX":"acd91017","rdataA":"172.217.16.23"}]}"#
+            )
+        );
+    }
+
+    #[test]
+    pub fn test_json_roundtrip() {
+        let mut dns = DNS::from_hex(
+            "224c81800001000300000000037777770667697468756203636f6d0000010001
+             c00c00050001000004930002c010c010000100010000003b0004c01efd71
+             c010000100010000003b0004c01efd70",
+        )
+        .unwrap();
+        dns.add_extended_error(ExtendedError::new(ExtendedErrorCode::Blocked, "ads"));
+
+        let json = dns.to_json();
+        assert!(json.contains(r#""rdataCNAME":"github.com""#));
+        assert!(json.contains(r#""TYPEname":"OPT""#));
+
+        let mut expected = dns.clone();
+        expected.arcount = 1;
+        assert_eq!(DNS::from_json(&json).unwrap(), expected);
+    }
+
+    #[test]
+    pub fn test_json_roundtrip_unknown() {
+        let dns = DNS::from_json(
+            r#"{ "QNAME": "example.com", "QTYPE": 65535, "QCLASS": 3,
+                 "answerRRs": [ { "NAME": "example.com", "TYPE": 65,
+                                  "CLASS": 3, "RDATAHEX": "0001" } ] }"#,
+        )
+        .unwrap();
+        assert_eq!(dns.questions[0].qtype, QType::Unknown(65535));
+        assert_eq!(dns.questions[0].qclass, QClass::Unknown(3));
+        assert_eq!(dns.resource_records[0].rtype, QType::Unknown(65));
+
+        let json = dns.to_json();
+        assert!(json.contains(r#""QTYPEname":"TYPE65535""#));
+        assert!(json.contains(r#""QCLASSname":"CLASS3""#));
+        assert!(json.contains(r#""TYPEname":"TYPE65""#));
+        assert!(json.contains(r#""CLASSname":"CLASS3""#));
+        assert_eq!(DNS::from_json(&json).unwrap(), dns);
+    }
+
+    #[test]
+    pub fn test_from_json() {
+        // example of RFC 8427 A.1, shortened to the supported members
+        let dns = DNS::from_json(
+            r#"{ "ID": 19678, "QR": 0, "Opcode": 0, "AA": 0, "TC": 0, "RD": 0,
+                 "RA": 0, "AD": 0, "CD": 0, "RCODE": 0, "QDCOUNT": 1,
+                 "ANCOUNT": 0, "NSCOUNT": 0, "ARCOUNT": 0,
+                 "QNAME": "example.com.", "QTYPE": 1, "QCLASS": 1 }"#,
+        )
+        .unwrap();
+        assert_eq!(dns.id, 19678);
+        assert_eq!(dns.questions[0].qname, "example.com");
+        assert_eq!(dns.questions[0].qtype, QType::A);
+
+        let dns = DNS::from_json(
+            r#"{ "QR": 1, "RCODE": 3, "answerRRs": [
+                   { "NAME": "example.com", "TYPE": 28, "TTL": 60,
+                     "rdataAAAA": "2001:db8::1" },
+                   { "NAME": "1.0.0.127.in-addr.arpa", "TYPE": 12,
+                     "rdataPTR": "localhost." } ] }"#,
+        )
+        .unwrap();
+        assert_eq!(dns.rcode, Rcode::NameError);
+        assert_eq!(dns.ancount, 2);
+        assert_eq!(dns.resource_records[0].rdata[..2], [32, 1]);
+        assert_eq!(dns.resource_records[0].rclass, QClass::IN);
+        assert_eq!(
+            dns.resource_records[1].rdata,
+            Writer::new().write_name_uncompressed("localhost").build()
+        );
+
+        let dns = DNS::from_json(
+            r#"{ "messageOctetsHEX": "349e010000010000000000000377777706676f6f676c650264650000010001" }"#,
+        )
+        .unwrap();
+        assert_eq!(dns.questions[0].qname, "www.google.de");
+    }
+
+    #[test]
+    pub fn test_from_json_invalid() {
+        let cases = [
+            "[]",
+            "{",
+            r#"{ "ID": "1" }"#,
+            r#"{ "answerRRs": [ { "NAME": "example.com", "TYPE": 1 } ] }"#,
+            r#"{ "answerRRs": [ { "NAME": "example.com", "TYPE": 1, "rdataA": "::1" } ] }"#,
+            r#"{ "answerRRs": {} }"#,
+            r#"{ "QNAME": "www..example.com", "QTYPE": 1 }"#,
+            r#"{ "ID": 65536 }"#,
+            r#"{ "QR": 2 }"#,
+            r#"{ "Opcode": 16 }"#,
+            r#"{ "RCODE": 16 }"#,
+            r#"{ "ANCOUNT": 65536 }"#,
+            r#"{ "answerRRs": [ { "NAME": "a", "TYPE": 1, "TTL": 4294967296, "rdataA": "192.0.2.1" } ] }"#,
+            r#"{ "answerRRs": [ { "NAME": "example.com", "TYPE": 15, "rdataMX": "mail.example.com" } ] }"#,
+            r#"{ "answerRRs": [ { "NAME": "example.com", "TYPE": 5, "rdataCNAME": "a..b" } ] }"#,
+        ];
+        for case in cases.iter() {
+            match DNS::from_json(case) {
+                Err(DnsParseError::InvalidJson(_)) => (),
+                x => panic!("Unexpected result for {}: {:?}", case, x),
+            }
+        }
+
+        assert!(DNS::from_json(r#"{ "messageOctetsHEX": "zz" }"#).is_err());
+
+        let long = format!(
+            r#"{{ "answerRRs": [ {{ "NAME": "example.com", "TYPE": 5, "rdataCNAME": "{}.com" }} ] }}"#,
+            "a".repeat(64)
+        );
+        match DNS::from_json(&long) {
+            Err(DnsParseError::InvalidJson(x)) => assert!(x.contains("rdataCNAME")),
+            x => panic!("Unexpected result {:?}", x),
+        }
+    }
+}
@@ -1,11 +1,28 @@
 mod dns;
+mod dnssec;
+mod encoding;
 mod error;
+mod idn;
+mod json;
+mod mdns;
+mod pcap;
 mod qclass;
 mod qtype;
 mod reader;
+mod reverse;
+mod tcp;
 mod writer;
 
 pub use crate::dns::*;
+pub use crate::dnssec::*;
+pub use crate::encoding::{
+    base32hex_decode, base32hex_encode, base64url_decode, base64url_encode, hex_decode, hex_encode,
+};
 pub use crate::error::DnsParseError;
+pub use crate::idn::{domain_to_ascii, domain_to_unicode};
+pub use crate::mdns::{MDNS_IPV4, MDNS_IPV6, MDNS_PORT};
+pub use crate::pcap::*;
 pub use crate::qclass::QClass;
 pub use crate::qtype::QType;
+pub use crate::reverse::{classless_reverse_name, parse_reverse_name, reverse_name};
+pub use crate::tcp::*;
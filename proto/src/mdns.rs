@@ -0,0 +1,110 @@
+use crate::dns::{ResourceRecord, DNS};
+
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+/// Port of multicast DNS
+pub const MDNS_PORT: u16 = 5353;
+/// Multicast group of mDNS for IPv4
+pub const MDNS_IPV4: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+/// Multicast group of mDNS for IPv6
+pub const MDNS_IPV6: Ipv6Addr = Ipv6Addr::new(0xFF02, 0, 0, 0, 0, 0, 0, 0xFB);
+
+/// Top bit of the class, the unicast response bit in questions and the
+/// cache flush bit in records
+pub(crate) const CLASS_FLAG: u16 = 0x8000;
+
+impl ResourceRecord {
+    /// Whether the record is contained in the known answers with at least
+    /// half of its TTL remaining, in that case a responder must not send it
+    /// (RFC 6762 7.1)
+    pub fn is_suppressed_by(&self, known_answers: &[ResourceRecord]) -> bool {
+        known_answers.iter().any(|x| {
+            x.name.eq_ignore_ascii_case(&self.name)
+                && x.rtype == self.rtype
+                && x.rclass == self.rclass
+                && x.rdata == self.rdata
+                && u64::from(x.ttl) * 2 >= u64::from(self.ttl)
+        })
+    }
+}
+
+impl DNS {
+    /// Removes all answers the query already lists as known answers
+    pub fn suppress_known_answers(&self, answers: Vec<ResourceRecord>) -> Vec<ResourceRecord> {
+        answers
+            .into_iter()
+            .filter(|x| !x.is_suppressed_by(&self.resource_records))
+            .collect()
+    }
+
+    /// Adds a cached record to the known answers of a query, `ttl` being
+    /// its remaining TTL
+    ///
+    /// Records with less than half of their original TTL remaining are
+    /// skipped, returns whether the record was added (RFC 6762 7.1).
+    pub fn add_known_answer(&mut self, record: &ResourceRecord, ttl: u32) -> bool {
+        if u64::from(ttl) * 2 < u64::from(record.ttl) {
+            return false;
+        }
+
+        self.resource_records.push(ResourceRecord {
+            ttl,
+            cache_flush: false,
+            ..record.clone()
+        });
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::qclass::QClass;
+    use crate::qtype::QType;
+
+    fn record(name: &str, ttl: u32) -> ResourceRecord {
+        ResourceRecord {
+            name: name.into(),
+            rtype: QType::A,
+            rclass: QClass::IN,
+            cache_flush: true,
+            ttl,
+            rdlength: 4,
+            rdata: vec![192, 168, 0, 10],
+        }
+    }
+
+    #[test]
+    pub fn test_parse_flags() {
+        // browsing query with unicast response bit and a known answer with
+        // the cache flush bit set
+        let hex = "000000000001000100000000
+                   077072696e746572056c6f63616c0000018001
+                   c00c00018001000000780004c0a8000a";
+        let dns = DNS::from_hex(hex).unwrap();
+        assert!(dns.questions[0].unicast_response);
+        assert_eq!(dns.questions[0].qclass, QClass::IN);
+        assert!(dns.resource_records[0].cache_flush);
+        assert_eq!(dns.resource_records[0].rclass, QClass::IN);
+        assert_eq!(dns.to_hex(), hex.split_whitespace().collect::<String>());
+        assert_eq!(DNS::from_json(&dns.to_json()).unwrap(), dns);
+    }
+
+    #[test]
+    pub fn test_known_answer_suppression() {
+        let mut query = DNS::default();
+        assert!(query.add_known_answer(&record("printer.local", 120), 60));
+        assert!(!query.add_known_answer(&record("scanner.local", 120), 59));
+        assert!(!query.resource_records[0].cache_flush);
+
+        let answers = vec![
+            record("Printer.local", 120),
+            record("printer.local", 121),
+            record("scanner.local", 120),
+        ];
+        let answers = query.suppress_known_answers(answers);
+        assert_eq!(answers.len(), 2);
+        assert_eq!(answers[0].ttl, 121);
+        assert_eq!(answers[1].name, "scanner.local");
+    }
+}
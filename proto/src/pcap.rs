@@ -0,0 +1,400 @@
+mod flow;
+mod writer;
+
+pub use self::writer::PcapWriter;
+
+use self::flow::FlowTracker;
+use crate::dns::DNS;
+use crate::error::*;
+
+use std::io::Read;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+/// Ports of unicast and multicast DNS
+pub const DNS_PORTS: [u16; 2] = [53, 5353];
+
+const PCAP_MAGIC_MICROS: u32 = 0xA1B2_C3D4;
+const PCAP_MAGIC_NANOS: u32 = 0xA1B2_3C4D;
+const PCAP_HEADER_LENGTH: usize = 24;
+const PCAP_RECORD_HEADER_LENGTH: usize = 16;
+
+const PCAPNG_SECTION_HEADER: [u8; 4] = [0x0A, 0x0D, 0x0D, 0x0A];
+const PCAPNG_BYTE_ORDER_MAGIC: u32 = 0x1A2B_3C4D;
+const PCAPNG_INTERFACE_DESCRIPTION: u32 = 1;
+const PCAPNG_SIMPLE_PACKET: u32 = 3;
+const PCAPNG_ENHANCED_PACKET: u32 = 6;
+/// Option of the interface description block containing the resolution
+/// of the timestamps
+const PCAPNG_IF_TSRESOL: u16 = 9;
+
+#[derive(Copy, Clone, Debug, Hash, Eq, PartialEq)]
+pub enum Transport {
+    Udp,
+    Tcp,
+}
+
+/// DNS message extracted from a capture
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CapturedMessage {
+    /// Capture time since the Unix epoch, for TCP the time of the segment
+    /// that completed the message
+    pub timestamp: Duration,
+    pub source: SocketAddr,
+    pub destination: SocketAddr,
+    pub transport: Transport,
+    /// Wire format of the message, without the TCP length prefix
+    pub payload: Vec<u8>,
+}
+
+impl CapturedMessage {
+    pub fn dns(&self) -> Result<DNS> {
+        DNS::parse(self.payload.clone())
+    }
+}
+
+/// Reads all DNS messages of a pcap or pcapng capture
+///
+/// Only traffic from or to one of the given ports is considered, an empty
+/// list accepts every port. See `parse_capture` for details.
+pub fn read_capture<R: Read>(mut reader: R, ports: &[u16]) -> Result<Vec<CapturedMessage>> {
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    parse_capture(&bytes, ports)
+}
+
+/// Extracts all DNS messages of a pcap or pcapng capture in the order they
+/// were completed
+///
+/// Supported are Ethernet, Linux cooked and loopback captures as well as raw
+/// IP. Fragmented IP packets are reassembled and TCP segments are ordered
+/// by their sequence number before being split into messages. Packets that
+/// can not be decoded are skipped, as is a truncated last packet left
+/// behind by an interrupted capture.
+pub fn parse_capture(bytes: &[u8], ports: &[u16]) -> Result<Vec<CapturedMessage>> {
+    let mut flows = FlowTracker::new(ports);
+
+    if bytes.starts_with(&PCAPNG_SECTION_HEADER) {
+        parse_pcapng(bytes, &mut flows)?;
+    } else {
+        parse_pcap(bytes, &mut flows)?;
+    }
+
+    Ok(flows.into_messages())
+}
+
+fn parse_pcap(bytes: &[u8], flows: &mut FlowTracker) -> Result<()> {
+    if bytes.len() < PCAP_HEADER_LENGTH {
+        return Err(invalid("file too short"));
+    }
+
+    let (big_endian, nanos) = match u32_at(bytes, 0, true)? {
+        PCAP_MAGIC_MICROS => (true, false),
+        PCAP_MAGIC_NANOS => (true, true),
+        _ => match u32_at(bytes, 0, false)? {
+            PCAP_MAGIC_MICROS => (false, false),
+            PCAP_MAGIC_NANOS => (false, true),
+            _ => return Err(invalid("unknown file format")),
+        },
+    };
+    // the upper bits may contain information about the frame check sequence
+    let linktype = u32_at(bytes, 20, big_endian)? & 0x0FFF_FFFF;
+
+    let mut offset = PCAP_HEADER_LENGTH;
+    while offset + PCAP_RECORD_HEADER_LENGTH <= bytes.len() {
+        let seconds = u32_at(bytes, offset, big_endian)?;
+        let fraction = u32_at(bytes, offset + 4, big_endian)?;
+        let length = u32_at(bytes, offset + 8, big_endian)? as usize;
+
+        let start = offset + PCAP_RECORD_HEADER_LENGTH;
+        let data = match bytes.get(start..start + length) {
+            Some(x) => x,
+            None => break,
+        };
+
+        let fraction = if nanos {
+            Duration::from_nanos(u64::from(fraction))
+        } else {
+            Duration::from_micros(u64::from(fraction))
+        };
+        let timestamp = Duration::from_secs(u64::from(seconds)) + fraction;
+        flows.packet(timestamp, linktype, data);
+
+        offset = start + length;
+    }
+
+    Ok(())
+}
+
+/// Interface of a pcapng section
+struct Interface {
+    linktype: u32,
+    /// Value of the `if_tsresol` option
+    resolution: u8,
+}
+
+impl Interface {
+    fn timestamp(&self, value: u64) -> Duration {
+        let units_per_second = if self.resolution & 0x80 == 0 {
+            10u128.pow(u32::from(self.resolution.min(19)))
+        } else {
+            1u128 << (self.resolution & 0x7F).min(63)
+        };
+
+        let value = u128::from(value);
+        let seconds = value / units_per_second;
+        let nanos = value % units_per_second * 1_000_000_000 / units_per_second;
+        Duration::new(seconds as u64, nanos as u32)
+    }
+}
+
+fn parse_pcapng(bytes: &[u8], flows: &mut FlowTracker) -> Result<()> {
+    let mut big_endian = false;
+    let mut interfaces = Vec::new();
+
+    let mut offset = 0;
+    while offset + 12 <= bytes.len() {
+        if bytes[offset..].starts_with(&PCAPNG_SECTION_HEADER) {
+            big_endian = match u32_at(bytes, offset + 8, true)? {
+                PCAPNG_BYTE_ORDER_MAGIC => true,
+                x if x.swap_bytes() == PCAPNG_BYTE_ORDER_MAGIC => false,
+                _ => return Err(invalid("invalid byte order magic")),
+            };
+            // interface ids are only valid inside their section
+            interfaces.clear();
+        }
+
+        let block_type = u32_at(bytes, offset, big_endian)?;
+        let length = u32_at(bytes, offset + 4, big_endian)? as usize;
+        if length < 12 || !length.is_multiple_of(4) {
+            return Err(invalid("invalid block length"));
+        }
+        let body = match bytes.get(offset + 8..offset + length - 4) {
+            Some(x) => x,
+            None => break,
+        };
+
+        match block_type {
+            PCAPNG_INTERFACE_DESCRIPTION => {
+                let linktype = u32::from(u16_at(body, 0, big_endian)?);
+                let resolution =
+                    option(body.get(8..).unwrap_or(&[]), PCAPNG_IF_TSRESOL, big_endian)
+                        .and_then(|x| x.first().copied())
+                        .unwrap_or(6);
+                interfaces.push(Interface {
+                    linktype,
+                    resolution,
+                });
+            }
+            PCAPNG_ENHANCED_PACKET => {
+                let interface = interfaces
+                    .get(u32_at(body, 0, big_endian)? as usize)
+                    .ok_or_else(|| invalid("unknown interface"))?;
+                let timestamp = u64::from(u32_at(body, 4, big_endian)?) << 32
+                    | u64::from(u32_at(body, 8, big_endian)?);
+                let length = u32_at(body, 12, big_endian)? as usize;
+                let data = body
+                    .get(20..20 + length)
+                    .ok_or_else(|| invalid("invalid packet length"))?;
+                flows.packet(interface.timestamp(timestamp), interface.linktype, data);
+            }
+            PCAPNG_SIMPLE_PACKET => {
+                // simple packets belong to the first interface and carry
+                // neither a timestamp nor the captured length
+                let interface = interfaces
+                    .first()
+                    .ok_or_else(|| invalid("unknown interface"))?;
+                let length = (u32_at(body, 0, big_endian)? as usize).min(body.len() - 4);
+                flows.packet(
+                    Duration::default(),
+                    interface.linktype,
+                    &body[4..4 + length],
+                );
+            }
+            _ => (),
+        }
+
+        offset += length;
+    }
+
+    Ok(())
+}
+
+/// Value of the first option with the given code
+fn option(mut options: &[u8], code: u16, big_endian: bool) -> Option<&[u8]> {
+    while options.len() >= 4 {
+        let current = u16_at(options, 0, big_endian).ok()?;
+        let length = u16_at(options, 2, big_endian).ok()? as usize;
+        // end of options
+        if current == 0 {
+            return None;
+        }
+
+        let value = options.get(4..4 + length)?;
+        if current == code {
+            return Some(value);
+        }
+
+        let padded = (length + 3) & !3;
+        options = options.get(4 + padded..)?;
+    }
+
+    None
+}
+
+fn u16_at(bytes: &[u8], offset: usize, big_endian: bool) -> Result<u16> {
+    let value = bytes
+        .get(offset..offset + 2)
+        .ok_or_else(|| invalid("unexpected end of block"))?;
+    let value = [value[0], value[1]];
+    Ok(if big_endian {
+        u16::from_be_bytes(value)
+    } else {
+        u16::from_le_bytes(value)
+    })
+}
+
+fn u32_at(bytes: &[u8], offset: usize, big_endian: bool) -> Result<u32> {
+    let value = bytes
+        .get(offset..offset + 4)
+        .ok_or_else(|| invalid("unexpected end of block"))?;
+    let value = [value[0], value[1], value[2], value[3]];
+    Ok(if big_endian {
+        u32::from_be_bytes(value)
+    } else {
+        u32::from_le_bytes(value)
+    })
+}
+
+fn invalid(message: &str) -> DnsParseError {
+    DnsParseError::InvalidCapture(message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query() -> Vec<u8> {
+        hex::decode("349e010000010000000000000377777706676f6f676c650264650000010001").unwrap()
+    }
+
+    fn message(transport: Transport, source: &str, destination: &str) -> CapturedMessage {
+        CapturedMessage {
+            timestamp: Duration::new(1_600_000_000, 123_456_000),
+            source: source.parse().unwrap(),
+            destination: destination.parse().unwrap(),
+            transport,
+            payload: query(),
+        }
+    }
+
+    #[test]
+    pub fn test_write_read() {
+        let messages = vec![
+            message(Transport::Udp, "127.0.0.1:40000", "127.0.0.1:53"),
+            message(Transport::Udp, "[::1]:40000", "[::1]:53"),
+            message(Transport::Tcp, "127.0.0.1:40001", "127.0.0.1:53"),
+            message(Transport::Tcp, "127.0.0.1:40001", "127.0.0.1:53"),
+            message(Transport::Udp, "127.0.0.1:40002", "127.0.0.1:1337"),
+        ];
+
+        let mut writer = PcapWriter::new(Vec::new()).unwrap();
+        for message in messages.iter() {
+            writer.write(message).unwrap();
+        }
+        let capture = writer.into_inner();
+
+        let read = read_capture(&capture[..], &DNS_PORTS).unwrap();
+        assert_eq!(read, messages[..4].to_vec());
+        assert_eq!(read[0].dns().unwrap().questions[0].qname, "www.google.de");
+
+        let read = parse_capture(&capture, &[]).unwrap();
+        assert_eq!(read, messages);
+    }
+
+    #[test]
+    pub fn test_read_big_endian_nanos() {
+        let packet =
+            writer::packet(&message(Transport::Udp, "10.0.0.1:53", "10.0.0.2:40000"), 0).unwrap();
+
+        let mut capture = Vec::new();
+        capture.extend_from_slice(&PCAP_MAGIC_NANOS.to_be_bytes());
+        capture.extend_from_slice(&[0, 2, 0, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 255, 255]);
+        capture.extend_from_slice(&101u32.to_be_bytes());
+        capture.extend_from_slice(&1u32.to_be_bytes());
+        capture.extend_from_slice(&999u32.to_be_bytes());
+        capture.extend_from_slice(&(packet.len() as u32).to_be_bytes());
+        capture.extend_from_slice(&(packet.len() as u32).to_be_bytes());
+        capture.extend_from_slice(&packet);
+        // truncated packet of an interrupted capture
+        capture.extend_from_slice(&[0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 100, 0, 0, 0, 100, 0x45]);
+
+        let read = parse_capture(&capture, &DNS_PORTS).unwrap();
+        assert_eq!(read.len(), 1);
+        assert_eq!(read[0].timestamp, Duration::new(1, 999));
+        assert_eq!(read[0].source, "10.0.0.1:53".parse().unwrap());
+    }
+
+    #[test]
+    pub fn test_read_pcapng() {
+        let packet =
+            writer::packet(&message(Transport::Udp, "10.0.0.1:40000", "10.0.0.2:53"), 0).unwrap();
+
+        let block = |block_type: u32, body: Vec<u8>| {
+            let length = (body.len() + 12) as u32;
+            let mut block = block_type.to_le_bytes().to_vec();
+            block.extend_from_slice(&length.to_le_bytes());
+            block.extend_from_slice(&body);
+            block.extend_from_slice(&length.to_le_bytes());
+            block
+        };
+
+        let mut section = PCAPNG_BYTE_ORDER_MAGIC.to_le_bytes().to_vec();
+        section.extend_from_slice(&[1, 0, 0, 0, 255, 255, 255, 255, 255, 255, 255, 255]);
+
+        // ethernet interface with millisecond timestamps
+        let mut interface = vec![1, 0, 0, 0, 0, 0, 0, 0];
+        interface.extend_from_slice(&[9, 0, 1, 0, 3, 0, 0, 0, 0, 0, 0, 0]);
+
+        let mut frame = vec![0; 12];
+        frame.extend_from_slice(&[0x81, 0x00, 0, 1, 0x08, 0x00]);
+        frame.extend_from_slice(&packet);
+        // ethernet padding behind the IP packet
+        frame.extend_from_slice(&[0; 3]);
+
+        let mut enhanced = vec![0, 0, 0, 0];
+        enhanced.extend_from_slice(&0u32.to_le_bytes());
+        enhanced.extend_from_slice(&1_500u32.to_le_bytes());
+        enhanced.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+        enhanced.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+        enhanced.extend_from_slice(&frame);
+        enhanced.resize((enhanced.len() + 3) & !3, 0);
+
+        let mut capture = block(0x0A0D_0D0A, section);
+        capture.extend(block(PCAPNG_INTERFACE_DESCRIPTION, interface));
+        capture.extend(block(5, vec![0; 8]));
+        capture.extend(block(PCAPNG_ENHANCED_PACKET, enhanced));
+
+        let read = parse_capture(&capture, &DNS_PORTS).unwrap();
+        assert_eq!(read.len(), 1);
+        assert_eq!(read[0].timestamp, Duration::from_millis(1_500));
+        assert_eq!(read[0].payload, query());
+    }
+
+    #[test]
+    pub fn test_read_invalid() {
+        assert!(parse_capture(&[], &DNS_PORTS).is_err());
+        assert!(parse_capture(&[0; 24], &DNS_PORTS).is_err());
+
+        // enhanced packet block without any interface description
+        let mut capture = vec![0x0A, 0x0D, 0x0D, 0x0A, 28, 0, 0, 0, 0x4D, 0x3C, 0x2B, 0x1A];
+        capture.extend_from_slice(&[1, 0, 0, 0, 255, 255, 255, 255, 255, 255, 255, 255]);
+        capture.extend_from_slice(&[28, 0, 0, 0, 6, 0, 0, 0, 32, 0, 0, 0]);
+        capture.extend_from_slice(&[0; 20]);
+        capture.extend_from_slice(&[32, 0, 0, 0]);
+        match parse_capture(&capture, &DNS_PORTS) {
+            Err(DnsParseError::InvalidCapture(_)) => (),
+            x => panic!("Unexpected result {:?}", x),
+        }
+    }
+}
@@ -0,0 +1,456 @@
+use super::{CapturedMessage, Transport};
+use crate::tcp::TcpDecoder;
+
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+
+const LINKTYPE_NULL: u32 = 0;
+const LINKTYPE_ETHERNET: u32 = 1;
+pub(super) const LINKTYPE_RAW: u32 = 101;
+const LINKTYPE_LOOP: u32 = 108;
+const LINKTYPE_LINUX_SLL: u32 = 113;
+const LINKTYPE_IPV4: u32 = 228;
+const LINKTYPE_IPV6: u32 = 229;
+const LINKTYPE_LINUX_SLL2: u32 = 276;
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86DD;
+const ETHERTYPE_VLAN: u16 = 0x8100;
+const ETHERTYPE_QINQ: u16 = 0x88A8;
+
+pub(super) const PROTOCOL_TCP: u8 = 6;
+pub(super) const PROTOCOL_UDP: u8 = 17;
+const IPV6_HOP_BY_HOP: u8 = 0;
+const IPV6_ROUTING: u8 = 43;
+const IPV6_FRAGMENT: u8 = 44;
+const IPV6_DESTINATION: u8 = 60;
+
+pub(super) const TCP_FIN: u8 = 0x01;
+pub(super) const TCP_SYN: u8 = 0x02;
+pub(super) const TCP_RST: u8 = 0x04;
+
+/// Fragments of an IP packet that is not complete yet
+#[derive(Default)]
+struct Fragments {
+    /// Offset and data of every received fragment
+    parts: Vec<(usize, Vec<u8>)>,
+    /// Known as soon as the last fragment arrived
+    length: Option<usize>,
+}
+
+impl Fragments {
+    /// Returns the payload once all fragments are received
+    fn insert(&mut self, offset: usize, data: &[u8], more: bool) -> Option<Vec<u8>> {
+        if !more {
+            self.length = Some(offset + data.len());
+        }
+        self.parts.push((offset, data.to_vec()));
+
+        let length = self.length?;
+        self.parts.sort_by_key(|x| x.0);
+
+        let mut covered = 0;
+        for (offset, data) in self.parts.iter() {
+            if *offset > covered {
+                return None;
+            }
+            covered = covered.max(offset + data.len());
+        }
+        if covered < length {
+            return None;
+        }
+
+        let mut payload = vec![0u8; length];
+        for (offset, data) in self.parts.iter() {
+            let end = (offset + data.len()).min(length);
+            if *offset < end {
+                payload[*offset..end].copy_from_slice(&data[..end - offset]);
+            }
+        }
+        Some(payload)
+    }
+}
+
+/// Receiving side of one direction of a TCP connection
+#[derive(Default)]
+struct Stream {
+    /// Sequence number of the next expected byte, unknown if the capture
+    /// started in the middle of the connection
+    next: Option<u32>,
+    /// Segments that arrived ahead of the next expected byte
+    pending: Vec<(u32, Vec<u8>)>,
+    decoder: TcpDecoder,
+}
+
+/// Decodes captured packets and collects the DNS messages they contain
+pub(super) struct FlowTracker<'a> {
+    ports: &'a [u16],
+    fragments: HashMap<(IpAddr, IpAddr, u32, u8), Fragments>,
+    streams: HashMap<(SocketAddr, SocketAddr), Stream>,
+    messages: Vec<CapturedMessage>,
+}
+
+impl<'a> FlowTracker<'a> {
+    pub(super) fn new(ports: &'a [u16]) -> Self {
+        Self {
+            ports,
+            fragments: HashMap::new(),
+            streams: HashMap::new(),
+            messages: Vec::new(),
+        }
+    }
+
+    pub(super) fn into_messages(self) -> Vec<CapturedMessage> {
+        self.messages
+    }
+
+    /// Handles a single captured frame, everything that is not IP or can
+    /// not be decoded is ignored
+    pub(super) fn packet(&mut self, timestamp: Duration, linktype: u32, data: &[u8]) {
+        if let Some(data) = link_payload(linktype, data) {
+            self.ip(timestamp, data);
+        }
+    }
+
+    fn ip(&mut self, timestamp: Duration, data: &[u8]) -> Option<()> {
+        match data.first()? >> 4 {
+            4 => {
+                let header_length = usize::from(data[0] & 0x0F) * 4;
+                // ignore the padding of short ethernet frames
+                let total_length = usize::from(u16_at(data, 2)?);
+                let payload = data.get(header_length..total_length)?;
+
+                let id = u32::from(u16_at(data, 4)?);
+                let fragment = u16_at(data, 6)?;
+                let protocol = *data.get(9)?;
+                let source = IpAddr::V4(Ipv4Addr::from(u32_at(data, 12)?));
+                let destination = IpAddr::V4(Ipv4Addr::from(u32_at(data, 16)?));
+
+                let more = fragment & 0x2000 != 0;
+                let offset = usize::from(fragment & 0x1FFF) * 8;
+                self.fragment(
+                    timestamp,
+                    (source, destination, id, protocol),
+                    offset,
+                    more,
+                    payload,
+                )
+            }
+            6 => {
+                let payload_length = usize::from(u16_at(data, 4)?);
+                let mut protocol = *data.get(6)?;
+                let source = IpAddr::V6(Ipv6Addr::from(octets(data.get(8..24)?)));
+                let destination = IpAddr::V6(Ipv6Addr::from(octets(data.get(24..40)?)));
+                let mut payload = data.get(40..40 + payload_length)?;
+
+                while [IPV6_HOP_BY_HOP, IPV6_ROUTING, IPV6_DESTINATION].contains(&protocol) {
+                    protocol = *payload.first()?;
+                    let length = (usize::from(*payload.get(1)?) + 1) * 8;
+                    payload = payload.get(length..)?;
+                }
+
+                if protocol == IPV6_FRAGMENT {
+                    protocol = *payload.first()?;
+                    let fragment = u16_at(payload, 2)?;
+                    let id = u32_at(payload, 4)?;
+                    let more = fragment & 0x0001 != 0;
+                    let offset = usize::from(fragment & 0xFFF8);
+                    self.fragment(
+                        timestamp,
+                        (source, destination, id, protocol),
+                        offset,
+                        more,
+                        payload.get(8..)?,
+                    )
+                } else {
+                    self.transport(timestamp, source, destination, protocol, payload)
+                }
+            }
+            _ => None,
+        }
+    }
+
+    fn fragment(
+        &mut self,
+        timestamp: Duration,
+        key: (IpAddr, IpAddr, u32, u8),
+        offset: usize,
+        more: bool,
+        payload: &[u8],
+    ) -> Option<()> {
+        let (source, destination, _, protocol) = key;
+        if offset == 0 && !more {
+            return self.transport(timestamp, source, destination, protocol, payload);
+        }
+
+        let payload = self
+            .fragments
+            .entry(key)
+            .or_default()
+            .insert(offset, payload, more)?;
+        self.fragments.remove(&key);
+        self.transport(timestamp, source, destination, protocol, &payload)
+    }
+
+    fn transport(
+        &mut self,
+        timestamp: Duration,
+        source: IpAddr,
+        destination: IpAddr,
+        protocol: u8,
+        payload: &[u8],
+    ) -> Option<()> {
+        let source = SocketAddr::new(source, u16_at(payload, 0)?);
+        let destination = SocketAddr::new(destination, u16_at(payload, 2)?);
+        if !self.ports.is_empty()
+            && !self.ports.contains(&source.port())
+            && !self.ports.contains(&destination.port())
+        {
+            return None;
+        }
+
+        match protocol {
+            PROTOCOL_UDP => {
+                let length = usize::from(u16_at(payload, 4)?);
+                let data = payload.get(8..length.min(payload.len()))?;
+                self.messages.push(CapturedMessage {
+                    timestamp,
+                    source,
+                    destination,
+                    transport: Transport::Udp,
+                    payload: data.to_vec(),
+                });
+            }
+            PROTOCOL_TCP => {
+                let sequence = u32_at(payload, 4)?;
+                let header_length = usize::from(*payload.get(12)? >> 4) * 4;
+                let flags = *payload.get(13)?;
+                let data = payload.get(header_length..)?;
+                self.segment(timestamp, source, destination, sequence, flags, data);
+            }
+            _ => (),
+        }
+
+        Some(())
+    }
+
+    fn segment(
+        &mut self,
+        timestamp: Duration,
+        source: SocketAddr,
+        destination: SocketAddr,
+        mut sequence: u32,
+        flags: u8,
+        data: &[u8],
+    ) {
+        let key = (source, destination);
+        if flags & TCP_RST != 0 {
+            self.streams.remove(&key);
+            return;
+        }
+
+        let stream = self.streams.entry(key).or_default();
+        if flags & TCP_SYN != 0 {
+            *stream = Stream::default();
+            sequence = sequence.wrapping_add(1);
+            stream.next = Some(sequence);
+        }
+        // without the handshake the first segment is assumed to start with
+        // a message
+        let mut next = *stream.next.get_or_insert(sequence);
+
+        if !data.is_empty() {
+            stream.pending.push((sequence, data.to_vec()));
+        }
+
+        // feed every segment that starts at or before the next expected
+        // byte, retransmitted bytes are skipped
+        while let Some(index) = stream
+            .pending
+            .iter()
+            .position(|(x, _)| next.wrapping_sub(*x) as i32 >= 0)
+        {
+            let (start, data) = stream.pending.swap_remove(index);
+            let skip = next.wrapping_sub(start) as usize;
+            if skip < data.len() {
+                stream.decoder.extend(&data[skip..]);
+                next = start.wrapping_add(data.len() as u32);
+            }
+        }
+        stream.next = Some(next);
+
+        loop {
+            match stream.decoder.decode() {
+                Ok(Some(payload)) => self.messages.push(CapturedMessage {
+                    timestamp,
+                    source,
+                    destination,
+                    transport: Transport::Tcp,
+                    payload,
+                }),
+                Ok(None) => break,
+                // out of sync, drop everything buffered so far
+                Err(_) => {
+                    stream.decoder = TcpDecoder::new();
+                    break;
+                }
+            }
+        }
+
+        if flags & TCP_FIN != 0 {
+            self.streams.remove(&key);
+        }
+    }
+}
+
+/// Strips the link layer header, returns `None` for everything but IP
+fn link_payload(linktype: u32, data: &[u8]) -> Option<&[u8]> {
+    let (ethertype, offset) = match linktype {
+        // address family in host byte order, the IP version tells enough
+        LINKTYPE_NULL | LINKTYPE_LOOP => return data.get(4..),
+        LINKTYPE_RAW | LINKTYPE_IPV4 | LINKTYPE_IPV6 => return Some(data),
+        LINKTYPE_ETHERNET => {
+            let mut offset = 12;
+            let mut ethertype = u16_at(data, offset)?;
+            while ethertype == ETHERTYPE_VLAN || ethertype == ETHERTYPE_QINQ {
+                offset += 4;
+                ethertype = u16_at(data, offset)?;
+            }
+            (ethertype, offset + 2)
+        }
+        LINKTYPE_LINUX_SLL => (u16_at(data, 14)?, 16),
+        LINKTYPE_LINUX_SLL2 => (u16_at(data, 0)?, 20),
+        _ => return None,
+    };
+
+    match ethertype {
+        ETHERTYPE_IPV4 | ETHERTYPE_IPV6 => data.get(offset..),
+        _ => None,
+    }
+}
+
+fn octets(bytes: &[u8]) -> [u8; 16] {
+    let mut octets = [0u8; 16];
+    octets.copy_from_slice(bytes);
+    octets
+}
+
+fn u16_at(bytes: &[u8], offset: usize) -> Option<u16> {
+    let value = bytes.get(offset..offset + 2)?;
+    Some(u16::from_be_bytes([value[0], value[1]]))
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> Option<u32> {
+    let value = bytes.get(offset..offset + 4)?;
+    Some(u32::from_be_bytes([value[0], value[1], value[2], value[3]]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::writer::{ip_packet, tcp_segment, udp_datagram};
+    use super::*;
+
+    fn addr(x: &str) -> SocketAddr {
+        x.parse().unwrap()
+    }
+
+    #[test]
+    pub fn test_ipv4_fragments() {
+        let source = addr("10.0.0.1:53");
+        let destination = addr("10.0.0.2:40000");
+        let payload = (0..100).collect::<Vec<u8>>();
+        let datagram = udp_datagram(source, destination, &payload);
+        let packet = ip_packet(source.ip(), destination.ip(), PROTOCOL_UDP, &datagram).unwrap();
+
+        // split the datagram at 8 byte boundaries and deliver in reverse
+        let fragments = [(0, 48, true), (48, 96, true), (96, datagram.len(), false)];
+        let mut flows = FlowTracker::new(&[53]);
+        for (start, end, more) in fragments.iter().rev() {
+            let mut fragment = packet[..20].to_vec();
+            fragment[2..4].copy_from_slice(&((20 + end - start) as u16).to_be_bytes());
+            let flags = if *more { 0x2000 } else { 0 } | (start / 8) as u16;
+            fragment[6..8].copy_from_slice(&flags.to_be_bytes());
+            fragment.extend_from_slice(&datagram[*start..*end]);
+
+            flows.packet(Duration::from_secs(1), LINKTYPE_RAW, &fragment);
+        }
+
+        let messages = flows.into_messages();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].payload, payload);
+        assert_eq!(messages[0].destination, destination);
+    }
+
+    #[test]
+    pub fn test_ipv6_extension_headers() {
+        let source = addr("[2001:db8::1]:5353");
+        let destination = addr("[ff02::fb]:5353");
+        let datagram = udp_datagram(source, destination, &[1, 2, 3]);
+        let packet = ip_packet(source.ip(), destination.ip(), PROTOCOL_UDP, &datagram).unwrap();
+
+        // insert a hop by hop header in front of the UDP header
+        let mut extended = packet[..40].to_vec();
+        extended[4..6].copy_from_slice(&((datagram.len() + 8) as u16).to_be_bytes());
+        extended[6] = IPV6_HOP_BY_HOP;
+        extended.extend_from_slice(&[PROTOCOL_UDP, 0, 1, 4, 0, 0, 0, 0]);
+        extended.extend_from_slice(&datagram);
+
+        let mut flows = FlowTracker::new(&[]);
+        flows.packet(Duration::from_secs(1), LINKTYPE_RAW, &extended);
+        // unsupported link type and not IP at all
+        flows.packet(Duration::from_secs(1), 12_345, &packet);
+        flows.packet(Duration::from_secs(1), LINKTYPE_RAW, &[0x10, 0, 0]);
+
+        let messages = flows.into_messages();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].payload, vec![1, 2, 3]);
+    }
+
+    #[test]
+    pub fn test_tcp_reassembly() {
+        let client = addr("127.0.0.1:40000");
+        let server = addr("127.0.0.1:53");
+        // two pipelined messages, split in the middle of the second one
+        let stream = [0, 3, 1, 2, 3, 0, 2, 4, 5];
+        let segment = |sequence: u32, flags: u8, data: &[u8]| {
+            let segment = tcp_segment(client, server, sequence, flags, data);
+            ip_packet(client.ip(), server.ip(), PROTOCOL_TCP, &segment).unwrap()
+        };
+
+        let mut flows = FlowTracker::new(&[53]);
+        flows.packet(
+            Duration::from_secs(1),
+            LINKTYPE_RAW,
+            &segment(99, TCP_SYN, &[]),
+        );
+        // second segment arrives first, followed by a retransmission
+        flows.packet(
+            Duration::from_secs(2),
+            LINKTYPE_RAW,
+            &segment(106, 0, &stream[6..]),
+        );
+        flows.packet(
+            Duration::from_secs(3),
+            LINKTYPE_RAW,
+            &segment(100, 0, &stream[..7]),
+        );
+        flows.packet(
+            Duration::from_secs(4),
+            LINKTYPE_RAW,
+            &segment(100, 0, &stream[..7]),
+        );
+        flows.packet(
+            Duration::from_secs(5),
+            LINKTYPE_RAW,
+            &segment(109, TCP_FIN, &[]),
+        );
+
+        let messages = flows.into_messages();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].payload, vec![1, 2, 3]);
+        assert_eq!(messages[1].payload, vec![4, 5]);
+        assert_eq!(messages[1].timestamp, Duration::from_secs(3));
+        assert_eq!(messages[1].transport, Transport::Tcp);
+    }
+}
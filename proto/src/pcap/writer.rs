@@ -0,0 +1,291 @@
+use super::flow::{LINKTYPE_RAW, PROTOCOL_TCP, PROTOCOL_UDP};
+use super::{CapturedMessage, Transport, PCAP_MAGIC_MICROS};
+use crate::error::*;
+use crate::tcp::encode_tcp_message;
+
+use std::collections::HashMap;
+use std::io::Write;
+use std::net::{IpAddr, SocketAddr};
+
+/// Flags of all written TCP segments
+const TCP_PSH_ACK: u8 = 0x18;
+
+/// Records DNS messages as pcap file that can be opened with Wireshark
+///
+/// Messages are written as raw IP packets with made up UDP or TCP headers.
+/// TCP messages are written without handshake, each one as a single segment
+/// continuing the sequence numbers of its direction.
+pub struct PcapWriter<W: Write> {
+    writer: W,
+    /// Next sequence number of every TCP direction
+    sequences: HashMap<(SocketAddr, SocketAddr), u32>,
+}
+
+impl<W: Write> PcapWriter<W> {
+    /// Writes the file header
+    pub fn new(mut writer: W) -> Result<Self> {
+        let mut header = Vec::with_capacity(24);
+        header.extend_from_slice(&PCAP_MAGIC_MICROS.to_le_bytes());
+        header.extend_from_slice(&2u16.to_le_bytes());
+        header.extend_from_slice(&4u16.to_le_bytes());
+        // timezone and accuracy of the timestamps
+        header.extend_from_slice(&[0; 8]);
+        header.extend_from_slice(&65_535u32.to_le_bytes());
+        header.extend_from_slice(&LINKTYPE_RAW.to_le_bytes());
+        writer.write_all(&header)?;
+
+        Ok(Self {
+            writer,
+            sequences: HashMap::new(),
+        })
+    }
+
+    /// Appends the message, source and destination need to be of the same
+    /// address family
+    pub fn write(&mut self, message: &CapturedMessage) -> Result<()> {
+        let packet = match message.transport {
+            Transport::Tcp => {
+                let sequence = self
+                    .sequences
+                    .entry((message.source, message.destination))
+                    .or_insert(1);
+                let packet = packet(message, *sequence)?;
+                *sequence = sequence.wrapping_add(message.payload.len() as u32 + 2);
+                packet
+            }
+            Transport::Udp => packet(message, 0)?,
+        };
+
+        let mut record = Vec::with_capacity(packet.len() + 16);
+        record.extend_from_slice(&(message.timestamp.as_secs() as u32).to_le_bytes());
+        record.extend_from_slice(&message.timestamp.subsec_micros().to_le_bytes());
+        record.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+        record.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+        record.extend_from_slice(&packet);
+        self.writer.write_all(&record)?;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+/// IP packet carrying the message
+pub(super) fn packet(message: &CapturedMessage, sequence: u32) -> Result<Vec<u8>> {
+    let (protocol, segment) = match message.transport {
+        Transport::Udp => (
+            PROTOCOL_UDP,
+            udp_datagram(message.source, message.destination, &message.payload),
+        ),
+        Transport::Tcp => (
+            PROTOCOL_TCP,
+            tcp_segment(
+                message.source,
+                message.destination,
+                sequence,
+                TCP_PSH_ACK,
+                &encode_tcp_message(&message.payload)?,
+            ),
+        ),
+    };
+
+    ip_packet(
+        message.source.ip(),
+        message.destination.ip(),
+        protocol,
+        &segment,
+    )
+}
+
+pub(super) fn ip_packet(
+    source: IpAddr,
+    destination: IpAddr,
+    protocol: u8,
+    payload: &[u8],
+) -> Result<Vec<u8>> {
+    let mut packet = Vec::with_capacity(payload.len() + 40);
+
+    match (source, destination) {
+        (IpAddr::V4(source), IpAddr::V4(destination)) => {
+            let length = payload.len() + 20;
+            if length > usize::from(u16::MAX) {
+                return Err(DnsParseError::MessageTooLong(length));
+            }
+
+            packet.extend_from_slice(&[0x45, 0]);
+            packet.extend_from_slice(&(length as u16).to_be_bytes());
+            // no fragmentation, time to live, protocol and empty checksum
+            packet.extend_from_slice(&[0, 0, 0x40, 0, 64, protocol, 0, 0]);
+            packet.extend_from_slice(&source.octets());
+            packet.extend_from_slice(&destination.octets());
+
+            let checksum = checksum(&[&packet]);
+            packet[10..12].copy_from_slice(&checksum.to_be_bytes());
+        }
+        (IpAddr::V6(source), IpAddr::V6(destination)) => {
+            if payload.len() > usize::from(u16::MAX) {
+                return Err(DnsParseError::MessageTooLong(payload.len()));
+            }
+
+            packet.extend_from_slice(&[0x60, 0, 0, 0]);
+            packet.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+            packet.extend_from_slice(&[protocol, 64]);
+            packet.extend_from_slice(&source.octets());
+            packet.extend_from_slice(&destination.octets());
+        }
+        _ => {
+            return Err(DnsParseError::InvalidCapture(
+                "mixed address families".to_string(),
+            ))
+        }
+    }
+
+    packet.extend_from_slice(payload);
+    Ok(packet)
+}
+
+pub(super) fn udp_datagram(source: SocketAddr, destination: SocketAddr, payload: &[u8]) -> Vec<u8> {
+    let mut datagram = Vec::with_capacity(payload.len() + 8);
+    datagram.extend_from_slice(&source.port().to_be_bytes());
+    datagram.extend_from_slice(&destination.port().to_be_bytes());
+    datagram.extend_from_slice(&((payload.len() + 8) as u16).to_be_bytes());
+    datagram.extend_from_slice(&[0, 0]);
+    datagram.extend_from_slice(payload);
+
+    // a calculated checksum of zero is sent as all ones (RFC 768)
+    let checksum = match transport_checksum(source, destination, PROTOCOL_UDP, &datagram) {
+        0 => 0xFFFF,
+        x => x,
+    };
+    datagram[6..8].copy_from_slice(&checksum.to_be_bytes());
+    datagram
+}
+
+pub(super) fn tcp_segment(
+    source: SocketAddr,
+    destination: SocketAddr,
+    sequence: u32,
+    flags: u8,
+    payload: &[u8],
+) -> Vec<u8> {
+    let mut segment = Vec::with_capacity(payload.len() + 20);
+    segment.extend_from_slice(&source.port().to_be_bytes());
+    segment.extend_from_slice(&destination.port().to_be_bytes());
+    segment.extend_from_slice(&sequence.to_be_bytes());
+    // acknowledgment number, header length, flags and window
+    segment.extend_from_slice(&[0, 0, 0, 0, 0x50, flags, 0xFF, 0xFF]);
+    // checksum and urgent pointer
+    segment.extend_from_slice(&[0, 0, 0, 0]);
+    segment.extend_from_slice(payload);
+
+    let checksum = transport_checksum(source, destination, PROTOCOL_TCP, &segment);
+    segment[16..18].copy_from_slice(&checksum.to_be_bytes());
+    segment
+}
+
+/// Checksum of UDP and TCP including the pseudo header of the IP layer
+fn transport_checksum(
+    source: SocketAddr,
+    destination: SocketAddr,
+    protocol: u8,
+    segment: &[u8],
+) -> u16 {
+    let length = segment.len() as u32;
+    match (source.ip(), destination.ip()) {
+        (IpAddr::V4(source), IpAddr::V4(destination)) => checksum(&[
+            &source.octets(),
+            &destination.octets(),
+            &[0, protocol],
+            &(length as u16).to_be_bytes(),
+            segment,
+        ]),
+        (source, destination) => {
+            let octets = |x: IpAddr| match x {
+                IpAddr::V4(x) => x.to_ipv6_mapped().octets(),
+                IpAddr::V6(x) => x.octets(),
+            };
+            checksum(&[
+                &octets(source),
+                &octets(destination),
+                &length.to_be_bytes(),
+                &[0, 0, 0, protocol],
+                segment,
+            ])
+        }
+    }
+}
+
+/// Internet checksum (RFC 1071) over the concatenation of all parts, every
+/// part except the last one needs to have an even length
+fn checksum(parts: &[&[u8]]) -> u16 {
+    let mut sum = parts
+        .iter()
+        .flat_map(|x| x.chunks(2))
+        .map(|x| u32::from(u16::from_be_bytes([x[0], *x.get(1).unwrap_or(&0)])))
+        .sum::<u32>();
+
+    while sum > 0xFFFF {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_checksum() {
+        // example of RFC 1071 3
+        assert_eq!(
+            checksum(&[&[0x00, 0x01, 0xF2, 0x03, 0xF4, 0xF5, 0xF6, 0xF7]]),
+            !0xDDF2
+        );
+
+        let source = "192.168.0.1:53".parse().unwrap();
+        let destination = "192.168.0.2:40000".parse().unwrap();
+        let datagram = udp_datagram(source, destination, &[1, 2, 3]);
+        assert_eq!(
+            transport_checksum(source, destination, PROTOCOL_UDP, &datagram),
+            0
+        );
+
+        let packet = ip_packet(source.ip(), destination.ip(), PROTOCOL_UDP, &datagram).unwrap();
+        assert_eq!(checksum(&[&packet[..20]]), 0);
+    }
+
+    #[test]
+    pub fn test_mixed_address_families() {
+        let message = CapturedMessage {
+            timestamp: Default::default(),
+            source: "127.0.0.1:53".parse().unwrap(),
+            destination: "[::1]:53".parse().unwrap(),
+            transport: Transport::Udp,
+            payload: vec![],
+        };
+        let mut writer = PcapWriter::new(Vec::new()).unwrap();
+        assert!(writer.write(&message).is_err());
+    }
+
+    #[test]
+    pub fn test_udp_sequences() {
+        let mut writer = PcapWriter::new(Vec::new()).unwrap();
+        for port in 1..=10 {
+            let message = CapturedMessage {
+                timestamp: Default::default(),
+                source: SocketAddr::from(([127, 0, 0, 1], port)),
+                destination: "127.0.0.1:53".parse().unwrap(),
+                transport: Transport::Udp,
+                payload: vec![1, 2, 3],
+            };
+            writer.write(&message).unwrap();
+        }
+        assert!(writer.sequences.is_empty());
+    }
+}
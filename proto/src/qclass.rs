@@ -2,26 +2,35 @@
 #[derive(Copy, Clone, Debug, Hash, Eq, PartialEq)]
 pub enum QClass {
     IN,
+    /// Class without special handling, for example CH (RFC 3597)
+    Unknown(u16),
 }
 
 impl From<u16> for QClass {
     fn from(x: u16) -> Self {
-        match x {
-            1 => QClass::IN,
-            _ => panic!("Unknown qtype"),
-        }
+        QClass::from_u16(x).unwrap_or(QClass::Unknown(x))
     }
 }
 
-pub fn as_qclass(val: u16) -> QClass {
-    match val {
-        1 => QClass::IN,
-        _ => panic!("Unknown qtype"),
+impl From<QClass> for u16 {
+    fn from(x: QClass) -> Self {
+        as_u16(x)
+    }
+}
+
+impl QClass {
+    /// Returns `None` for every class that has no variant of its own
+    pub(crate) fn from_u16(x: u16) -> Option<Self> {
+        match x {
+            1 => Some(QClass::IN),
+            _ => None,
+        }
     }
 }
 
 pub fn as_u16(val: QClass) -> u16 {
     match val {
         QClass::IN => 1,
+        QClass::Unknown(x) => x,
     }
 }
@@ -18,11 +18,37 @@ pub enum QType {
     MX,
     TXT,
     AAAA,
+    /// Redirects all names below its owner (RFC 6672)
+    DNAME,
+    DS,
+    RRSIG,
+    NSEC,
+    DNSKEY,
+    NSEC3,
+    NSEC3PARAM,
+    /// Only valid in questions, requests all records of the name
+    ANY,
+    /// Type without special handling, its RDATA is kept as it is
+    /// (RFC 3597)
+    Unknown(u16),
 }
 
 impl From<u16> for QType {
     fn from(x: u16) -> Self {
-        match x {
+        QType::from_u16(x).unwrap_or(QType::Unknown(x))
+    }
+}
+
+impl From<QType> for u16 {
+    fn from(x: QType) -> Self {
+        as_u16(x)
+    }
+}
+
+impl QType {
+    /// Returns `None` for every type that has no variant of its own
+    pub(crate) fn from_u16(x: u16) -> Option<Self> {
+        let qtype = match x {
             1 => QType::A,
             2 => QType::NS,
             3 => QType::MD,
@@ -40,31 +66,17 @@ impl From<u16> for QType {
             15 => QType::MX,
             16 => QType::TXT,
             28 => QType::AAAA,
-            _ => panic!("Unknown qtype"),
-        }
-    }
-}
-
-pub fn as_qtype(val: u16) -> QType {
-    match val {
-        1 => QType::A,
-        2 => QType::NS,
-        3 => QType::MD,
-        4 => QType::MF,
-        5 => QType::CNAME,
-        6 => QType::SOA,
-        7 => QType::MB,
-        8 => QType::MG,
-        9 => QType::MR,
-        10 => QType::NULL,
-        11 => QType::WKS,
-        12 => QType::PTR,
-        13 => QType::HINFO,
-        14 => QType::MINFO,
-        15 => QType::MX,
-        16 => QType::TXT,
-        28 => QType::AAAA,
-        _ => panic!("Unknown qtype"),
+            39 => QType::DNAME,
+            43 => QType::DS,
+            46 => QType::RRSIG,
+            47 => QType::NSEC,
+            48 => QType::DNSKEY,
+            50 => QType::NSEC3,
+            51 => QType::NSEC3PARAM,
+            255 => QType::ANY,
+            _ => return None,
+        };
+        Some(qtype)
     }
 }
 
@@ -87,5 +99,14 @@ pub fn as_u16(val: QType) -> u16 {
         QType::MX => 15,
         QType::TXT => 16,
         QType::AAAA => 28,
+        QType::DNAME => 39,
+        QType::DS => 43,
+        QType::RRSIG => 46,
+        QType::NSEC => 47,
+        QType::DNSKEY => 48,
+        QType::NSEC3 => 50,
+        QType::NSEC3PARAM => 51,
+        QType::ANY => 255,
+        QType::Unknown(x) => x,
     }
 }
@@ -1,121 +1,6 @@
 use crate::error::*;
 
-use std::io::{Cursor, Read};
-use std::mem;
-
-#[derive(Debug)]
-pub struct Reader<'a> {
-    buffer: Cursor<&'a [u8]>,
-}
-
-impl<'a> Reader<'a> {
-    pub fn new(bytes: &'a [u8]) -> Self {
-        Self {
-            buffer: Cursor::new(bytes),
-        }
-    }
-
-    pub fn read_length(&mut self, length: usize) -> Result<Vec<u8>> {
-        let mut buf = vec![0u8; length];
-        self.buffer.read_exact(&mut buf)?;
-        Ok(buf)
-    }
-
-    pub fn position(&self) -> u64 {
-        self.buffer.position()
-    }
-
-    pub fn set_position(&mut self, position: u64) {
-        self.buffer.set_position(position);
-    }
-
-    pub fn read_u8(&mut self) -> Result<u8> {
-        let mut buffer = [0; 1];
-        self.buffer.read_exact(&mut buffer)?;
-        Ok(buffer[0])
-    }
-
-    pub fn read_u16(&mut self) -> Result<u16> {
-        let mut buffer = [0; 2];
-        self.buffer.read_exact(&mut buffer)?;
-
-        Ok(unsafe { mem::transmute::<[u8; 2], u16>(buffer) })
-    }
-
-    pub fn read_u16_be(&mut self) -> Result<u16> {
-        Ok(self.read_u16()?.to_be())
-    }
-
-    pub fn read_u32(&mut self) -> Result<u32> {
-        let mut buffer = [0; 4];
-        self.buffer.read_exact(&mut buffer)?;
-
-        Ok(unsafe { mem::transmute::<[u8; 4], u32>(buffer) })
-    }
-
-    pub fn read_u32_be(&mut self) -> Result<u32> {
-        Ok(self.read_u32()?.to_be())
-    }
-
-    pub fn read_u8_as_binary(&mut self) -> Result<[u8; 8]> {
-        let mut val = self.read_u8()?;
-        let mut result = [0; 8];
-
-        if val >= 128 {
-            result[0] = 1;
-            val -= 128;
-        }
-
-        if val >= 64 {
-            result[1] = 1;
-            val -= 64;
-        }
-
-        if val >= 32 {
-            result[2] = 1;
-            val -= 32;
-        }
-
-        if val >= 16 {
-            result[3] = 1;
-            val -= 16;
-        }
-
-        if val >= 8 {
-            result[4] = 1;
-            val -= 8;
-        }
-
-        if val >= 4 {
-            result[5] = 1;
-            val -= 4;
-        }
-
-        if val >= 2 {
-            result[6] = 1;
-            val -= 2;
-        }
-
-        if val >= 1 {
-            result[7] = 1;
-        }
-
-        Ok(result)
-    }
-}
-
-#[cfg(tests)]
-mod tests {
-    use super::*;
-
-    #[test]
-    pub fn test_to_binary() {
-        assert_eq!(read_u8_as_binary(255), [1, 1, 1, 1, 1, 1, 1, 1]);
-        assert_eq!(read_u8_as_binary(128), [1, 0, 0, 0, 0, 0, 0, 0]);
-        assert_eq!(read_u8_as_binary(65), [0, 1, 0, 0, 0, 0, 0, 1]);
-        assert_eq!(read_u8_as_binary(0), [0, 0, 0, 0, 0, 0, 0, 0]);
-    }
-}
+use std::io::Cursor;
 
 pub trait ByteReader: std::io::Read {
     /// Reads one byte from the byte array and returns it as u8 value
@@ -157,12 +42,7 @@ pub trait ByteReader: std::io::Read {
     /// containing the underlying `std::io::Error`
     #[inline]
     fn read_length(&mut self, length: usize) -> Result<Vec<u8>> {
-        let mut buf = Vec::with_capacity(length);
-
-        for _ in 0..length {
-            buf.push(0);
-        }
-
+        let mut buf = vec![0u8; length];
         self.read_exact(&mut buf)?;
         Ok(buf)
     }
@@ -174,7 +54,7 @@ pub trait ByteReader: std::io::Read {
 
         for i in (0..8).rev() {
             buf[i] = val % 2;
-            val = val / 2;
+            val /= 2;
 
             if val == 0 {
                 break;
@@ -187,12 +67,66 @@ pub trait ByteReader: std::io::Read {
 
 impl<R: std::io::Read + ?Sized> ByteReader for R {}
 
+/// Maximum length of a domain name in its wire format, RFC 1035 2.3.4
+const MAX_NAME_LENGTH: usize = 255;
+
+/// Reads a domain name at the current position, following compression
+/// pointers as described in RFC 1035 4.1.4
+///
+/// Labels are joined by `.`, the root name is returned as empty string.
+/// Afterwards the cursor points directly behind the name as it is stored
+/// at the original position, not behind the last followed pointer.
+///
+/// Pointers are only allowed to point backwards, which rules out loops.
+pub fn read_name(reader: &mut Cursor<&[u8]>) -> Result<String> {
+    let mut labels = Vec::with_capacity(4);
+    let mut name_length = 0;
+    let mut resume_position = None;
+
+    loop {
+        let label_start = reader.position();
+        let length = reader.read_u8()?;
+
+        match length & 0b1100_0000 {
+            0b1100_0000 => {
+                let offset = u64::from(length & 0b0011_1111) << 8 | u64::from(reader.read_u8()?);
+                if offset >= label_start {
+                    return Err(DnsParseError::InvalidName);
+                }
+
+                if resume_position.is_none() {
+                    resume_position = Some(reader.position());
+                }
+                reader.set_position(offset);
+            }
+            0b0000_0000 => {
+                if length == 0 {
+                    break;
+                }
+
+                name_length += length as usize + 1;
+                if name_length >= MAX_NAME_LENGTH {
+                    return Err(DnsParseError::InvalidName);
+                }
+
+                let label = reader.read_length(length as usize)?;
+                labels.push(String::from_utf8(label).map_err(DnsParseError::StringParseError)?);
+            }
+            _ => return Err(DnsParseError::InvalidName),
+        }
+    }
+
+    if let Some(position) = resume_position {
+        reader.set_position(position);
+    }
+
+    Ok(labels.join("."))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    use std::io::Cursor;
-
     #[test]
     fn read_u8_001() {
         let mut reader = Cursor::new(vec![100u8]);
@@ -270,4 +204,43 @@ mod tests {
         let mut reader = Cursor::new(vec![0u8]);
         assert_eq!(reader.read_binary().unwrap(), [0, 0, 0, 0, 0, 0, 0, 0]);
     }
+
+    #[test]
+    fn read_name_001() {
+        let bytes = [
+            3, 119, 119, 119, 6, 103, 111, 111, 103, 108, 101, 2, 100, 101, 0,
+        ];
+        let mut reader = Cursor::new(&bytes[..]);
+        assert_eq!(read_name(&mut reader).unwrap(), "www.google.de");
+        assert_eq!(reader.position(), 15);
+    }
+
+    #[test]
+    fn read_name_002() {
+        let bytes = [2, 100, 101, 0, 6, 103, 111, 111, 103, 108, 101, 192, 0, 1];
+        let mut reader = Cursor::new(&bytes[..]);
+        reader.set_position(4);
+        assert_eq!(read_name(&mut reader).unwrap(), "google.de");
+        assert_eq!(reader.position(), 13);
+    }
+
+    #[test]
+    fn read_name_003() {
+        let bytes = [0];
+        let mut reader = Cursor::new(&bytes[..]);
+        assert_eq!(read_name(&mut reader).unwrap(), "");
+    }
+
+    #[test]
+    fn read_name_004() {
+        // pointer to itself
+        let bytes = [192, 0];
+        let mut reader = Cursor::new(&bytes[..]);
+        assert!(read_name(&mut reader).is_err());
+
+        // reserved label type
+        let bytes = [64, 0];
+        let mut reader = Cursor::new(&bytes[..]);
+        assert!(read_name(&mut reader).is_err());
+    }
 }
@@ -0,0 +1,186 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+const IPV4_SUFFIX: &str = "in-addr.arpa";
+const IPV6_SUFFIX: &str = "ip6.arpa";
+
+/// Creates the name used for reverse lookups of the given address
+///
+/// IPv4 addresses are written as reversed octets below `in-addr.arpa`,
+/// IPv6 addresses as reversed nibbles below `ip6.arpa` (RFC 3596 2.5).
+pub fn reverse_name(addr: IpAddr) -> String {
+    match addr {
+        IpAddr::V4(x) => {
+            let [a, b, c, d] = x.octets();
+            format!("{}.{}.{}.{}.{}", d, c, b, a, IPV4_SUFFIX)
+        }
+        IpAddr::V6(x) => {
+            let mut name = String::with_capacity(72);
+            for octet in x.octets().iter().rev() {
+                name.push_str(&format!("{:x}.{:x}.", octet & 0x0F, octet >> 4));
+            }
+            name.push_str(IPV6_SUFFIX);
+            name
+        }
+    }
+}
+
+/// Creates the name of an IPv4 address inside a classless delegation as
+/// described in RFC 2317, for example `1.0/25.2.0.192.in-addr.arpa`
+///
+/// Only prefixes longer than 24 bits need a classless delegation, for all
+/// other prefixes the normal reverse name is returned.
+pub fn classless_reverse_name(addr: Ipv4Addr, prefix: u8) -> String {
+    if prefix <= 24 || prefix >= 32 {
+        return reverse_name(IpAddr::V4(addr));
+    }
+
+    let [a, b, c, d] = addr.octets();
+    let network = d & (0xFF << (32 - prefix));
+    format!(
+        "{}.{}/{}.{}.{}.{}.{}",
+        d, network, prefix, c, b, a, IPV4_SUFFIX
+    )
+}
+
+/// Parses a reverse lookup name back into the address it represents
+///
+/// Names of classless delegations (`1.0/25.2.0.192.in-addr.arpa` or
+/// `1.0-127.2.0.192.in-addr.arpa`) are accepted as well. Returns `None` if
+/// the name does not describe a complete address.
+pub fn parse_reverse_name(name: &str) -> Option<IpAddr> {
+    let name = name.trim_end_matches('.').to_lowercase();
+
+    if let Some(labels) = strip_suffix(&name, IPV4_SUFFIX) {
+        parse_ipv4(&labels).map(IpAddr::V4)
+    } else if let Some(labels) = strip_suffix(&name, IPV6_SUFFIX) {
+        parse_ipv6(&labels).map(IpAddr::V6)
+    } else {
+        None
+    }
+}
+
+/// Splits the labels in front of the suffix, returned in reversed order
+fn strip_suffix<'a>(name: &'a str, suffix: &str) -> Option<Vec<&'a str>> {
+    let labels = name.strip_suffix(suffix)?.strip_suffix('.')?;
+    Some(labels.split('.').rev().collect())
+}
+
+fn parse_ipv4(labels: &[&str]) -> Option<Ipv4Addr> {
+    let octet = |x: &str| {
+        if x.is_empty() || x.len() > 3 || !x.bytes().all(|x| x.is_ascii_digit()) {
+            None
+        } else {
+            x.parse::<u8>().ok()
+        }
+    };
+
+    match labels {
+        [a, b, c, d] => Some(Ipv4Addr::new(octet(a)?, octet(b)?, octet(c)?, octet(d)?)),
+        [a, b, c, range, d] => {
+            let host = octet(d)?;
+            let (first, last) = parse_range(range)?;
+            if host < first || host > last {
+                return None;
+            }
+
+            Some(Ipv4Addr::new(octet(a)?, octet(b)?, octet(c)?, host))
+        }
+        _ => None,
+    }
+}
+
+/// Parses the range label of a classless delegation, either written as
+/// `<network>/<prefix>` or as `<first>-<last>`
+fn parse_range(label: &str) -> Option<(u8, u8)> {
+    if let Some((network, prefix)) = label.split_once('/') {
+        let network = network.parse::<u8>().ok()?;
+        let prefix = prefix.parse::<u8>().ok()?;
+        if prefix <= 24 || prefix > 32 {
+            return None;
+        }
+
+        let size = 1u16 << (32 - prefix);
+        Some((network, (u16::from(network) + size - 1).min(255) as u8))
+    } else if let Some((first, last)) = label.split_once('-') {
+        Some((first.parse().ok()?, last.parse().ok()?))
+    } else {
+        None
+    }
+}
+
+fn parse_ipv6(labels: &[&str]) -> Option<Ipv6Addr> {
+    if labels.len() != 32 {
+        return None;
+    }
+
+    let mut octets = [0u8; 16];
+    for (i, label) in labels.iter().enumerate() {
+        if label.len() != 1 {
+            return None;
+        }
+
+        let nibble = u8::from_str_radix(label, 16).ok()?;
+        if i % 2 == 0 {
+            octets[i / 2] = nibble << 4;
+        } else {
+            octets[i / 2] |= nibble;
+        }
+    }
+
+    Some(Ipv6Addr::from(octets))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_reverse_name_ipv4() {
+        let addr = "127.0.0.1".parse().unwrap();
+        assert_eq!(reverse_name(addr), "1.0.0.127.in-addr.arpa");
+        assert_eq!(parse_reverse_name("1.0.0.127.in-addr.arpa"), Some(addr));
+        assert_eq!(parse_reverse_name("1.0.0.127.IN-ADDR.ARPA."), Some(addr));
+    }
+
+    #[test]
+    pub fn test_reverse_name_ipv6() {
+        let addr = "2001:db8::567:89ab".parse().unwrap();
+        let name = "b.a.9.8.7.6.5.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.ip6.arpa";
+        assert_eq!(reverse_name(addr), name);
+        assert_eq!(parse_reverse_name(name), Some(addr));
+        assert_eq!(parse_reverse_name(&name.to_uppercase()), Some(addr));
+    }
+
+    #[test]
+    pub fn test_classless_reverse_name() {
+        let addr = "192.0.2.130".parse().unwrap();
+        assert_eq!(
+            classless_reverse_name(addr, 25),
+            "130.128/25.2.0.192.in-addr.arpa"
+        );
+        assert_eq!(classless_reverse_name(addr, 24), "130.2.0.192.in-addr.arpa");
+
+        let addr = IpAddr::V4(addr);
+        assert_eq!(
+            parse_reverse_name("130.128/25.2.0.192.in-addr.arpa"),
+            Some(addr)
+        );
+        assert_eq!(
+            parse_reverse_name("130.128-191.2.0.192.in-addr.arpa"),
+            Some(addr)
+        );
+        assert_eq!(parse_reverse_name("130.0/25.2.0.192.in-addr.arpa"), None);
+        assert_eq!(parse_reverse_name("130.0-63.2.0.192.in-addr.arpa"), None);
+    }
+
+    #[test]
+    pub fn test_parse_reverse_name_invalid() {
+        assert_eq!(parse_reverse_name("0.127.in-addr.arpa"), None);
+        assert_eq!(parse_reverse_name("256.0.0.127.in-addr.arpa"), None);
+        assert_eq!(parse_reverse_name("+1.0.0.127.in-addr.arpa"), None);
+        assert_eq!(parse_reverse_name("1.0.0.127.xin-addr.arpa"), None);
+        assert_eq!(parse_reverse_name("in-addr.arpa"), None);
+        assert_eq!(parse_reverse_name("1.0.ip6.arpa"), None);
+        assert_eq!(parse_reverse_name("www.google.de"), None);
+    }
+}
@@ -0,0 +1,256 @@
+use crate::error::*;
+
+use std::io::{ErrorKind, Read, Write};
+
+/// Largest message that can be described by the two byte length prefix
+pub const MAX_TCP_MESSAGE_LENGTH: usize = 65_535;
+
+/// Prefixes the message with its length, as required for DNS over TCP and
+/// TLS (RFC 1035 4.2.2, RFC 7766 8)
+pub fn encode_tcp_message(message: &[u8]) -> Result<Vec<u8>> {
+    if message.len() > MAX_TCP_MESSAGE_LENGTH {
+        return Err(DnsParseError::MessageTooLong(message.len()));
+    }
+
+    let mut frame = Vec::with_capacity(message.len() + 2);
+    frame.extend_from_slice(&(message.len() as u16).to_be_bytes());
+    frame.extend_from_slice(message);
+    Ok(frame)
+}
+
+/// Reads the next length prefixed message from a blocking stream
+///
+/// Returns `None` if the stream was closed before the next message started.
+/// Messages longer than `max_length` are rejected without reading them.
+pub fn read_tcp_message<R: Read>(reader: &mut R, max_length: usize) -> Result<Option<Vec<u8>>> {
+    let mut prefix = [0u8; 2];
+    match reader.read(&mut prefix[..1]) {
+        Ok(0) => return Ok(None),
+        Ok(_) => reader.read_exact(&mut prefix[1..])?,
+        Err(e) if e.kind() == ErrorKind::Interrupted => {
+            return read_tcp_message(reader, max_length)
+        }
+        Err(e) => return Err(e.into()),
+    }
+
+    let length = check_length(prefix, max_length)?;
+    let mut message = vec![0u8; length];
+    reader.read_exact(&mut message)?;
+    Ok(Some(message))
+}
+
+/// Writes the message with its length prefix to a blocking stream
+pub fn write_tcp_message<W: Write>(writer: &mut W, message: &[u8]) -> Result<()> {
+    writer.write_all(&encode_tcp_message(message)?)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Async version of `read_tcp_message`
+#[cfg(feature = "async")]
+pub async fn read_tcp_message_async<R>(reader: &mut R, max_length: usize) -> Result<Option<Vec<u8>>>
+where
+    R: futures_lite::AsyncRead + Unpin,
+{
+    use futures_lite::AsyncReadExt;
+
+    let mut prefix = [0u8; 2];
+    loop {
+        match reader.read(&mut prefix[..1]).await {
+            Ok(0) => return Ok(None),
+            Ok(_) => break,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    reader.read_exact(&mut prefix[1..]).await?;
+
+    let length = check_length(prefix, max_length)?;
+    let mut message = vec![0u8; length];
+    reader.read_exact(&mut message).await?;
+    Ok(Some(message))
+}
+
+/// Async version of `write_tcp_message`
+#[cfg(feature = "async")]
+pub async fn write_tcp_message_async<W>(writer: &mut W, message: &[u8]) -> Result<()>
+where
+    W: futures_lite::AsyncWrite + Unpin,
+{
+    use futures_lite::AsyncWriteExt;
+
+    writer.write_all(&encode_tcp_message(message)?).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+fn check_length(prefix: [u8; 2], max_length: usize) -> Result<usize> {
+    let length = u16::from_be_bytes(prefix) as usize;
+    if length > max_length {
+        Err(DnsParseError::MessageTooLong(length))
+    } else {
+        Ok(length)
+    }
+}
+
+/// Splits a stream of bytes into length prefixed messages
+///
+/// Useful when bytes arrive in arbitrary chunks, for example from a non
+/// blocking socket or a captured TCP flow. Chunks may end in the middle of a
+/// message and may contain multiple pipelined messages.
+#[derive(Clone, Debug)]
+pub struct TcpDecoder {
+    buffer: Vec<u8>,
+    max_length: usize,
+}
+
+impl Default for TcpDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TcpDecoder {
+    pub fn new() -> Self {
+        Self::with_max_length(MAX_TCP_MESSAGE_LENGTH)
+    }
+
+    /// Decoder that rejects all messages longer than `max_length`
+    pub fn with_max_length(max_length: usize) -> Self {
+        Self {
+            buffer: Vec::new(),
+            max_length,
+        }
+    }
+
+    /// Appends received bytes
+    pub fn extend(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// Returns the next complete message, `None` if more bytes are required
+    ///
+    /// After an error the stream is out of sync and should be closed.
+    pub fn decode(&mut self) -> Result<Option<Vec<u8>>> {
+        if self.buffer.len() < 2 {
+            return Ok(None);
+        }
+
+        let length = check_length([self.buffer[0], self.buffer[1]], self.max_length)?;
+        if self.buffer.len() < length + 2 {
+            return Ok(None);
+        }
+
+        let message = self.buffer[2..length + 2].to_vec();
+        self.buffer.drain(..length + 2);
+        Ok(Some(message))
+    }
+
+    /// Number of buffered bytes that do not form a complete message yet
+    pub fn pending(&self) -> usize {
+        self.buffer.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Cursor;
+
+    #[test]
+    pub fn test_encode() {
+        assert_eq!(encode_tcp_message(&[1, 2, 3]).unwrap(), vec![0, 3, 1, 2, 3]);
+        assert_eq!(encode_tcp_message(&[]).unwrap(), vec![0, 0]);
+        assert!(encode_tcp_message(&vec![0; MAX_TCP_MESSAGE_LENGTH + 1]).is_err());
+    }
+
+    #[test]
+    pub fn test_read_write() {
+        let mut stream = Vec::new();
+        write_tcp_message(&mut stream, &[1, 2, 3]).unwrap();
+        write_tcp_message(&mut stream, &[4]).unwrap();
+
+        let mut reader = Cursor::new(stream);
+        assert_eq!(
+            read_tcp_message(&mut reader, 512).unwrap(),
+            Some(vec![1, 2, 3])
+        );
+        assert_eq!(read_tcp_message(&mut reader, 512).unwrap(), Some(vec![4]));
+        assert_eq!(read_tcp_message(&mut reader, 512).unwrap(), None);
+    }
+
+    #[test]
+    pub fn test_read_invalid() {
+        // connection closed in the middle of a message
+        let mut reader = Cursor::new(vec![0, 3, 1, 2]);
+        assert!(read_tcp_message(&mut reader, 512).is_err());
+
+        let mut reader = Cursor::new(vec![0]);
+        assert!(read_tcp_message(&mut reader, 512).is_err());
+
+        let mut reader = Cursor::new(vec![2, 1, 1, 2]);
+        match read_tcp_message(&mut reader, 512) {
+            Err(DnsParseError::MessageTooLong(513)) => (),
+            x => panic!("Unexpected result {:?}", x),
+        }
+    }
+
+    #[test]
+    pub fn test_decoder() {
+        let mut decoder = TcpDecoder::new();
+        assert_eq!(decoder.decode().unwrap(), None);
+
+        decoder.extend(&[0]);
+        assert_eq!(decoder.decode().unwrap(), None);
+        decoder.extend(&[3, 1, 2]);
+        assert_eq!(decoder.decode().unwrap(), None);
+        assert_eq!(decoder.pending(), 4);
+
+        // rest of the first message and two pipelined ones
+        decoder.extend(&[3, 0, 1, 4, 0, 0, 0]);
+        assert_eq!(decoder.decode().unwrap(), Some(vec![1, 2, 3]));
+        assert_eq!(decoder.decode().unwrap(), Some(vec![4]));
+        assert_eq!(decoder.decode().unwrap(), Some(vec![]));
+        assert_eq!(decoder.decode().unwrap(), None);
+        assert_eq!(decoder.pending(), 1);
+    }
+
+    #[test]
+    pub fn test_decoder_max_length() {
+        let mut decoder = TcpDecoder::with_max_length(2);
+        decoder.extend(&[0, 2, 1, 2, 0, 3]);
+        assert_eq!(decoder.decode().unwrap(), Some(vec![1, 2]));
+        assert!(decoder.decode().is_err());
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    pub fn test_read_write_async() {
+        futures_lite::future::block_on(async {
+            let mut stream = Vec::new();
+            write_tcp_message_async(&mut stream, &[1, 2, 3])
+                .await
+                .unwrap();
+            write_tcp_message_async(&mut stream, &[4]).await.unwrap();
+
+            // deliver the bytes in chunks of one byte to force partial reads
+            let mut reader = futures_lite::io::BufReader::with_capacity(1, &stream[..]);
+            assert_eq!(
+                read_tcp_message_async(&mut reader, 512).await.unwrap(),
+                Some(vec![1, 2, 3])
+            );
+            assert_eq!(
+                read_tcp_message_async(&mut reader, 512).await.unwrap(),
+                Some(vec![4])
+            );
+            assert_eq!(
+                read_tcp_message_async(&mut reader, 512).await.unwrap(),
+                None
+            );
+
+            let mut reader = &[0u8, 3, 1][..];
+            assert!(read_tcp_message_async(&mut reader, 512).await.is_err());
+        });
+    }
+}
@@ -1,16 +1,26 @@
-#[derive(Clone, Debug)]
+use crate::idn::domain_to_ascii;
+
+use std::collections::HashMap;
+
+/// Highest offset a compression pointer is able to address
+const MAX_POINTER_OFFSET: usize = 0x3FFF;
+
+#[derive(Clone, Debug, Default)]
 pub struct Writer {
     bytes: Vec<u8>,
+    /// Offsets of all names written so far, used for compression
+    names: HashMap<String, u16>,
 }
 
 impl Writer {
     pub fn new() -> Self {
-        Self { bytes: Vec::new() }
+        Self::default()
     }
 
     pub fn with_capacity(capacity: usize) -> Self {
         Self {
             bytes: Vec::with_capacity(capacity),
+            ..Self::new()
         }
     }
 
@@ -58,8 +68,7 @@ impl Writer {
     }
 
     pub fn write_u16(mut self, value: u16) -> Self {
-        let bytes: [u8; 2] = unsafe { ::std::mem::transmute(value) };
-        self.bytes.append(&mut bytes.to_vec());
+        self.bytes.extend_from_slice(&value.to_ne_bytes());
         self
     }
 
@@ -68,8 +77,7 @@ impl Writer {
     }
 
     pub fn write_u32(mut self, value: u32) -> Self {
-        let bytes: [u8; 4] = unsafe { ::std::mem::transmute(value) };
-        self.bytes.append(&mut bytes.to_vec());
+        self.bytes.extend_from_slice(&value.to_ne_bytes());
         self
     }
 
@@ -82,6 +90,63 @@ impl Writer {
         self
     }
 
+    /// Writes a domain name, replacing its longest suffix that was already
+    /// written with a compression pointer as described in RFC 1035 4.1.4
+    ///
+    /// Names containing non ASCII characters are converted to their ASCII
+    /// form first. If that fails, the name is written as it is, which keeps
+    /// names of parsed messages intact. Any other name has to be accepted by
+    /// `domain_to_ascii`, otherwise its labels may be too long or empty, so
+    /// callers check names where they enter, like `DNS::from_json` does.
+    pub fn write_name(self, name: &str) -> Self {
+        self.write_labels(name, true)
+    }
+
+    /// Writes a domain name without compressing it, as required for names
+    /// in RDATA that is copied as it is
+    pub fn write_name_uncompressed(self, name: &str) -> Self {
+        self.write_labels(name, false)
+    }
+
+    fn write_labels(mut self, name: &str, compress: bool) -> Self {
+        let name = if name.is_ascii() {
+            name.to_string()
+        } else {
+            domain_to_ascii(name).unwrap_or_else(|_| name.to_string())
+        };
+        let mut remaining = name.trim_end_matches('.');
+
+        while !remaining.is_empty() {
+            if compress {
+                if let Some(&offset) = self.names.get(remaining) {
+                    return self.write_u16_be(0xC000 | offset);
+                }
+
+                if self.bytes.len() <= MAX_POINTER_OFFSET {
+                    self.names
+                        .insert(remaining.to_string(), self.bytes.len() as u16);
+                }
+            }
+
+            let (label, rest) = match remaining.find('.') {
+                Some(index) => (&remaining[..index], &remaining[index + 1..]),
+                None => (remaining, ""),
+            };
+            self.bytes.push(label.len() as u8);
+            self.bytes.extend_from_slice(label.as_bytes());
+            remaining = rest;
+        }
+
+        self.write_u8(0)
+    }
+
+    /// Overwrites two already written bytes, used for length fields that are
+    /// only known after the content was written
+    pub fn replace_u16_be(mut self, position: usize, value: u16) -> Self {
+        self.bytes[position..position + 2].copy_from_slice(&value.to_be_bytes());
+        self
+    }
+
     pub fn position(&self) -> usize {
         self.bytes.len()
     }
@@ -124,4 +189,49 @@ mod tests {
         let builder = Writer::new().write_u32_be(167_437_900).build();
         assert_eq!(builder, [9, 250, 230, 76]);
     }
+
+    #[test]
+    pub fn test_name() {
+        let builder = Writer::new().write_name("www.google.de").build();
+        assert_eq!(
+            builder,
+            [3, 119, 119, 119, 6, 103, 111, 111, 103, 108, 101, 2, 100, 101, 0]
+        );
+
+        let builder = Writer::new().write_name("").write_name(".").build();
+        assert_eq!(builder, [0, 0]);
+
+        let builder = Writer::new()
+            .write_name("google.de")
+            .write_name("mail.google.de.")
+            .write_name("google.de")
+            .build();
+        assert_eq!(
+            builder,
+            [
+                6, 103, 111, 111, 103, 108, 101, 2, 100, 101, 0, 4, 109, 97, 105, 108, 192, 0, 192,
+                0
+            ]
+        );
+    }
+
+    #[test]
+    pub fn test_name_uncompressed() {
+        let builder = Writer::new()
+            .write_name("de")
+            .write_name_uncompressed("de")
+            .write_u16_be(0)
+            .replace_u16_be(8, 1337)
+            .build();
+        assert_eq!(builder, [2, 100, 101, 0, 2, 100, 101, 0, 5, 57]);
+    }
+
+    #[test]
+    pub fn test_name_unicode() {
+        let builder = Writer::new().write_name("bücher.de").build();
+        let mut expected = vec![13];
+        expected.extend_from_slice(b"xn--bcher-kva");
+        expected.extend_from_slice(&[2, 100, 101, 0]);
+        assert_eq!(builder, expected);
+    }
 }